@@ -0,0 +1,110 @@
+-- Wireshark dissector for captures made by bevy_stardust_extras' pcapng exporter.
+--
+-- Copy this file into your Wireshark plugin folder, then open a capture.
+-- Captures use the LINKTYPE_USER0 link type, and each packet is a single message:
+--
+--   kind      u8    0 = message, 1 = channel table
+--   direction u8    0 = incoming, 1 = outgoing
+--   peer      u64   the peer's entity id, as Entity::to_bits
+--   channel   u32   the channel id
+--   payload   ..    the message itself
+--
+-- Channel table packets contain a u32 count, followed by that many entries
+-- of a u32 channel id, a u16 name length, and the name as UTF-8.
+-- All integers are big endian.
+
+local stardust = Proto("stardust", "Stardust Message")
+
+local kinds = { [0] = "Message", [1] = "Channel table" }
+local directions = { [0] = "Incoming", [1] = "Outgoing" }
+
+local f_kind = ProtoField.uint8("stardust.kind", "Kind", base.DEC, kinds)
+local f_direction = ProtoField.uint8("stardust.direction", "Direction", base.DEC, directions)
+local f_peer = ProtoField.uint64("stardust.peer", "Peer", base.HEX)
+local f_peer_index = ProtoField.uint32("stardust.peer.index", "Index", base.DEC)
+local f_peer_generation = ProtoField.uint32("stardust.peer.generation", "Generation", base.DEC)
+local f_channel = ProtoField.uint32("stardust.channel", "Channel", base.DEC)
+local f_channel_name = ProtoField.string("stardust.channel.name", "Channel name")
+local f_length = ProtoField.uint32("stardust.length", "Length", base.DEC)
+local f_payload = ProtoField.bytes("stardust.payload", "Payload")
+
+stardust.fields = {
+    f_kind, f_direction,
+    f_peer, f_peer_index, f_peer_generation,
+    f_channel, f_channel_name,
+    f_length, f_payload,
+}
+
+-- Filled in when a channel table packet is dissected.
+-- The exporter always writes the table first, so names
+-- are known by the time any message is dissected.
+local channel_names = {}
+
+local function dissect_channel_table(buffer, pinfo, subtree)
+    local count = buffer(1, 4):uint()
+    local offset = 5
+
+    for _ = 1, count do
+        local id = buffer(offset, 4):uint()
+        local len = buffer(offset + 4, 2):uint()
+        local name = buffer(offset + 6, len):string()
+        channel_names[id] = name
+
+        local entry = subtree:add(f_channel, buffer(offset, 4))
+        entry:add(f_channel_name, buffer(offset + 6, len))
+        entry:append_text(" (" .. name .. ")")
+
+        offset = offset + 6 + len
+    end
+
+    pinfo.cols.info = "Channel table, " .. count .. " channels"
+end
+
+local function dissect_message(buffer, pinfo, subtree)
+    local direction = buffer(1, 1):uint()
+    local generation = buffer(2, 4):uint()
+    local index = buffer(6, 4):uint()
+    local channel = buffer(10, 4):uint()
+    local name = channel_names[channel] or "unknown"
+    local length = buffer:len() - 14
+
+    subtree:add(f_direction, buffer(1, 1))
+
+    local peer = subtree:add(f_peer, buffer(2, 8))
+    peer:add(f_peer_generation, buffer(2, 4))
+    peer:add(f_peer_index, buffer(6, 4))
+    peer:append_text(" (" .. index .. "v" .. generation .. ")")
+
+    local channel_item = subtree:add(f_channel, buffer(10, 4))
+    channel_item:append_text(" (" .. name .. ")")
+    subtree:add(f_channel_name, name):set_generated()
+
+    subtree:add(f_length, length):set_generated()
+    if length > 0 then
+        subtree:add(f_payload, buffer(14, length))
+    end
+
+    local arrow = direction == 0 and "<-" or "->"
+    pinfo.cols.src = direction == 0 and (index .. "v" .. generation) or "local"
+    pinfo.cols.dst = direction == 0 and "local" or (index .. "v" .. generation)
+    pinfo.cols.info = arrow .. " " .. name .. " (" .. channel .. "), " .. length .. " bytes"
+end
+
+function stardust.dissector(buffer, pinfo, tree)
+    if buffer:len() < 1 then return 0 end
+    pinfo.cols.protocol = "Stardust"
+
+    local subtree = tree:add(stardust, buffer(), "Stardust")
+    local kind = buffer(0, 1):uint()
+    subtree:add(f_kind, buffer(0, 1))
+
+    if kind == 1 then
+        dissect_channel_table(buffer, pinfo, subtree)
+    elseif kind == 0 and buffer:len() >= 14 then
+        dissect_message(buffer, pinfo, subtree)
+    end
+
+    return buffer:len()
+end
+
+DissectorTable.get("wtap_encap"):add(wtap.USER0, stardust)
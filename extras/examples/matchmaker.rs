@@ -0,0 +1,50 @@
+//! A minimal matchmaker that issues connect tokens, for local testing.
+//!
+//! Listens on `127.0.0.1:7000`. Each connection should send a peer uid as a decimal
+//! number followed by a newline, and receives a hex-encoded connect token in return.
+//! The key is hardcoded, so this must never be used in production.
+//!
+//! ```sh
+//! cargo run --example matchmaker --features tokens
+//! echo 1234 | nc 127.0.0.1 7000
+//! ```
+
+use std::{io::{BufRead, BufReader, Write}, net::TcpListener, time::Duration};
+use bevy_stardust::prelude::*;
+use bevy_stardust_extras::tokens::{TokenIssuer, TokenKey};
+
+/// The key shared with the game server. Use a randomly generated key in real deployments.
+const KEY: TokenKey = TokenKey::from_bytes(*b"stardust-example-key-do-not-use!");
+
+fn main() -> std::io::Result<()> {
+    let mut issuer = TokenIssuer::new(KEY);
+    let listener = TcpListener::bind("127.0.0.1:7000")?;
+    println!("Issuing tokens on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+
+        // Read the uid the client wants a token for
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let Ok(uid) = line.trim().parse::<u64>() else {
+            writeln!(stream, "error: expected a peer uid")?;
+            continue;
+        };
+
+        // Issue and return the token
+        let token = match issuer.issue(PeerUid(uid), Duration::from_secs(30), Bytes::new()) {
+            Ok(token) => token,
+            Err(err) => {
+                writeln!(stream, "error: {err}")?;
+                continue;
+            },
+        };
+
+        let hex: String = token.to_bytes().iter().map(|b| format!("{b:02x}")).collect();
+        writeln!(stream, "{hex}")?;
+        println!("Issued token for {:?}", token.uid);
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,67 @@
+use bytes::{Buf, BytesMut};
+use bevy_stardust::prelude::*;
+use crate::numbers::VarInt;
+
+/// The largest message that can be read from a stream,
+/// so the remote can't make us buffer an unlimited amount of data.
+pub(crate) const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Why a message couldn't be read from a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FramingError {
+    /// The header couldn't be parsed.
+    Malformed,
+
+    /// The message was larger than [`MAX_MESSAGE_SIZE`].
+    TooLarge,
+}
+
+/// Reads the next message from a stream of messages, each encoded as a [`VarInt`]
+/// channel id and length, followed by the payload.
+///
+/// Returns `Ok(None)` without consuming anything if the whole message hasn't arrived yet.
+/// The length is checked as soon as the header arrives, before waiting for the payload.
+pub(crate) fn read_message(buf: &mut BytesMut) -> Result<Option<ChannelMessage>, FramingError> {
+    // Parse the header without consuming anything,
+    // in case the rest of the message hasn't arrived
+    let mut cursor = &buf[..];
+    let Ok(channel) = VarInt::read(&mut cursor) else { return Ok(None) };
+    let Ok(len) = VarInt::read(&mut cursor) else { return Ok(None) };
+    let remaining = cursor.len();
+
+    let (Ok(channel), Ok(len)) = (u32::try_from(channel), usize::try_from(u64::from(len))) else {
+        return Err(FramingError::Malformed);
+    };
+
+    if len > MAX_MESSAGE_SIZE { return Err(FramingError::TooLarge) }
+    if remaining < len { return Ok(None) }
+
+    buf.advance(buf.len() - remaining);
+    return Ok(Some(ChannelMessage {
+        channel: ChannelId::from(channel),
+        message: Message::from_bytes(buf.split_to(len).freeze()),
+    }));
+}
+
+#[test]
+fn read_message_test() {
+    let mut buf = BytesMut::new();
+    VarInt::from(3u32).write(&mut buf).unwrap();
+    VarInt::from(5u32).write(&mut buf).unwrap();
+    buf.extend_from_slice(b"Hel");
+
+    // Nothing is consumed until the whole message arrives
+    assert!(read_message(&mut buf).unwrap().is_none());
+    assert_eq!(buf.len(), 5);
+
+    buf.extend_from_slice(b"lo");
+    let message = read_message(&mut buf).unwrap().unwrap();
+    assert_eq!(message.channel, ChannelId::from(3));
+    assert_eq!(message.message.as_slice(), b"Hello");
+    assert!(buf.is_empty());
+
+    // Oversized messages are rejected before their payload arrives
+    VarInt::from(0u32).write(&mut buf).unwrap();
+    VarInt::try_from(MAX_MESSAGE_SIZE + 1).unwrap().write(&mut buf).unwrap();
+    assert!(matches!(read_message(&mut buf), Err(FramingError::TooLarge)));
+}
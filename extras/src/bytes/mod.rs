@@ -1,5 +1,10 @@
 //! Types for working with bytes over the network.
 
+mod packing;
 mod stream;
 
+#[cfg(any(feature="noise", feature="tls"))]
+pub(crate) mod framing;
+
+pub use packing::{PacketPacker, PacketUnpacker, MalformedPacket};
 pub use stream::ChunkStream;
\ No newline at end of file
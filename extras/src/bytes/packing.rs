@@ -0,0 +1,189 @@
+use std::fmt::Display;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bevy_stardust::channels::MessageTooLarge;
+use bevy_stardust::prelude::*;
+use crate::numbers::VarInt;
+
+/// Coalesces many small messages into packets no larger than a maximum size.
+///
+/// Each message is encoded as a [`VarInt`] channel id and length, followed by the payload.
+/// Messages are packed in the order they're pushed, and are never split across packets,
+/// so a message that's too large to fit into a packet on its own is rejected.
+/// Packets can be read with [`PacketUnpacker`].
+///
+/// ```
+/// # use bevy_stardust::prelude::*;
+/// # use bevy_stardust_extras::bytes::PacketPacker;
+/// fn pack(messages: &PeerMessages<Outgoing>) -> Vec<Bytes> {
+///     let mut packer = PacketPacker::new(1200);
+///     for (channel, messages) in messages.iter() {
+///         for message in messages {
+///             # let _ =
+///             packer.push(ChannelMessage { channel, message });
+///         }
+///     }
+///
+///     packer.finish().collect()
+/// }
+/// ```
+pub struct PacketPacker {
+    mtu: usize,
+    current: BytesMut,
+    packets: Vec<Bytes>,
+}
+
+impl PacketPacker {
+    /// Creates a new `PacketPacker` that creates packets of up to `mtu` bytes.
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            mtu,
+            current: BytesMut::new(),
+            packets: Vec::new(),
+        }
+    }
+
+    /// Returns the maximum size of packets.
+    #[inline]
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Adds a message to the current packet, starting a new packet if it doesn't fit.
+    /// Fails if the message can't fit into a packet on its own.
+    pub fn push(&mut self, message: ChannelMessage) -> Result<(), MessageTooLarge> {
+        let channel = VarInt::from(message.channel);
+        let header = channel.len() as usize + VarInt::len_u64(message.message.len() as u64).unwrap_or(8) as usize;
+        let size = header + message.message.len();
+
+        if size > self.mtu {
+            return Err(MessageTooLarge {
+                channel: message.channel,
+                size: message.message.len(),
+                limit: self.mtu.saturating_sub(header),
+            });
+        }
+
+        if self.current.len() + size > self.mtu {
+            self.flush();
+        }
+
+        if self.current.is_empty() {
+            self.current.reserve(self.mtu);
+        }
+
+        // The length always fits, since it's no larger than the mtu
+        channel.write(&mut self.current).unwrap();
+        VarInt::try_from(message.message.len()).unwrap().write(&mut self.current).unwrap();
+        self.current.put_slice(message.message.as_slice());
+        return Ok(());
+    }
+
+    /// Returns the number of packets that have been filled, including the current one.
+    pub fn packets(&self) -> usize {
+        self.packets.len() + usize::from(!self.current.is_empty())
+    }
+
+    /// Finishes the current packet, and returns all packed packets in order.
+    pub fn finish(&mut self) -> impl Iterator<Item = Bytes> + '_ {
+        self.flush();
+        self.packets.drain(..)
+    }
+
+    fn flush(&mut self) {
+        if self.current.is_empty() { return }
+        self.packets.push(self.current.split().freeze());
+    }
+}
+
+/// Reads the messages in a packet created by a [`PacketPacker`].
+///
+/// Messages are returned as slices of the packet, without copying.
+/// If the packet is malformed, the unpacker returns an error, and then stops.
+pub struct PacketUnpacker {
+    packet: Bytes,
+}
+
+impl PacketUnpacker {
+    /// Creates a new `PacketUnpacker` that reads from `packet`.
+    pub fn new(packet: Bytes) -> Self {
+        Self { packet }
+    }
+
+    fn read(&mut self) -> Result<ChannelMessage, MalformedPacket> {
+        let mut cursor = &self.packet[..];
+        let channel = VarInt::read(&mut cursor).map_err(|_| MalformedPacket)?;
+        let len = VarInt::read(&mut cursor).map_err(|_| MalformedPacket)?;
+
+        let channel = ChannelId::try_from(channel).map_err(|_| MalformedPacket)?;
+        let len = usize::try_from(u64::from(len)).map_err(|_| MalformedPacket)?;
+        if cursor.len() < len { return Err(MalformedPacket) }
+
+        self.packet.advance(self.packet.len() - cursor.len());
+        return Ok(ChannelMessage {
+            channel,
+            message: Message::from_bytes(self.packet.split_to(len)),
+        });
+    }
+}
+
+impl Iterator for PacketUnpacker {
+    type Item = Result<ChannelMessage, MalformedPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.packet.is_empty() { return None }
+
+        let result = self.read();
+        if result.is_err() { self.packet.clear() }
+        return Some(result);
+    }
+}
+
+/// An error returned by [`PacketUnpacker`] when a packet couldn't be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MalformedPacket;
+
+impl Display for MalformedPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("malformed packet")
+    }
+}
+
+impl std::error::Error for MalformedPacket {}
+
+#[test]
+fn packet_packing_test() {
+    let message = |channel: u32, payload: &'static [u8]| ChannelMessage {
+        channel: ChannelId::from(channel),
+        message: Message::from_static(payload),
+    };
+
+    // Each message takes 2 bytes of header, so only two fit in a packet
+    let mut packer = PacketPacker::new(16);
+    packer.push(message(0, b"first")).unwrap();
+    packer.push(message(1, b"second")).unwrap();
+    packer.push(message(0, b"third")).unwrap();
+    assert_eq!(packer.push(message(2, b"this one is far too large")).unwrap_err().limit, 14);
+    assert_eq!(packer.packets(), 2);
+
+    let packets: Vec<_> = packer.finish().collect();
+    assert_eq!(packets.len(), 2);
+    assert!(packets.iter().all(|packet| packet.len() <= 16));
+
+    let unpacked: Vec<_> = packets.iter()
+        .flat_map(|packet| PacketUnpacker::new(packet.clone()))
+        .map(|message| message.unwrap())
+        .map(|message| (u32::from(message.channel), message.message.as_slice().to_vec()))
+        .collect();
+
+    assert_eq!(unpacked, vec![
+        (0, b"first".to_vec()),
+        (1, b"second".to_vec()),
+        (0, b"third".to_vec()),
+    ]);
+
+    // Truncated messages are an error, and nothing is read afterwards
+    let mut unpacker = PacketUnpacker::new(packets[0].slice(..10));
+    assert!(unpacker.next().unwrap().is_ok());
+    assert!(matches!(unpacker.next(), Some(Err(MalformedPacket))));
+    assert!(unpacker.next().is_none());
+}
@@ -0,0 +1,187 @@
+use std::{fmt::Display, net::SocketAddr, time::{Duration, SystemTime, UNIX_EPOCH}};
+use bevy_ecs::prelude::*;
+use bytes::BufMut;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The length of the truncated signature in a cookie.
+const MAC_LEN: usize = 16;
+
+/// The length of a cookie created by [`HandshakeCookies::issue`].
+pub const COOKIE_LEN: usize = 8 + MAC_LEN;
+
+/// A secret key used to sign handshake cookies.
+///
+/// This key never leaves the server, and can be regenerated every time it starts.
+#[derive(Clone)]
+pub struct CookieKey([u8; 32]);
+
+impl CookieKey {
+    /// Creates a `CookieKey` from 32 bytes.
+    /// These bytes should come from a cryptographically secure random number generator.
+    #[inline]
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    fn sign(&self, issued_at: u64, address: SocketAddr) -> [u8; MAC_LEN] {
+        // HMAC accepts keys of any length, so this can't fail
+        let mut hmac = HmacSha256::new_from_slice(&self.0).unwrap();
+
+        let mut body = Vec::with_capacity(40);
+        body.put_slice(b"stardust cookie");
+        body.put_u64(issued_at);
+        // IPv4 addresses are mapped, so both families are the same length
+        body.put_slice(&match address.ip() {
+            std::net::IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            std::net::IpAddr::V6(ip) => ip,
+        }.octets());
+        body.put_u16(address.port());
+
+        hmac.update(&body);
+        let mac = hmac.finalize().into_bytes();
+        return mac[..MAC_LEN].try_into().unwrap();
+    }
+}
+
+impl std::fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Don't leak the key into logs
+        f.write_str("CookieKey(..)")
+    }
+}
+
+/// Issues and verifies stateless handshake cookies.
+///
+/// A cookie is a timestamp and a signature over the timestamp and the client's address.
+/// Since the server doesn't need to store anything to check a cookie later, it can
+/// respond to any number of connection attempts without allocating anything.
+///
+/// The key can be changed with [`rotate`](Self::rotate). Cookies signed with the previous
+/// key are still accepted, so clients in the middle of a handshake aren't affected.
+#[derive(Resource)]
+pub struct HandshakeCookies {
+    current: CookieKey,
+    previous: Option<CookieKey>,
+    lifetime: Duration,
+}
+
+impl HandshakeCookies {
+    /// Creates a new `HandshakeCookies` that signs cookies with `key`.
+    pub fn new(key: CookieKey) -> Self {
+        Self {
+            current: key,
+            previous: None,
+            lifetime: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets how long a cookie is valid for after it's issued.
+    /// This should be long enough for a client to respond. Defaults to 10 seconds.
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        return self;
+    }
+
+    /// Replaces the signing key.
+    /// Cookies signed with the current key are accepted until the next rotation.
+    pub fn rotate(&mut self, key: CookieKey) {
+        self.previous = Some(std::mem::replace(&mut self.current, key));
+    }
+
+    /// Creates a cookie for a client at `address`, to be sent back to it.
+    pub fn issue(&self, address: SocketAddr, now: SystemTime) -> [u8; COOKIE_LEN] {
+        let issued_at = unix_secs(now);
+
+        let mut cookie = [0u8; COOKIE_LEN];
+        cookie[..8].copy_from_slice(&issued_at.to_be_bytes());
+        cookie[8..].copy_from_slice(&self.current.sign(issued_at, address));
+        return cookie;
+    }
+
+    /// Checks that `cookie` was issued to a client at `address`, and hasn't expired.
+    ///
+    /// Cookies aren't tracked, so a valid cookie can be used more than once until it expires.
+    /// Transport layers should ignore cookies from addresses that already have a peer entity.
+    pub fn verify(&self, cookie: &[u8], address: SocketAddr, now: SystemTime) -> Result<(), CookieError> {
+        if cookie.len() != COOKIE_LEN { return Err(CookieError::Malformed) }
+        let issued_at = u64::from_be_bytes(cookie[..8].try_into().unwrap());
+        let mac = &cookie[8..];
+
+        // Check the signature before trusting the timestamp
+        let valid = |key: &CookieKey| constant_eq(&key.sign(issued_at, address), mac);
+        if !valid(&self.current) && !self.previous.as_ref().is_some_and(valid) {
+            return Err(CookieError::Invalid);
+        }
+
+        let now = unix_secs(now);
+        if issued_at > now || now - issued_at >= self.lifetime.as_secs() {
+            return Err(CookieError::Expired);
+        }
+
+        return Ok(());
+    }
+}
+
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() { return false }
+    a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// An error returned when verifying a handshake cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CookieError {
+    /// The cookie was the wrong length.
+    Malformed,
+
+    /// The cookie wasn't issued by this server, or was issued to a different address.
+    Invalid,
+
+    /// The cookie was issued too long ago.
+    Expired,
+}
+
+impl Display for CookieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CookieError::Malformed => f.write_str("malformed cookie"),
+            CookieError::Invalid => f.write_str("invalid cookie"),
+            CookieError::Expired => f.write_str("cookie expired"),
+        }
+    }
+}
+
+impl std::error::Error for CookieError {}
+
+#[test]
+fn handshake_cookie_test() {
+    let mut cookies = HandshakeCookies::new(CookieKey::from_bytes([1; 32]))
+        .with_lifetime(Duration::from_secs(5));
+
+    let now = SystemTime::now();
+    let client: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+    let spoofed: SocketAddr = "192.0.2.1:4001".parse().unwrap();
+    let cookie = cookies.issue(client, now);
+
+    // Cookies are only valid for the address they were issued to
+    assert_eq!(cookies.verify(&cookie, client, now), Ok(()));
+    assert_eq!(cookies.verify(&cookie, spoofed, now), Err(CookieError::Invalid));
+    assert_eq!(cookies.verify(&cookie[1..], client, now), Err(CookieError::Malformed));
+
+    // Cookies expire
+    let later = now + Duration::from_secs(6);
+    assert_eq!(cookies.verify(&cookie, client, later), Err(CookieError::Expired));
+
+    // Cookies from the previous key are accepted, but not from the one before that
+    cookies.rotate(CookieKey::from_bytes([2; 32]));
+    assert_eq!(cookies.verify(&cookie, client, now), Ok(()));
+    cookies.rotate(CookieKey::from_bytes([3; 32]));
+    assert_eq!(cookies.verify(&cookie, client, now), Err(CookieError::Invalid));
+}
@@ -0,0 +1,126 @@
+use std::{collections::HashMap, net::{IpAddr, Ipv6Addr}, time::{Duration, Instant}};
+use bevy_ecs::prelude::*;
+
+/// Limits how often each source address can attempt to connect, using a token bucket.
+///
+/// IPv6 addresses are grouped by their /64 prefix, since a single host is usually
+/// assigned an entire /64, and could otherwise cycle through addresses to avoid the limit.
+///
+/// The number of tracked addresses is capped, so a flood of spoofed addresses can't use up memory.
+/// Once the cap is reached, addresses that haven't been seen recently are forgotten, at most once
+/// a second, and new addresses are rejected until space is freed.
+#[derive(Resource)]
+pub struct AddressRateLimiter {
+    buckets: HashMap<IpAddr, Bucket>,
+    rate: f32,
+    burst: f32,
+    max_addresses: usize,
+    last_prune: Option<Instant>,
+}
+
+struct Bucket {
+    tokens: f32,
+    updated: Instant,
+}
+
+impl AddressRateLimiter {
+    /// Creates a new `AddressRateLimiter` that allows `rate` attempts per second from each address,
+    /// with up to `burst` attempts at once.
+    pub fn new(rate: f32, burst: u32) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            rate,
+            burst: burst as f32,
+            max_addresses: 65536,
+            last_prune: None,
+        }
+    }
+
+    /// Sets the maximum number of addresses that are tracked at once. Defaults to `65536`.
+    pub fn with_max_addresses(mut self, max_addresses: usize) -> Self {
+        self.max_addresses = max_addresses;
+        return self;
+    }
+
+    /// Returns `true` and consumes an attempt if `address` is allowed to attempt a connection at `now`.
+    pub fn check(&mut self, address: IpAddr, now: Instant) -> bool {
+        let key = group(address);
+
+        if !self.buckets.contains_key(&key) && self.buckets.len() >= self.max_addresses {
+            // Pruning scans every address, so it's rate limited too
+            self.prune_if_due(now);
+            if self.buckets.len() >= self.max_addresses { return false }
+        }
+
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+
+        // Refill the bucket for the time since it was last used
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f32();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 { return false }
+        bucket.tokens -= 1.0;
+        return true;
+    }
+
+    /// Forgets addresses whose buckets would be full at `now`, since they behave the same as new addresses.
+    pub fn prune(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f32();
+            bucket.tokens + elapsed * rate < burst
+        });
+
+        self.last_prune = Some(now);
+    }
+
+    pub(super) fn prune_if_due(&mut self, now: Instant) {
+        if self.last_prune.is_some_and(|last| now.saturating_duration_since(last) < Duration::from_secs(1)) { return }
+        self.prune(now);
+    }
+}
+
+fn group(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V4(_) => address,
+        IpAddr::V6(ip) => {
+            // Treat IPv4-mapped addresses like IPv4 addresses
+            if let Some(ip) = ip.to_ipv4_mapped() { return IpAddr::V4(ip) }
+            let prefix = u128::from(ip) & !((1u128 << 64) - 1);
+            IpAddr::V6(Ipv6Addr::from(prefix))
+        },
+    }
+}
+
+#[test]
+fn address_rate_limiter_test() {
+    let mut limiter = AddressRateLimiter::new(1.0, 2).with_max_addresses(2);
+    let now = Instant::now();
+    let a: IpAddr = "192.0.2.1".parse().unwrap();
+    let b: IpAddr = "2001:db8::1".parse().unwrap();
+    let b2: IpAddr = "2001:db8::ffff".parse().unwrap();
+    let c: IpAddr = "192.0.2.3".parse().unwrap();
+
+    // Bursts are allowed, and then refill over time
+    assert!(limiter.check(a, now));
+    assert!(limiter.check(a, now));
+    assert!(!limiter.check(a, now));
+    assert!(limiter.check(a, now + Duration::from_secs(1)));
+
+    // Addresses in the same /64 share a bucket
+    assert!(limiter.check(b, now));
+    assert!(limiter.check(b2, now));
+    assert!(!limiter.check(b, now));
+
+    // New addresses are rejected when full, until old ones can be forgotten
+    assert!(!limiter.check(c, now));
+    assert!(!limiter.check(c, now + Duration::from_millis(1800)));
+
+    // Old addresses are only forgotten once a second, even if they could be forgotten sooner
+    assert!(!limiter.check(c, now + Duration::from_millis(2500)));
+    assert!(limiter.check(c, now + Duration::from_secs(3)));
+}
@@ -0,0 +1,48 @@
+//! Stateless handshake cookies and per-address rate limits, for datagram transports.
+//!
+//! Datagram transports that create a [`Peer`] entity for the first packet from a new address can be
+//! flooded with packets from spoofed addresses, allocating an entity for each one, and filling up the
+//! server before any real client can connect. Instead, transports can use this module to make sure
+//! the client can receive packets at the address it claims before spawning anything:
+//!
+//! 1. When a packet arrives from an unknown address, check the [`AddressRateLimiter`], and drop the packet if it fails.
+//! 2. If the packet doesn't contain a cookie, reply with one from [`HandshakeCookies::issue`], and forget about it.
+//! 3. If the packet contains a cookie, check it with [`HandshakeCookies::verify`].
+//!    Only spawn a peer entity if the cookie is valid.
+//!
+//! Since the cookie is signed over the client's address, a client using a spoofed address never receives
+//! a valid cookie. Nothing is stored for clients that haven't returned a cookie, so the only cost
+//! of a flood is the bandwidth of the replies. To avoid being used to amplify attacks, the reply
+//! should be no larger than the packet that caused it, which clients can ensure by padding their first packet.
+//!
+//! Add [`CookiePlugin`] to the app to periodically forget addresses in the
+//! [`AddressRateLimiter`] resource, if one exists.
+
+mod cookie;
+mod limiter;
+
+pub use cookie::{HandshakeCookies, CookieKey, CookieError, COOKIE_LEN};
+pub use limiter::AddressRateLimiter;
+
+use std::time::Instant;
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+
+/// Maintains the [`AddressRateLimiter`] resource.
+/// See the [module level documentation](self) for more information.
+pub struct CookiePlugin;
+
+impl Plugin for CookiePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, prune_limiter_system
+            .run_if(resource_exists::<AddressRateLimiter>)
+            .before(NetworkRecv::Receive));
+    }
+}
+
+fn prune_limiter_system(
+    mut limiter: ResMut<AddressRateLimiter>,
+) {
+    limiter.prune_if_due(Instant::now());
+}
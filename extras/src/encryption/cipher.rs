@@ -0,0 +1,217 @@
+use std::fmt::Display;
+use bevy_ecs::prelude::*;
+use bytes::{BufMut, Bytes};
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, ChaCha20Poly1305, Key, Nonce};
+use crate::numbers::Sequence;
+
+/// The length of the sequence number at the start of every packet.
+const HEADER_LEN: usize = 8;
+
+/// The length of the authentication tag at the end of every packet.
+const TAG_LEN: usize = 16;
+
+/// The number of packets before the latest one that can still be received.
+const WINDOW_SIZE: u64 = 64;
+
+/// Authenticated encryption of packets with ChaCha20-Poly1305, created by a [`KeyExchange`](super::KeyExchange).
+///
+/// Each packet is prefixed with an 8-byte sequence number, which is used as the nonce,
+/// and suffixed with a 16-byte authentication tag, adding 24 bytes of overhead in total.
+/// Packets can arrive out of order, but packets that have already been received, or are
+/// too far behind the most recent packet, are rejected to prevent replay attacks.
+///
+/// When added to a peer entity, packets that fail authentication are counted,
+/// and the peer is disconnected once the threshold set in [`EncryptionPlugin`](super::EncryptionPlugin) is exceeded.
+#[derive(Component)]
+pub struct PacketCipher {
+    send_cipher: ChaCha20Poly1305,
+    recv_cipher: ChaCha20Poly1305,
+
+    send_seq: Sequence<u64>,
+    recv_latest: Option<Sequence<u64>>,
+    recv_window: u64,
+
+    pub(super) failures: u32,
+    pub(super) reported: bool,
+}
+
+impl PacketCipher {
+    pub(super) fn new(send_key: [u8; 32], recv_key: [u8; 32]) -> Self {
+        Self {
+            send_cipher: ChaCha20Poly1305::new(Key::from_slice(&send_key)),
+            recv_cipher: ChaCha20Poly1305::new(Key::from_slice(&recv_key)),
+
+            send_seq: Sequence::default(),
+            recv_latest: None,
+            recv_window: 0,
+
+            failures: 0,
+            reported: false,
+        }
+    }
+
+    /// Returns the amount of bytes added to every packet by [`seal`](Self::seal).
+    #[inline]
+    pub const fn overhead() -> usize {
+        HEADER_LEN + TAG_LEN
+    }
+
+    /// Returns how many received packets have failed authentication.
+    #[inline]
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Encrypts `packet`, returning the data to send to the remote peer.
+    pub fn seal(&mut self, packet: &[u8]) -> Result<Bytes, CipherError> {
+        let seq = self.send_seq;
+        self.send_seq.increment();
+
+        let header = seq.inner().to_be_bytes();
+        let ciphertext = self.send_cipher.encrypt(&nonce(seq), Payload {
+            msg: packet,
+            aad: &header,
+        }).map_err(|_| CipherError::Tampered)?;
+
+        let mut buf = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        buf.put_slice(&header);
+        buf.put_slice(&ciphertext);
+        return Ok(Bytes::from(buf));
+    }
+
+    /// Decrypts and authenticates a packet received from the remote peer.
+    ///
+    /// Packets that fail authentication are counted towards [`failures`](Self::failures).
+    pub fn open(&mut self, packet: &[u8]) -> Result<Bytes, CipherError> {
+        if packet.len() < HEADER_LEN + TAG_LEN {
+            self.failures += 1;
+            return Err(CipherError::Malformed);
+        }
+
+        let (header, ciphertext) = packet.split_at(HEADER_LEN);
+        let seq = Sequence::from(u64::from_be_bytes(header.try_into().unwrap()));
+
+        // Check for replays before doing any expensive work
+        if !self.check_window(seq) { return Err(CipherError::Replayed) }
+
+        let plaintext = match self.recv_cipher.decrypt(&nonce(seq), Payload {
+            msg: ciphertext,
+            aad: header,
+        }) {
+            Ok(plaintext) => plaintext,
+            Err(_) => {
+                self.failures += 1;
+                return Err(CipherError::Tampered);
+            },
+        };
+
+        // Only update the window once the packet is known to be genuine,
+        // otherwise an attacker could move it forward with forged packets
+        self.update_window(seq);
+        return Ok(Bytes::from(plaintext));
+    }
+
+    fn check_window(&self, seq: Sequence<u64>) -> bool {
+        let Some(latest) = self.recv_latest else { return true };
+        if seq > latest { return true }
+
+        let diff = latest.diff(&seq);
+        if diff >= WINDOW_SIZE { return false }
+        return self.recv_window & (1 << diff) == 0;
+    }
+
+    fn update_window(&mut self, seq: Sequence<u64>) {
+        let Some(latest) = self.recv_latest else {
+            self.recv_latest = Some(seq);
+            self.recv_window = 1;
+            return;
+        };
+
+        if seq > latest {
+            let shift = seq.diff(&latest);
+            self.recv_window = if shift >= WINDOW_SIZE { 0 } else { self.recv_window << shift };
+            self.recv_window |= 1;
+            self.recv_latest = Some(seq);
+        } else {
+            self.recv_window |= 1 << latest.diff(&seq);
+        }
+    }
+}
+
+fn nonce(seq: Sequence<u64>) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&seq.inner().to_be_bytes());
+    return Nonce::from(nonce);
+}
+
+/// An error returned by a [`PacketCipher`] or [`KeyExchange`](super::KeyExchange).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CipherError {
+    /// The remote peer's public key was invalid.
+    BadKey,
+
+    /// The packet was too short to be valid.
+    Malformed,
+
+    /// The packet failed authentication, and was either corrupted or tampered with.
+    Tampered,
+
+    /// The packet was already received, or is too old to check.
+    Replayed,
+}
+
+impl Display for CipherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CipherError::BadKey => f.write_str("invalid public key"),
+            CipherError::Malformed => f.write_str("malformed packet"),
+            CipherError::Tampered => f.write_str("packet failed authentication"),
+            CipherError::Replayed => f.write_str("packet was replayed"),
+        }
+    }
+}
+
+impl std::error::Error for CipherError {}
+
+#[test]
+fn packet_cipher_test() {
+    use super::{KeyExchange, ExchangeRole};
+
+    let initiator = KeyExchange::new();
+    let responder = KeyExchange::new();
+    let (ipub, rpub) = (initiator.public_key(), responder.public_key());
+    let mut initiator = initiator.finish(rpub, ExchangeRole::Initiator).unwrap();
+    let mut responder = responder.finish(ipub, ExchangeRole::Responder).unwrap();
+
+    // Packets should round trip in both directions
+    let a = initiator.seal(b"Hello, world!").unwrap();
+    let b = initiator.seal(b"Goodbye, world!").unwrap();
+    assert_eq!(a.len(), 13 + PacketCipher::overhead());
+    assert_eq!(&responder.open(&b).unwrap()[..], b"Goodbye, world!");
+    assert_eq!(&responder.open(&a).unwrap()[..], b"Hello, world!");
+    let c = responder.seal(b"It's a very nice day, isn't it?").unwrap();
+    assert_eq!(&initiator.open(&c).unwrap()[..], b"It's a very nice day, isn't it?");
+
+    // Receiving a packet twice should fail
+    assert_eq!(responder.open(&a).unwrap_err(), CipherError::Replayed);
+    assert_eq!(responder.failures(), 0);
+
+    // Tampering with a packet should fail
+    let mut d = initiator.seal(b"Yeah, I agree!").unwrap().to_vec();
+    d[10] ^= 1;
+    assert_eq!(responder.open(&d).unwrap_err(), CipherError::Tampered);
+    assert_eq!(responder.failures(), 1);
+
+    // A packet sealed for the other direction shouldn't be accepted
+    for _ in 0..4 { responder.seal(b"").unwrap(); }
+    let e = responder.seal(b"No, I think it's fine!").unwrap();
+    assert_eq!(responder.open(&e).unwrap_err(), CipherError::Tampered);
+
+    // Packets far behind the latest one should be rejected
+    let old = initiator.seal(b"old").unwrap();
+    for _ in 0..WINDOW_SIZE { initiator.seal(b"").unwrap(); }
+    let new = initiator.seal(b"new").unwrap();
+    assert_eq!(&responder.open(&new).unwrap()[..], b"new");
+    assert_eq!(responder.open(&old).unwrap_err(), CipherError::Replayed);
+}
@@ -0,0 +1,82 @@
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+use super::{CipherError, PacketCipher};
+
+/// Which side of the key exchange the local peer is on.
+///
+/// Each side derives a different key for sending, so the two
+/// sides must disagree on this for the exchange to succeed.
+/// Usually, the client is the initiator, and the server the responder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExchangeRole {
+    /// The peer that started the connection.
+    Initiator,
+
+    /// The peer that accepted the connection.
+    Responder,
+}
+
+/// An in-progress X25519 key exchange, to create a [`PacketCipher`].
+///
+/// Each side creates a `KeyExchange`, sends its [`public_key`](Self::public_key) to the other
+/// during the handshake, and then calls [`finish`](Self::finish) with the key it received.
+/// A new `KeyExchange` must be used for every connection.
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    public: PublicKey,
+}
+
+impl KeyExchange {
+    /// Creates a new `KeyExchange` with a random ephemeral key.
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random();
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    /// Returns the public key to send to the remote peer.
+    #[inline]
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    /// Completes the exchange with the public key sent by the remote peer.
+    ///
+    /// Fails with [`CipherError::BadKey`] if the remote key is a low-order point,
+    /// which would let an attacker force a known shared secret.
+    pub fn finish(self, remote: [u8; 32], role: ExchangeRole) -> Result<PacketCipher, CipherError> {
+        let remote = PublicKey::from(remote);
+        let shared = self.secret.diffie_hellman(&remote);
+        if !shared.was_contributory() { return Err(CipherError::BadKey) }
+
+        // Both keys are hashed in a fixed order, so both sides derive the same keys
+        let (initiator, responder) = match role {
+            ExchangeRole::Initiator => (self.public.as_bytes(), remote.as_bytes()),
+            ExchangeRole::Responder => (remote.as_bytes(), self.public.as_bytes()),
+        };
+
+        let derive = |label: &[u8]| -> [u8; 32] {
+            let mut hasher = Sha256::new();
+            hasher.update(label);
+            hasher.update(shared.as_bytes());
+            hasher.update(initiator);
+            hasher.update(responder);
+            hasher.finalize().into()
+        };
+
+        let to_responder = derive(b"stardust initiator to responder");
+        let to_initiator = derive(b"stardust responder to initiator");
+
+        return Ok(match role {
+            ExchangeRole::Initiator => PacketCipher::new(to_responder, to_initiator),
+            ExchangeRole::Responder => PacketCipher::new(to_initiator, to_responder),
+        });
+    }
+}
+
+impl Default for KeyExchange {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
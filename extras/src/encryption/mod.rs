@@ -0,0 +1,70 @@
+//! Authenticated encryption for datagram transports.
+//!
+//! During the handshake, both peers create a [`KeyExchange`] and send each other their public keys.
+//! Once both keys are known, [`KeyExchange::finish`] creates a [`PacketCipher`], which the transport
+//! layer uses to [`seal`](PacketCipher::seal) every outgoing packet and [`open`](PacketCipher::open)
+//! every incoming packet. Keys are derived from an X25519 exchange, and packets are encrypted with
+//! ChaCha20-Poly1305, using a [`Sequence`](crate::numbers::Sequence) number as the nonce.
+//!
+//! If the `PacketCipher` is added to the peer entity, and [`EncryptionPlugin`] is added to the app,
+//! peers that send too many packets that fail authentication are disconnected with
+//! [`DisconnectReason::FailedVerification`]. Occasional failures are tolerated, since
+//! anyone who can guess the peer's address can send it garbage.
+//!
+//! Note that this only protects the contents of packets, and doesn't authenticate the remote peer.
+//! Without some way to verify the remote's public key, this is vulnerable to man-in-the-middle attacks.
+
+mod cipher;
+mod exchange;
+
+pub use cipher::{PacketCipher, CipherError};
+pub use exchange::{KeyExchange, ExchangeRole};
+
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+
+/// Disconnects peers whose [`PacketCipher`] has seen too many packets fail authentication.
+/// See the [module level documentation](self) for more information.
+pub struct EncryptionPlugin {
+    /// How many packets can fail authentication before the peer is disconnected.
+    pub max_failures: u32,
+}
+
+impl Default for EncryptionPlugin {
+    fn default() -> Self {
+        Self {
+            max_failures: 32,
+        }
+    }
+}
+
+impl Plugin for EncryptionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MaxFailures(self.max_failures));
+
+        app.add_systems(PreUpdate, check_failures_system
+            .in_set(NetworkRecv::Synchronise));
+    }
+}
+
+#[derive(Resource)]
+struct MaxFailures(u32);
+
+fn check_failures_system(
+    max_failures: Res<MaxFailures>,
+    mut query: Query<(Entity, &mut PacketCipher), With<Peer>>,
+    mut disconnects: EventWriter<DisconnectPeerEvent>,
+) {
+    for (entity, mut cipher) in query.iter_mut() {
+        if cipher.reported || cipher.failures <= max_failures.0 { continue }
+        cipher.reported = true;
+
+        disconnects.send(DisconnectPeerEvent {
+            peer: entity,
+            reason: DisconnectReason::FailedVerification,
+            comment: Some(format!("{} packets failed authentication", cipher.failures).into()),
+            force: false,
+        });
+    }
+}
@@ -3,4 +3,21 @@
 
 pub mod bytes;
 pub mod link;
-pub mod numbers;
\ No newline at end of file
+pub mod numbers;
+pub mod pcapng;
+pub mod recording;
+
+#[cfg(feature="cookies")]
+pub mod cookies;
+
+#[cfg(feature="encryption")]
+pub mod encryption;
+
+#[cfg(feature="noise")]
+pub mod noise;
+
+#[cfg(feature="tls")]
+pub mod tls;
+
+#[cfg(feature="tokens")]
+pub mod tokens;
\ No newline at end of file
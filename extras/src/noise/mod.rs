@@ -0,0 +1,69 @@
+//! Noise protocol handshakes and encryption for stream transports.
+//!
+//! Stream transports like TCP can't use [`PacketCipher`](crate::encryption::PacketCipher),
+//! since they don't preserve packet boundaries. Instead, a [`NoiseSession`] runs a [Noise]
+//! handshake over the stream, and then encrypts the stream of messages in both directions.
+//! Unlike the `encryption` feature, the handshake authenticates both peers with static keys,
+//! so a man-in-the-middle can be detected by checking the remote's key.
+//!
+//! Two handshake patterns are supported, set with [`NoisePattern`]:
+//! - `XX`, where neither side knows the other's static key in advance.
+//! - `IK`, where the client already knows the server's static key, such as one shipped with the game.
+//!
+//! If the `NoiseSession` is added to the peer entity, and [`NoisePlugin`] is added to the app,
+//! the remote's static key is added as a [`PeerStaticKey`] once the handshake finishes, and
+//! peers whose session fails are disconnected with [`DisconnectReason::FailedVerification`].
+//!
+//! [Noise]: https://noiseprotocol.org/noise.html
+
+mod session;
+
+pub use session::{NoiseSession, NoiseKeypair, NoisePattern, NoiseError};
+
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+
+/// Manages peers with a [`NoiseSession`].
+/// See the [module level documentation](self) for more information.
+pub struct NoisePlugin;
+
+impl Plugin for NoisePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, session_status_system
+            .in_set(NetworkRecv::Synchronise));
+    }
+}
+
+/// The static public key of a peer, verified during its Noise handshake.
+///
+/// Unlike the peer's address, this stays the same across connections,
+/// so it can be used to recognise returning players.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub struct PeerStaticKey(pub [u8; 32]);
+
+fn session_status_system(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut NoiseSession, Option<&PeerStaticKey>), With<Peer>>,
+    mut disconnects: EventWriter<DisconnectPeerEvent>,
+) {
+    for (entity, mut session, key) in query.iter_mut() {
+        if key.is_none() && session.is_established() {
+            if let Some(remote) = session.remote_static() {
+                commands.entity(entity).insert(PeerStaticKey(remote));
+            }
+        }
+
+        if session.reported { continue }
+        let Some(err) = session.error() else { continue };
+        let comment = format!("noise session failed: {err}");
+        session.reported = true;
+
+        disconnects.send(DisconnectPeerEvent {
+            peer: entity,
+            reason: DisconnectReason::FailedVerification,
+            comment: Some(comment.into()),
+            force: false,
+        });
+    }
+}
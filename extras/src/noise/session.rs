@@ -0,0 +1,428 @@
+use std::{fmt::Display, sync::Arc};
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use snow::{Builder, HandshakeState, TransportState};
+use crate::bytes::framing::{read_message, FramingError};
+use crate::numbers::VarInt;
+
+/// The largest Noise message, including the authentication tag.
+const MAX_NOISE_MESSAGE: usize = 65535;
+
+/// The length of the authentication tag on every Noise transport message.
+const TAG_LEN: usize = 16;
+
+/// The largest plaintext that fits in a single Noise transport message.
+const MAX_PLAINTEXT: usize = MAX_NOISE_MESSAGE - TAG_LEN;
+
+/// Mixed into the handshake so that sessions can't be confused with other Noise protocols.
+const PROLOGUE: &[u8] = b"bevy_stardust noise v1";
+
+/// A static X25519 keypair, identifying a peer across connections.
+#[derive(Clone)]
+pub struct NoiseKeypair {
+    private: Vec<u8>,
+    public: [u8; 32],
+}
+
+impl NoiseKeypair {
+    /// Generates a new random keypair.
+    pub fn generate() -> Self {
+        let keypair = Builder::new(NoisePattern::XX.params())
+            .generate_keypair()
+            .unwrap();
+
+        Self {
+            private: keypair.private,
+            public: keypair.public.try_into().unwrap(),
+        }
+    }
+
+    /// Creates a keypair from a stored private key.
+    pub fn from_private(private: [u8; 32]) -> Self {
+        let public = x25519_dalek::x25519(private, x25519_dalek::X25519_BASEPOINT_BYTES);
+
+        Self {
+            private: private.to_vec(),
+            public,
+        }
+    }
+
+    /// Returns the private key, for storage.
+    pub fn private(&self) -> [u8; 32] {
+        self.private.as_slice().try_into().unwrap()
+    }
+
+    /// Returns the public key, which is shared with remote peers.
+    #[inline]
+    pub fn public(&self) -> [u8; 32] {
+        self.public
+    }
+}
+
+impl std::fmt::Debug for NoiseKeypair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Don't leak the private key into logs
+        f.debug_struct("NoiseKeypair")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The Noise handshake pattern to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// Both sides transmit their static keys during the handshake.
+    /// Takes three messages, and neither side needs to know the other's key in advance.
+    XX,
+
+    /// The initiator already knows the responder's static key.
+    /// Takes two messages, and fails if the responder doesn't have the matching private key.
+    IK {
+        /// The responder's static public key.
+        remote: [u8; 32],
+    },
+}
+
+impl NoisePattern {
+    fn params(&self) -> snow::params::NoiseParams {
+        match self {
+            NoisePattern::XX => "Noise_XX_25519_ChaChaPoly_BLAKE2s",
+            NoisePattern::IK { .. } => "Noise_IK_25519_ChaChaPoly_BLAKE2s",
+        }.parse().unwrap()
+    }
+}
+
+enum SessionState {
+    Handshake(Box<HandshakeState>),
+    Transport(Box<TransportState>),
+    Failed(NoiseError),
+}
+
+/// A Noise session over a byte stream, such as a TCP connection.
+///
+/// The transport layer passes every chunk of bytes it reads from the stream to [`recv`](Self::recv),
+/// and writes everything returned by [`take_outgoing`](Self::take_outgoing) to the stream. Once the
+/// handshake is complete, messages are encrypted with [`send`](Self::send), and decrypted messages
+/// are returned by `recv`.
+///
+/// Noise messages are framed with a 2-byte big-endian length prefix. After the handshake,
+/// messages are encoded as a [`VarInt`] channel id and length, followed by the payload,
+/// and this stream of bytes is split into Noise messages of up to 65535 bytes.
+/// Messages larger than 16 MiB fail the session with [`NoiseError::TooLarge`].
+#[derive(Component)]
+pub struct NoiseSession {
+    state: SessionState,
+    expected_remote: Option<[u8; 32]>,
+
+    // Bytes read from the stream that haven't formed a whole frame yet
+    framed: BytesMut,
+
+    // Decrypted bytes that haven't formed a whole message yet
+    plaintext: BytesMut,
+
+    outgoing: BytesMut,
+    scratch: Vec<u8>,
+
+    pub(super) reported: bool,
+}
+
+impl NoiseSession {
+    /// Creates a session for the peer that opened the connection, and starts the handshake.
+    pub fn initiator(keypair: &NoiseKeypair, pattern: NoisePattern) -> Self {
+        let mut builder = Builder::new(pattern.params())
+            .local_private_key(&keypair.private)
+            .prologue(PROLOGUE);
+
+        if let NoisePattern::IK { remote } = &pattern {
+            builder = builder.remote_public_key(remote);
+        }
+
+        let mut session = Self::new(builder.build_initiator().unwrap());
+
+        // The initiator always sends the first message
+        session.advance_handshake();
+        return session;
+    }
+
+    /// Creates a session for the peer that accepted the connection.
+    pub fn responder(keypair: &NoiseKeypair, pattern: NoisePattern) -> Self {
+        let builder = Builder::new(pattern.params())
+            .local_private_key(&keypair.private)
+            .prologue(PROLOGUE);
+
+        return Self::new(builder.build_responder().unwrap());
+    }
+
+    fn new(handshake: HandshakeState) -> Self {
+        Self {
+            state: SessionState::Handshake(Box::new(handshake)),
+            expected_remote: None,
+            framed: BytesMut::new(),
+            plaintext: BytesMut::new(),
+            outgoing: BytesMut::new(),
+            scratch: vec![0; MAX_NOISE_MESSAGE],
+            reported: false,
+        }
+    }
+
+    /// Fails the handshake if the remote peer's static key isn't `key`.
+    ///
+    /// With [`NoisePattern::IK`], the initiator already checks the responder's key,
+    /// so this is mostly useful for the XX pattern, or to check the initiator's key.
+    pub fn expect_remote(mut self, key: [u8; 32]) -> Self {
+        self.expected_remote = Some(key);
+        return self;
+    }
+
+    /// Returns `true` if the handshake has completed, and messages can be sent.
+    #[inline]
+    pub fn is_established(&self) -> bool {
+        matches!(self.state, SessionState::Transport(_))
+    }
+
+    /// Returns the error that ended the session, if any.
+    pub fn error(&self) -> Option<&NoiseError> {
+        match &self.state {
+            SessionState::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// Returns the remote peer's static public key, once the handshake has revealed it.
+    pub fn remote_static(&self) -> Option<[u8; 32]> {
+        let key = match &self.state {
+            SessionState::Handshake(state) => state.get_remote_static(),
+            SessionState::Transport(state) => state.get_remote_static(),
+            SessionState::Failed(_) => None,
+        }?;
+
+        return key.try_into().ok();
+    }
+
+    /// Returns all bytes that should be written to the stream.
+    pub fn take_outgoing(&mut self) -> Bytes {
+        self.outgoing.split().freeze()
+    }
+
+    /// Processes bytes read from the stream, returning any messages that were decrypted.
+    ///
+    /// If this fails, the session can no longer be used, and the connection should be closed.
+    pub fn recv(&mut self, bytes: &[u8]) -> Result<Vec<ChannelMessage>, NoiseError> {
+        if let Some(err) = self.error() { return Err(err.clone()) }
+        self.framed.extend_from_slice(bytes);
+
+        let mut messages = Vec::new();
+        while let Some(frame) = self.next_frame() {
+            let result = match &mut self.state {
+                SessionState::Handshake(state) => {
+                    state.read_message(&frame, &mut self.scratch)
+                        .map(|_| ())
+                        .map_err(NoiseError::from)
+                },
+
+                SessionState::Transport(state) => {
+                    state.read_message(&frame, &mut self.scratch)
+                        .map(|len| self.plaintext.extend_from_slice(&self.scratch[..len]))
+                        .map_err(NoiseError::from)
+                },
+
+                SessionState::Failed(err) => Err(err.clone()),
+            };
+
+            if let Err(err) = result { return Err(self.fail(err)) }
+
+            match self.state {
+                SessionState::Handshake(_) => self.advance_handshake(),
+                _ => self.decode_messages(&mut messages)?,
+            }
+
+            if let Some(err) = self.error() { return Err(err.clone()) }
+        }
+
+        return Ok(messages);
+    }
+
+    /// Encrypts `message`, queuing it to be written to the stream.
+    pub fn send(&mut self, message: &ChannelMessage) -> Result<(), NoiseError> {
+        let SessionState::Transport(_) = self.state else {
+            return Err(self.error().cloned().unwrap_or(NoiseError::NotEstablished));
+        };
+
+        let mut plaintext = Vec::with_capacity(message.message.len() + 16);
+        VarInt::from(message.channel).write(&mut plaintext).unwrap();
+        VarInt::try_from(message.message.len()).unwrap().write(&mut plaintext).unwrap();
+        plaintext.put_slice(message.message.as_slice());
+
+        for chunk in plaintext.chunks(MAX_PLAINTEXT) {
+            self.write_frame(chunk)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Encrypts all messages in `queue`, queuing them to be written to the stream.
+    pub fn send_all(&mut self, queue: &PeerMessages<Outgoing>) -> Result<(), NoiseError> {
+        for (channel, messages) in queue {
+            for message in messages {
+                self.send(&ChannelMessage { channel, message })?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn next_frame(&mut self) -> Option<BytesMut> {
+        if self.framed.len() < 2 { return None }
+        let len = u16::from_be_bytes([self.framed[0], self.framed[1]]) as usize;
+        if self.framed.len() < len + 2 { return None }
+
+        self.framed.advance(2);
+        return Some(self.framed.split_to(len));
+    }
+
+    fn write_frame(&mut self, payload: &[u8]) -> Result<(), NoiseError> {
+        let len = match &mut self.state {
+            SessionState::Handshake(state) => state.write_message(payload, &mut self.scratch),
+            SessionState::Transport(state) => state.write_message(payload, &mut self.scratch),
+            SessionState::Failed(err) => return Err(err.clone()),
+        };
+
+        let len = len.map_err(|err| self.fail(err.into()))?;
+        self.outgoing.put_u16(len as u16);
+        self.outgoing.put_slice(&self.scratch[..len]);
+        return Ok(());
+    }
+
+    fn advance_handshake(&mut self) {
+        // Write handshake messages until it's the remote's turn
+        loop {
+            let SessionState::Handshake(state) = &self.state else { return };
+            if state.is_handshake_finished() || !state.is_my_turn() { break }
+            if self.write_frame(&[]).is_err() { return }
+        }
+
+        // Check the remote's key once we know it
+        if let (Some(expected), Some(remote)) = (self.expected_remote, self.remote_static()) {
+            if expected != remote {
+                self.fail(NoiseError::KeyMismatch);
+                return;
+            }
+        }
+
+        // Switch to transport mode once the handshake is done
+        let SessionState::Handshake(state) = &self.state else { return };
+        if !state.is_handshake_finished() { return }
+        let SessionState::Handshake(state) = std::mem::replace(&mut self.state, SessionState::Failed(NoiseError::NotEstablished)) else { unreachable!() };
+        self.state = match state.into_transport_mode() {
+            Ok(state) => SessionState::Transport(Box::new(state)),
+            Err(err) => SessionState::Failed(err.into()),
+        };
+    }
+
+    fn decode_messages(&mut self, messages: &mut Vec<ChannelMessage>) -> Result<(), NoiseError> {
+        loop {
+            match read_message(&mut self.plaintext) {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) => return Ok(()),
+                Err(FramingError::Malformed) => return Err(self.fail(NoiseError::Malformed)),
+                Err(FramingError::TooLarge) => return Err(self.fail(NoiseError::TooLarge)),
+            }
+        }
+    }
+
+    fn fail(&mut self, err: NoiseError) -> NoiseError {
+        self.state = SessionState::Failed(err.clone());
+        return err;
+    }
+}
+
+/// An error that ended a [`NoiseSession`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum NoiseError {
+    /// The handshake hasn't finished yet, so messages can't be sent.
+    NotEstablished,
+
+    /// The remote peer's static key wasn't the one that was expected.
+    KeyMismatch,
+
+    /// A decrypted message couldn't be parsed.
+    Malformed,
+
+    /// The remote sent a message larger than 16 MiB.
+    TooLarge,
+
+    /// An error from the Noise implementation, such as a message failing authentication.
+    Noise(Arc<snow::Error>),
+}
+
+impl Display for NoiseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoiseError::NotEstablished => f.write_str("handshake not finished"),
+            NoiseError::KeyMismatch => f.write_str("remote static key mismatch"),
+            NoiseError::Malformed => f.write_str("malformed message"),
+            NoiseError::TooLarge => f.write_str("message too large"),
+            NoiseError::Noise(err) => f.write_fmt(format_args!("noise error: {err}")),
+        }
+    }
+}
+
+impl std::error::Error for NoiseError {}
+
+impl From<snow::Error> for NoiseError {
+    #[inline]
+    fn from(value: snow::Error) -> Self {
+        Self::Noise(Arc::new(value))
+    }
+}
+
+#[test]
+fn noise_session_test() {
+    // Shuttles bytes between two sessions until neither has anything to send
+    fn pump(a: &mut NoiseSession, b: &mut NoiseSession) -> (Vec<ChannelMessage>, Vec<ChannelMessage>) {
+        let (mut to_a, mut to_b) = (Vec::new(), Vec::new());
+        loop {
+            let (ab, ba) = (a.take_outgoing(), b.take_outgoing());
+            if ab.is_empty() && ba.is_empty() { break }
+            to_b.extend(b.recv(&ab).unwrap_or_default());
+            to_a.extend(a.recv(&ba).unwrap_or_default());
+        }
+        return (to_a, to_b);
+    }
+
+    let client_key = NoiseKeypair::generate();
+    let server_key = NoiseKeypair::generate();
+
+    // XX handshake, then a large message in each direction
+    let mut client = NoiseSession::initiator(&client_key, NoisePattern::XX);
+    let mut server = NoiseSession::responder(&server_key, NoisePattern::XX);
+    pump(&mut client, &mut server);
+    assert!(client.is_established() && server.is_established());
+    assert_eq!(client.remote_static(), Some(server_key.public()));
+    assert_eq!(server.remote_static(), Some(client_key.public()));
+
+    let large = Message::from_bytes(Bytes::from(vec![7u8; 100_000]));
+    client.send(&ChannelMessage { channel: ChannelId::from(3), message: large.clone() }).unwrap();
+    server.send(&ChannelMessage { channel: ChannelId::from(1), message: Message::from_static(b"Hello!") }).unwrap();
+    let (to_client, to_server) = pump(&mut client, &mut server);
+    assert_eq!(to_server.len(), 1);
+    assert_eq!(to_server[0].channel, ChannelId::from(3));
+    assert_eq!(to_server[0].message.as_slice(), large.as_slice());
+    assert_eq!(to_client[0].message.as_slice(), b"Hello!");
+
+    // IK handshake with the wrong responder key should never complete
+    let wrong = NoiseKeypair::generate();
+    let mut client = NoiseSession::initiator(&client_key, NoisePattern::IK { remote: wrong.public() });
+    let mut server = NoiseSession::responder(&server_key, NoisePattern::IK { remote: wrong.public() });
+    pump(&mut client, &mut server);
+    assert!(!client.is_established());
+    assert!(server.error().is_some());
+
+    // XX handshake with an unexpected key should fail
+    let mut client = NoiseSession::initiator(&client_key, NoisePattern::XX).expect_remote(wrong.public());
+    let mut server = NoiseSession::responder(&server_key, NoisePattern::XX);
+    pump(&mut client, &mut server);
+    assert!(matches!(client.error(), Some(NoiseError::KeyMismatch)));
+}
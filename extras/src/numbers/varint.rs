@@ -159,10 +159,12 @@ impl From<VarInt> for u64 {
     }
 }
 
-impl From<VarInt> for ChannelId {
+impl TryFrom<VarInt> for ChannelId {
+    type Error = ();
+
     #[inline]
-    fn from(value: VarInt) -> Self {
-        value.into()
+    fn try_from(value: VarInt) -> Result<Self, Self::Error> {
+        u32::try_from(value).map(ChannelId::from)
     }
 }
 
@@ -0,0 +1,135 @@
+//! Exporting message traffic to [pcapng] captures, for inspection in Wireshark.
+//!
+//! Add [`PcapngExportPlugin`] and insert a [`PcapngExport`] resource to start capturing.
+//! Incoming [`PeerMessages`] are captured after [`NetworkRecv::Receive`], before they're validated,
+//! and outgoing [`PeerMessages`] are captured in [`NetworkSend::Diagnostics`], before they're cleared.
+//! Both directions are captured as the transport layer sees them, so messages on channels with
+//! [compression](bevy_stardust::channels::ChannelCompression) are captured compressed.
+//! Every message becomes a single packet with the `LINKTYPE_USER0` link type.
+//!
+//! To view the captures, install the Lua dissector from `dissectors/stardust.lua` in this crate's
+//! repository by copying it into your [Wireshark plugin folder]. The dissector shows the direction,
+//! peer, channel id and channel name of every message.
+//!
+//! [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html
+//! [Wireshark plugin folder]: https://www.wireshark.org/docs/wsug_html_chunked/ChPluginFolders.html
+
+mod writer;
+
+pub use writer::{PcapngWriter, LINKTYPE_STARDUST};
+
+use std::{io::{self, Write}, time::SystemTime};
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::messages::MessageDirection;
+
+/// Captures all messages to a [`PcapngExport`] resource, if present.
+/// See the [module level documentation](self) for more information.
+pub struct PcapngExportPlugin;
+
+impl Plugin for PcapngExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, capture_system::<Incoming>
+            .run_if(resource_exists::<PcapngExport>)
+            .after(NetworkRecv::Receive)
+            .before(NetworkRecv::Validate));
+
+        app.add_systems(PostUpdate, capture_system::<Outgoing>
+            .run_if(resource_exists::<PcapngExport>)
+            .in_set(NetworkSend::Diagnostics));
+    }
+}
+
+type BoxedWriter = Box<dyn Write + Send + Sync>;
+
+/// Writes all messages to a pcapng capture while present in the `World`.
+///
+/// If writing fails, capturing stops, and the error is available through [`error`](Self::error).
+#[derive(Resource)]
+pub struct PcapngExport {
+    state: ExportState,
+}
+
+enum ExportState {
+    // The channel table is written when the exporter is first run,
+    // as the channel registry might not exist yet when it's created.
+    Pending(BoxedWriter),
+    Writing(PcapngWriter<BoxedWriter>),
+    Failed(io::Error),
+    Empty,
+}
+
+impl PcapngExport {
+    /// Creates a new `PcapngExport` that writes to `writer`.
+    ///
+    /// Packets are written as soon as they're captured, so you probably want to use a [`BufWriter`](std::io::BufWriter).
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + Sync + 'static,
+    {
+        Self {
+            state: ExportState::Pending(Box::new(writer)),
+        }
+    }
+
+    /// Returns the error that stopped the capture, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        match &self.state {
+            ExportState::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.state {
+            ExportState::Pending(writer) => writer.flush(),
+            ExportState::Writing(writer) => writer.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    fn writer(&mut self, channels: &Channels, time: SystemTime) -> Option<&mut PcapngWriter<BoxedWriter>> {
+        // Write the headers and channel table if we haven't already
+        if let ExportState::Pending(_) = self.state {
+            let ExportState::Pending(writer) = std::mem::replace(&mut self.state, ExportState::Empty) else { unreachable!() };
+            self.state = match PcapngWriter::new(writer).and_then(|mut writer| {
+                writer.write_channels(channels, time)?;
+                Ok(writer)
+            }) {
+                Ok(writer) => ExportState::Writing(writer),
+                Err(err) => ExportState::Failed(err),
+            };
+        }
+
+        match &mut self.state {
+            ExportState::Writing(writer) => Some(writer),
+            _ => None,
+        }
+    }
+}
+
+fn capture_system<D: MessageDirection>(
+    channels: Channels,
+    mut export: ResMut<PcapngExport>,
+    query: Query<(Entity, &PeerMessages<D>), With<Peer>>,
+) {
+    let time = SystemTime::now();
+    let export = export.as_mut();
+    let Some(writer) = export.writer(&channels, time) else { return };
+
+    let result = query.iter().try_for_each(|(peer, messages)| {
+        for (channel, queue) in messages {
+            for message in queue {
+                writer.write_message(time, peer, D::net_dir(), channel, &message)?;
+            }
+        }
+
+        Ok(())
+    });
+
+    if let Err(err) = result {
+        export.state = ExportState::Failed(err);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{io::{self, Write}, time::{SystemTime, UNIX_EPOCH}};
+use bevy_ecs::entity::Entity;
+use bevy_stardust::channels::ChannelRegistry;
+use bevy_stardust::prelude::*;
+use bytes::BufMut;
+
+/// The link type used for Stardust captures, `LINKTYPE_USER0`.
+/// The shipped Lua dissector registers itself for this link type.
+pub const LINKTYPE_STARDUST: u16 = 147;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x00000006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+const OPT_END: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_EPB_FLAGS: u16 = 2;
+
+const KIND_MESSAGE: u8 = 0;
+const KIND_CHANNEL_TABLE: u8 = 1;
+
+/// Writes Stardust messages to a writer in the [pcapng] format.
+///
+/// Each packet in the capture is a single message, prefixed by a small header.
+/// The header contains the direction, the peer's entity id, and the channel id.
+/// A special 'channel table' packet can also be written, which tells the dissector
+/// the names of each channel.
+///
+/// [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html
+pub struct PcapngWriter<W> {
+    inner: W,
+    scratch: Vec<u8>,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Creates a new `PcapngWriter`, immediately writing
+    /// the section header and interface description to `inner`.
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let mut scratch = Vec::with_capacity(256);
+
+        // Section header block
+        let mut body = Vec::with_capacity(16);
+        body.put_u32_le(BYTE_ORDER_MAGIC);
+        body.put_u16_le(1); // major version
+        body.put_u16_le(0); // minor version
+        body.put_i64_le(-1); // section length is unknown
+        put_block(&mut scratch, BLOCK_SECTION_HEADER, &body);
+
+        // Interface description block
+        body.clear();
+        body.put_u16_le(LINKTYPE_STARDUST);
+        body.put_u16_le(0); // reserved
+        body.put_u32_le(0); // no snap length
+        put_option(&mut body, OPT_IF_NAME, b"stardust");
+        put_option(&mut body, OPT_END, &[]);
+        put_block(&mut scratch, BLOCK_INTERFACE_DESCRIPTION, &body);
+
+        inner.write_all(&scratch)?;
+        scratch.clear();
+
+        return Ok(Self { inner, scratch });
+    }
+
+    /// Writes a packet containing the names of all channels in `registry`.
+    ///
+    /// This should be written before any messages, so
+    /// the dissector can show channel names for them.
+    pub fn write_channels(&mut self, registry: &ChannelRegistry, time: SystemTime) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(64);
+        packet.put_u8(KIND_CHANNEL_TABLE);
+        packet.put_u32(registry.count());
+
+        for id in 0..registry.count() {
+            let id = ChannelId::from(id);
+            let name = registry.metadata(id).unwrap().type_name.as_bytes();
+            let name = &name[..name.len().min(u16::MAX as usize)];
+            packet.put_u32(id.into());
+            packet.put_u16(name.len() as u16);
+            packet.put_slice(name);
+        }
+
+        return self.write_packet(time, None, &packet);
+    }
+
+    /// Writes a single message to the capture.
+    pub fn write_message(
+        &mut self,
+        time: SystemTime,
+        peer: Entity,
+        direction: NetDirection,
+        channel: ChannelId,
+        message: &Message,
+    ) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(14 + message.len());
+        packet.put_u8(KIND_MESSAGE);
+        packet.put_u8(match direction {
+            NetDirection::Incoming => 0,
+            NetDirection::Outgoing => 1,
+        });
+        packet.put_u64(peer.to_bits());
+        packet.put_u32(channel.into());
+        packet.put_slice(message.as_slice());
+
+        return self.write_packet(time, Some(direction), &packet);
+    }
+
+    /// Flushes the underlying writer.
+    #[inline]
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns the underlying writer.
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn write_packet(
+        &mut self,
+        time: SystemTime,
+        direction: Option<NetDirection>,
+        packet: &[u8],
+    ) -> io::Result<()> {
+        // Timestamps are in microseconds, the default resolution
+        let micros = time.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+
+        let mut body = Vec::with_capacity(32 + packet.len());
+        body.put_u32_le(0); // interface id
+        body.put_u32_le((micros >> 32) as u32);
+        body.put_u32_le(micros as u32);
+        body.put_u32_le(packet.len() as u32); // captured length
+        body.put_u32_le(packet.len() as u32); // original length
+        body.put_slice(packet);
+        pad(&mut body);
+
+        // The direction is also stored in the packet flags,
+        // so that Wireshark can show it without the dissector
+        if let Some(direction) = direction {
+            let flags: u32 = match direction {
+                NetDirection::Incoming => 0b01,
+                NetDirection::Outgoing => 0b10,
+            };
+
+            put_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+            put_option(&mut body, OPT_END, &[]);
+        }
+
+        self.scratch.clear();
+        put_block(&mut self.scratch, BLOCK_ENHANCED_PACKET, &body);
+        return self.inner.write_all(&self.scratch);
+    }
+}
+
+fn put_block(buf: &mut Vec<u8>, kind: u32, body: &[u8]) {
+    // The block body is always a multiple of 4 bytes
+    debug_assert_eq!(body.len() % 4, 0);
+    let len = (body.len() + 12) as u32;
+    buf.put_u32_le(kind);
+    buf.put_u32_le(len);
+    buf.put_slice(body);
+    buf.put_u32_le(len);
+}
+
+fn put_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.put_u16_le(code);
+    buf.put_u16_le(value.len() as u16);
+    buf.put_slice(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+#[test]
+fn pcapng_block_layout_test() {
+    let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+    writer.write_message(
+        UNIX_EPOCH,
+        Entity::from_raw(3),
+        NetDirection::Outgoing,
+        ChannelId::from(7),
+        &Message::from_static(b"Hello!"),
+    ).unwrap();
+
+    // Walk through every block, checking the lengths line up
+    let bytes = writer.into_inner();
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let kind = u32::from_le_bytes(bytes[offset..offset+4].try_into().unwrap());
+        let len = u32::from_le_bytes(bytes[offset+4..offset+8].try_into().unwrap()) as usize;
+        let trailer = u32::from_le_bytes(bytes[offset+len-4..offset+len].try_into().unwrap()) as usize;
+        assert_eq!(len, trailer);
+        assert_eq!(len % 4, 0);
+        blocks.push(kind);
+        offset += len;
+    }
+
+    assert_eq!(offset, bytes.len());
+    assert_eq!(blocks, [BLOCK_SECTION_HEADER, BLOCK_INTERFACE_DESCRIPTION, BLOCK_ENHANCED_PACKET]);
+}
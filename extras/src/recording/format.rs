@@ -0,0 +1,343 @@
+use std::{fmt::Display, io::{self, Read, Write}};
+use bevy_ecs::entity::Entity;
+use bevy_stardust::channels::ChannelRegistry;
+use bevy_stardust::prelude::*;
+use bytes::{Buf, BufMut, Bytes};
+use crate::numbers::VarInt;
+
+/// Magic bytes at the start of every recording.
+const MAGIC: &[u8; 8] = b"STARDREC";
+
+/// The version of the recording format.
+/// Incremented whenever the format changes in an incompatible way.
+const VERSION: u8 = 1;
+
+/// A single message captured by a [`RecordWriter`].
+#[derive(Clone)]
+pub struct Record {
+    /// The tick the message was recorded on.
+    pub tick: u64,
+
+    /// The peer entity the message was sent to or received from.
+    pub peer: Entity,
+
+    /// Whether the message was incoming or outgoing.
+    pub direction: NetDirection,
+
+    /// The message and the channel it was sent on.
+    pub message: ChannelMessage,
+}
+
+/// A channel as it was registered when the recording was made.
+#[derive(Debug, Clone)]
+pub struct RecordedChannel {
+    /// The id of the channel in the recording.
+    pub id: ChannelId,
+
+    /// The type name of the channel, from [`ChannelMetadata`](bevy_stardust::channels::ChannelMetadata).
+    pub name: Box<str>,
+}
+
+/// Writes [`Record`]s to a writer in the recording format.
+pub struct RecordWriter<W> {
+    inner: W,
+    scratch: Vec<u8>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Creates a new `RecordWriter`, immediately writing a header
+    /// containing the names of all channels in `registry` to `inner`.
+    pub fn new(mut inner: W, registry: &ChannelRegistry) -> io::Result<Self> {
+        let mut scratch = Vec::with_capacity(256);
+
+        // Identifying information
+        scratch.put_slice(MAGIC);
+        scratch.put_u8(VERSION);
+
+        // Channel table
+        put_varint(&mut scratch, registry.count() as u64);
+        for id in 0..registry.count() {
+            let id = ChannelId::from(id);
+            let name = registry.metadata(id).unwrap().type_name;
+            put_varint(&mut scratch, u32::from(id) as u64);
+            put_varint(&mut scratch, name.len() as u64);
+            scratch.put_slice(name.as_bytes());
+        }
+
+        inner.write_all(&scratch)?;
+        scratch.clear();
+
+        return Ok(Self { inner, scratch });
+    }
+
+    /// Writes a single message to the recording.
+    pub fn write(
+        &mut self,
+        tick: u64,
+        peer: Entity,
+        direction: NetDirection,
+        channel: ChannelId,
+        message: &Message,
+    ) -> io::Result<()> {
+        let scratch = &mut self.scratch;
+        scratch.clear();
+
+        put_varint(scratch, tick);
+        put_varint(scratch, peer.index() as u64);
+        put_varint(scratch, peer.generation() as u64);
+        scratch.put_u8(match direction {
+            NetDirection::Incoming => 0,
+            NetDirection::Outgoing => 1,
+        });
+        put_varint(scratch, u32::from(channel) as u64);
+        put_varint(scratch, message.len() as u64);
+        scratch.put_slice(message.as_slice());
+
+        return self.inner.write_all(scratch);
+    }
+
+    /// Flushes the underlying writer.
+    #[inline]
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns the underlying writer.
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// A recording read into memory, created by a [`RecordWriter`].
+pub struct Recording {
+    channels: Vec<RecordedChannel>,
+    records: Vec<Record>,
+}
+
+impl Recording {
+    /// Reads a recording from `reader` until it is exhausted.
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, RecordingError> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        return Self::from_bytes(Bytes::from(buf));
+    }
+
+    /// Parses a recording from `bytes`.
+    /// Message payloads are slices of `bytes`, and are not copied.
+    pub fn from_bytes(mut bytes: Bytes) -> Result<Self, RecordingError> {
+        // Check the header is what we expect
+        if bytes.remaining() < MAGIC.len() + 1 { return Err(RecordingError::BadMagic) }
+        if &bytes[..MAGIC.len()] != MAGIC { return Err(RecordingError::BadMagic) }
+        bytes.advance(MAGIC.len());
+        let version = bytes.get_u8();
+        if version != VERSION { return Err(RecordingError::UnsupportedVersion(version)) }
+
+        // Read the channel table
+        let count = get_varint(&mut bytes)?;
+        let mut channels = Vec::with_capacity(count.min(1024) as usize);
+        for _ in 0..count {
+            let id = get_channel(&mut bytes)?;
+            let len = get_varint(&mut bytes)? as usize;
+            if bytes.remaining() < len { return Err(RecordingError::Malformed) }
+            let name = std::str::from_utf8(&bytes[..len])
+                .map_err(|_| RecordingError::Malformed)?
+                .into();
+            bytes.advance(len);
+            channels.push(RecordedChannel { id, name });
+        }
+
+        // Read records until we run out of bytes
+        let mut records = Vec::new();
+        while bytes.has_remaining() {
+            let tick = get_varint(&mut bytes)?;
+            let index = get_u32(&mut bytes)?;
+            let generation = get_u32(&mut bytes)?;
+            let peer = Entity::try_from_bits((u64::from(generation) << 32) | u64::from(index))
+                .map_err(|_| RecordingError::Malformed)?;
+
+            if !bytes.has_remaining() { return Err(RecordingError::Malformed) }
+            let direction = match bytes.get_u8() {
+                0 => NetDirection::Incoming,
+                1 => NetDirection::Outgoing,
+                _ => return Err(RecordingError::Malformed),
+            };
+
+            let channel = get_channel(&mut bytes)?;
+            let len = get_varint(&mut bytes)? as usize;
+            if bytes.remaining() < len { return Err(RecordingError::Malformed) }
+            let payload = bytes.split_to(len);
+
+            records.push(Record {
+                tick,
+                peer,
+                direction,
+                message: ChannelMessage {
+                    channel,
+                    message: Message::from_bytes(payload),
+                },
+            });
+        }
+
+        return Ok(Self { channels, records });
+    }
+
+    /// Returns the channels that were registered when the recording was made.
+    #[inline]
+    pub fn channels(&self) -> &[RecordedChannel] {
+        &self.channels
+    }
+
+    /// Returns all records, in the order they were written.
+    #[inline]
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Returns all unique peers that appear in the recording.
+    pub fn peers(&self) -> Vec<Entity> {
+        let mut peers: Vec<Entity> = self.records.iter().map(|r| r.peer).collect();
+        peers.sort_unstable();
+        peers.dedup();
+        return peers;
+    }
+
+    /// Rewrites the channel ids of all records to match `registry`, using the channel names.
+    ///
+    /// This is useful if the channel registration order has changed since the recording was made.
+    /// Fails if a channel in the recording doesn't exist in `registry`.
+    pub fn remap_channels(&mut self, registry: &ChannelRegistry) -> Result<(), RecordingError> {
+        // Build a table from recorded ids to registry ids
+        let mut table = Vec::with_capacity(self.channels.len());
+        for channel in &self.channels {
+            let new = (0..registry.count())
+                .map(ChannelId::from)
+                .find(|id| registry.metadata(*id).unwrap().type_name == &*channel.name)
+                .ok_or_else(|| RecordingError::UnknownChannel(channel.name.clone()))?;
+
+            table.push((channel.id, new));
+        }
+
+        // Apply the table to all records
+        for record in self.records.iter_mut() {
+            let (_, new) = table.iter()
+                .find(|(old, _)| *old == record.message.channel)
+                .ok_or(RecordingError::Malformed)?;
+            record.message.channel = *new;
+        }
+
+        for (channel, (_, new)) in self.channels.iter_mut().zip(table.iter()) {
+            channel.id = *new;
+        }
+
+        return Ok(());
+    }
+}
+
+/// An error returned when reading a [`Recording`].
+#[derive(Debug)]
+pub enum RecordingError {
+    /// An I/O error occurred.
+    Io(io::Error),
+
+    /// The data didn't start with the expected magic bytes.
+    BadMagic,
+
+    /// The recording was made with an unsupported version of the format.
+    UnsupportedVersion(u8),
+
+    /// The recording was truncated or otherwise malformed.
+    Malformed,
+
+    /// A channel in the recording didn't exist in the registry.
+    UnknownChannel(Box<str>),
+}
+
+impl Display for RecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordingError::Io(err) => f.write_fmt(format_args!("i/o error: {err}")),
+            RecordingError::BadMagic => f.write_str("not a recording"),
+            RecordingError::UnsupportedVersion(v) => f.write_fmt(format_args!("unsupported version {v}")),
+            RecordingError::Malformed => f.write_str("malformed recording"),
+            RecordingError::UnknownChannel(name) => f.write_fmt(format_args!("unknown channel {name}")),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<io::Error> for RecordingError {
+    #[inline]
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn put_varint(buf: &mut Vec<u8>, value: u64) {
+    // Vec<u8> always has space, and all values we write are far below VarInt::MAX
+    VarInt::try_from(value).unwrap().write(buf).unwrap();
+}
+
+fn get_varint(buf: &mut Bytes) -> Result<u64, RecordingError> {
+    VarInt::read(buf).map(u64::from).map_err(|_| RecordingError::Malformed)
+}
+
+fn get_u32(buf: &mut Bytes) -> Result<u32, RecordingError> {
+    let value = get_varint(buf)?;
+    return u32::try_from(value).map_err(|_| RecordingError::Malformed);
+}
+
+fn get_channel(buf: &mut Bytes) -> Result<ChannelId, RecordingError> {
+    return Ok(ChannelId::from(get_u32(buf)?));
+}
+
+#[test]
+fn recording_round_trip_test() {
+    let registry = ChannelRegistry::default();
+    let peer = Entity::from_raw(12);
+
+    const MESSAGES: &[(u64, NetDirection, u32, &[u8])] = &[
+        (0, NetDirection::Incoming, 0, b"Hello, world!"),
+        (0, NetDirection::Outgoing, 1, b""),
+        (1, NetDirection::Incoming, 0, b"Goodbye, world!"),
+        (70000, NetDirection::Outgoing, 5, b"It's a very nice day, isn't it?"),
+    ];
+
+    let mut writer = RecordWriter::new(Vec::new(), &registry).unwrap();
+    for (tick, direction, channel, payload) in MESSAGES {
+        writer.write(
+            *tick,
+            peer,
+            *direction,
+            ChannelId::from(*channel),
+            &Message::from_static(payload),
+        ).unwrap();
+    }
+
+    let recording = Recording::from_bytes(Bytes::from(writer.into_inner())).unwrap();
+    assert_eq!(recording.peers(), vec![peer]);
+    assert_eq!(recording.records().len(), MESSAGES.len());
+
+    for (record, (tick, direction, channel, payload)) in recording.records().iter().zip(MESSAGES) {
+        assert_eq!(record.tick, *tick);
+        assert_eq!(record.peer, peer);
+        assert_eq!(record.direction, *direction);
+        assert_eq!(record.message.channel, ChannelId::from(*channel));
+        assert_eq!(record.message.message.as_slice(), *payload);
+    }
+}
+
+#[test]
+fn recording_malformed_peer_test() {
+    let registry = ChannelRegistry::default();
+    let mut buf = RecordWriter::new(Vec::new(), &registry).unwrap().into_inner();
+
+    // An entity index that doesn't fit in 32 bits
+    put_varint(&mut buf, 0);
+    put_varint(&mut buf, u32::MAX as u64 + 1);
+    put_varint(&mut buf, 1);
+
+    let result = Recording::from_bytes(Bytes::from(buf));
+    assert!(matches!(result, Err(RecordingError::Malformed)));
+}
@@ -0,0 +1,259 @@
+//! Recording and replaying network traffic, for reproducing desyncs and other bugs.
+//!
+//! Add [`RecordingPlugin`] and insert a [`Recorder`] resource to start recording.
+//! Every message in [`PeerMessages<Incoming>`] is recorded after [`NetworkRecv::Receive`],
+//! and every message in [`PeerMessages<Outgoing>`] is recorded in [`NetworkSend::Diagnostics`].
+//! Both directions are recorded as the transport layer sees them, before incoming messages
+//! are validated and after outgoing messages are prepared, so messages on channels with
+//! [compression](bevy_stardust::channels::ChannelCompression) are recorded compressed.
+//! Recordings are written in a compact binary format, which embeds the names of all channels.
+//!
+//! Recordings can be read back with [`Recording`], and fed back into an app
+//! with the [`ReplayTransportPlugin`] and a [`Replay`] component on a peer entity.
+//!
+//! ```no_run
+//! # use bevy_app::prelude::*;
+//! # use bevy_stardust_extras::recording::*;
+//! # fn main() -> std::io::Result<()> {
+//! # let mut app = App::new();
+//! let file = std::fs::File::create("session.rec")?;
+//! app.add_plugins(RecordingPlugin);
+//! app.insert_resource(Recorder::new(std::io::BufWriter::new(file)));
+//! # Ok(())
+//! # }
+//! ```
+
+mod format;
+mod replay;
+
+pub use format::{Record, RecordedChannel, RecordWriter, Recording, RecordingError};
+pub use replay::{Replay, ReplayTransportPlugin};
+
+use std::io::{self, Write};
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::messages::MessageDirection;
+
+/// Records all messages sent and received while a [`Recorder`] resource exists.
+/// See the [module level documentation](self) for more information.
+pub struct RecordingPlugin;
+
+impl Plugin for RecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, record_system::<Incoming>
+            .run_if(resource_exists::<Recorder>)
+            .after(NetworkRecv::Receive)
+            .before(NetworkRecv::Validate));
+
+        app.add_systems(PostUpdate, (record_system::<Outgoing>, advance_tick_system)
+            .chain()
+            .run_if(resource_exists::<Recorder>)
+            .in_set(NetworkSend::Diagnostics));
+    }
+}
+
+type BoxedWriter = Box<dyn Write + Send + Sync>;
+
+/// Writes all messages to a writer while present in the `World`.
+///
+/// The tick counter starts at `0` and is incremented once per app update.
+/// If writing fails, recording stops, and the error is available through [`error`](Self::error).
+#[derive(Resource)]
+pub struct Recorder {
+    state: RecorderState,
+    tick: u64,
+}
+
+enum RecorderState {
+    // The header is written when the recorder is first run,
+    // as the channel registry might not exist yet when it's created.
+    Pending(BoxedWriter),
+    Writing(RecordWriter<BoxedWriter>),
+    Failed(io::Error),
+    Empty,
+}
+
+impl Recorder {
+    /// Creates a new `Recorder` that writes to `writer`.
+    ///
+    /// Records are written as soon as they're captured, so you probably want to use a [`BufWriter`](std::io::BufWriter).
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + Sync + 'static,
+    {
+        Self {
+            state: RecorderState::Pending(Box::new(writer)),
+            tick: 0,
+        }
+    }
+
+    /// Returns the current tick.
+    #[inline]
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Returns the error that stopped the recording, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        match &self.state {
+            RecorderState::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.state {
+            RecorderState::Pending(writer) => writer.flush(),
+            RecorderState::Writing(writer) => writer.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    fn writer(&mut self, channels: &Channels) -> Option<&mut RecordWriter<BoxedWriter>> {
+        // Write the header if we haven't already
+        if let RecorderState::Pending(_) = self.state {
+            let RecorderState::Pending(writer) = std::mem::replace(&mut self.state, RecorderState::Empty) else { unreachable!() };
+            self.state = match RecordWriter::new(writer, channels) {
+                Ok(writer) => RecorderState::Writing(writer),
+                Err(err) => RecorderState::Failed(err),
+            };
+        }
+
+        match &mut self.state {
+            RecorderState::Writing(writer) => Some(writer),
+            _ => None,
+        }
+    }
+}
+
+fn record_system<D: MessageDirection>(
+    channels: Channels,
+    mut recorder: ResMut<Recorder>,
+    query: Query<(Entity, &PeerMessages<D>), With<Peer>>,
+) {
+    let recorder = recorder.as_mut();
+    let tick = recorder.tick;
+    let Some(writer) = recorder.writer(&channels) else { return };
+
+    let mut result = Ok(());
+    'outer: for (peer, messages) in query.iter() {
+        for (channel, queue) in messages {
+            for message in queue {
+                result = writer.write(tick, peer, D::net_dir(), channel, &message);
+                if result.is_err() { break 'outer }
+            }
+        }
+    }
+
+    if let Err(err) = result {
+        recorder.state = RecorderState::Failed(err);
+    }
+}
+
+fn advance_tick_system(
+    mut recorder: ResMut<Recorder>,
+) {
+    recorder.tick += 1;
+}
+
+#[test]
+fn replay_compressed_test() {
+    use std::sync::{Arc, Mutex};
+    use bevy_stardust::channels::{ChannelCompression, CompressionAlgorithm};
+
+    struct Compressed;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn recording(&self) -> Recording {
+            Recording::from_bytes(self.0.lock().unwrap().clone().into()).unwrap()
+        }
+    }
+
+    #[derive(Default, Resource)]
+    struct Received(Vec<Vec<u8>>);
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((StardustPlugin, RecordingPlugin, ReplayTransportPlugin));
+        app.add_channel::<Compressed>(ChannelConfiguration {
+            consistency: MessageConsistency::ReliableOrdered,
+            priority: 0,
+            compression: Some(ChannelCompression {
+                algorithm: CompressionAlgorithm::Lz4,
+                threshold: 0,
+                dictionary: None,
+            }),
+            ..Default::default()
+        });
+
+        app.init_resource::<Received>();
+        app.add_systems(Update, |mut received: ResMut<Received>, query: Query<&PeerMessages<Incoming>>| {
+            for messages in query.iter() {
+                for (_, queue) in messages {
+                    received.0.extend(queue.map(|message| message.as_slice().to_vec()));
+                }
+            }
+        });
+
+        app.finish();
+        app.cleanup();
+        app
+    }
+
+    fn peer(app: &mut App) -> Entity {
+        app.world_mut().spawn((Peer::new(), PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new())).id()
+    }
+
+    let payload = vec![7; 1024];
+    let channel = ChannelId::from(0); // the only channel
+
+    // Record the compressed message as it's sent
+    let sent = Buffer::default();
+    let mut sender = app();
+    sender.insert_resource(Recorder::new(sent.clone()));
+    let peer_entity = peer(&mut sender);
+    sender.world_mut().get_mut::<PeerMessages<Outgoing>>(peer_entity).unwrap()
+        .push_one(ChannelMessage { channel, message: Message::from_bytes(payload.clone().into()) });
+    sender.update();
+
+    let sent = sent.recording().records().to_vec();
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].message.message.len() < payload.len());
+
+    // Receive it, recording it as it was received
+    let received = Buffer::default();
+    let mut receiver = app();
+    receiver.insert_resource(Recorder::new(received.clone()));
+    let peer_entity = peer(&mut receiver);
+    receiver.world_mut().get_mut::<PeerMessages<Incoming>>(peer_entity).unwrap()
+        .push_one(sent[0].message.clone());
+    receiver.update();
+    assert_eq!(receiver.world().resource::<Received>().0, vec![payload.clone()]);
+
+    // Both sides of the connection recorded the same bytes
+    let recording = received.recording();
+    assert_eq!(recording.records()[0].message.message.as_slice(), sent[0].message.message.as_slice());
+
+    // Replaying the recording decompresses the message once
+    let mut replayer = app();
+    let replay = Replay::new(&recording, recording.peers()[0]);
+    let peer_entity = peer(&mut replayer);
+    replayer.world_mut().entity_mut(peer_entity).insert(replay);
+    replayer.update();
+    assert_eq!(replayer.world().resource::<Received>().0, vec![payload]);
+}
@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use super::Recording;
+
+/// A transport layer that feeds recorded messages into [`PeerMessages<Incoming>`].
+///
+/// Messages are replayed from [`Replay`] components on peer entities, one recorded tick per app update.
+/// Once a replay is exhausted, the peer is disconnected with [`DisconnectReason::Finished`].
+///
+/// Replayed messages are validated and decompressed like messages from any other transport layer,
+/// so the app must have the same channel configuration as the app that made the recording.
+pub struct ReplayTransportPlugin;
+
+impl Plugin for ReplayTransportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, (replay_system, remove_finished)
+            .chain().in_set(NetworkRecv::Receive));
+    }
+}
+
+/// Replays the incoming messages of one peer in a [`Recording`].
+/// Used by the [`ReplayTransportPlugin`].
+#[derive(Component)]
+pub struct Replay {
+    queue: VecDeque<(u64, ChannelMessage)>,
+    tick: u64,
+}
+
+impl Replay {
+    /// Creates a `Replay` of all messages received from `peer` in `recording`.
+    ///
+    /// `peer` is the entity id of the peer *when the recording was made*, as returned by [`Recording::peers`].
+    /// Replay starts at the first tick in the recording, not the first tick `peer` received a message,
+    /// so that timing is preserved when replaying multiple peers from the same recording.
+    pub fn new(recording: &Recording, peer: Entity) -> Self {
+        let tick = recording.records().first().map(|r| r.tick).unwrap_or(0);
+
+        let queue = recording.records().iter()
+            .filter(|r| r.peer == peer && r.direction == NetDirection::Incoming)
+            .map(|r| (r.tick, r.message.clone()))
+            .collect();
+
+        return Self { queue, tick };
+    }
+
+    /// Returns the recorded tick that will be replayed next.
+    #[inline]
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Returns `true` if all messages have been replayed.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+fn replay_system(
+    mut query: Query<(&mut Replay, &mut PeerMessages<Incoming>), With<Peer>>,
+) {
+    query.par_iter_mut().for_each(|(mut replay, mut queue)| {
+        let replay = replay.as_mut();
+
+        while let Some((tick, _)) = replay.queue.front() {
+            if *tick > replay.tick { break }
+            let (_, message) = replay.queue.pop_front().unwrap();
+            queue.push_one(message);
+        }
+
+        replay.tick += 1;
+    });
+}
+
+fn remove_finished(
+    mut commands: Commands,
+    mut query: Query<(Entity, &Replay, Option<&mut PeerLifestage>)>,
+    mut events: EventWriter<PeerDisconnectedEvent>,
+) {
+    for (entity, replay, stage) in query.iter_mut() {
+        if replay.is_finished() {
+            commands.entity(entity).remove::<Replay>();
+
+            events.send(PeerDisconnectedEvent {
+                peer: entity,
+                reason: DisconnectReason::Finished,
+                comment: None,
+            });
+
+            if let Some(mut stage) = stage {
+                *stage = PeerLifestage::Closed;
+            }
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::{io::{self, Read, Write}, net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs}, sync::Arc};
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bytes::{Buf, BytesMut};
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, Connection, ServerConfig, ServerConnection};
+use crate::bytes::framing::{read_message, FramingError};
+use crate::numbers::VarInt;
+
+/// Accepts incoming TLS connections over TCP.
+///
+/// Can be added to any entity. Every accepted connection is spawned as a new peer entity,
+/// with a [`TlsConnection`] component, in the [`Handshaking`](PeerLifestage::Handshaking) lifestage.
+#[derive(Component)]
+pub struct TlsListener {
+    listener: TcpListener,
+    pub(super) config: Arc<ServerConfig>,
+}
+
+impl TlsListener {
+    /// Binds a listener to `address`, accepting connections with `config`.
+    pub fn bind(address: impl ToSocketAddrs, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        return Ok(Self { listener, config });
+    }
+
+    /// Returns the address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts the next connection, if there is one.
+    ///
+    /// The outer error is an error from the listener itself,
+    /// and the inner error is an error setting up the accepted connection.
+    pub(super) fn accept(&self) -> io::Result<Option<io::Result<(TlsConnection, SocketAddr)>>> {
+        let (stream, address) = match self.listener.accept() {
+            Ok(value) => value,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+
+            // The connection was closed before it could be accepted
+            Err(err) if matches!(err.kind(), io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset) => return Ok(Some(Err(err))),
+
+            Err(err) => return Err(err),
+        };
+
+        let result = ServerConnection::new(self.config.clone())
+            .map_err(io::Error::other)
+            .and_then(|connection| TlsConnection::new(stream, connection.into()));
+
+        return Ok(Some(result.map(|connection| (connection, address))));
+    }
+}
+
+/// A TLS connection over TCP to a remote peer.
+///
+/// Created by a [`TlsListener`] for incoming connections,
+/// or with [`connect`](Self::connect) for outgoing connections.
+///
+/// Messages are encoded as a [`VarInt`] channel id and length, followed by the payload.
+/// Remotes that send messages larger than 16 MiB are disconnected with [`DisconnectReason::ProtocolViolation`].
+#[derive(Component)]
+pub struct TlsConnection {
+    stream: TcpStream,
+    connection: Connection,
+
+    // Decrypted bytes that haven't formed a whole message yet
+    plaintext: BytesMut,
+
+    // Encoded messages that rustls hasn't accepted yet, since it only buffers 64 KiB of plaintext
+    outgoing: BytesMut,
+
+    pub(super) closed: Option<(DisconnectReason, Option<Arc<str>>)>,
+}
+
+impl TlsConnection {
+    /// Opens a connection to `address`, verifying that the server's certificate is valid for `server_name`.
+    ///
+    /// This blocks until the TCP connection is established, but the TLS handshake happens in the background.
+    /// The returned component should be added to a new peer entity, in the
+    /// [`Handshaking`](PeerLifestage::Handshaking) lifestage, alongside its [`PeerMessages`].
+    pub fn connect(
+        address: impl ToSocketAddrs,
+        server_name: &str,
+        config: Arc<ClientConfig>,
+    ) -> io::Result<Self> {
+        let server_name = ServerName::try_from(server_name.to_owned())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        let connection = ClientConnection::new(config, server_name)
+            .map_err(io::Error::other)?;
+
+        let stream = TcpStream::connect(address)?;
+        return Self::new(stream, connection.into());
+    }
+
+    fn new(stream: TcpStream, connection: Connection) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        return Ok(Self {
+            stream,
+            connection,
+            plaintext: BytesMut::new(),
+            outgoing: BytesMut::new(),
+            closed: None,
+        });
+    }
+
+    /// Returns `true` if the TLS handshake is still in progress.
+    #[inline]
+    pub fn is_handshaking(&self) -> bool {
+        self.connection.is_handshaking()
+    }
+
+    /// Returns the underlying rustls connection, to inspect the negotiated parameters.
+    #[inline]
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Returns the address of the remote peer.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub(super) fn recv(&mut self, queue: &mut PeerMessages<Incoming>) {
+        if self.closed.is_some() { return }
+
+        let mut eof = false;
+        let result = loop {
+            match self.connection.read_tls(&mut self.stream) {
+                Ok(0) => { eof = true; break Ok(false) },
+                Ok(_) => {},
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(false),
+                Err(err) => {
+                    self.close(DisconnectReason::Unspecified, Some(&err.to_string()));
+                    return;
+                },
+            }
+
+            if let Err(err) = self.connection.process_new_packets() {
+                // Try to tell the remote why, but it doesn't matter if this fails
+                let _ = self.connection.write_tls(&mut self.stream);
+                self.close(classify(&err), Some(&err.to_string()));
+                return;
+            }
+
+            // rustls only buffers 16 KiB of decrypted plaintext,
+            // so it has to be taken out before reading any more
+            match self.read_plaintext() {
+                Ok(false) => {},
+                result => break result,
+            }
+        };
+
+        self.decode_messages(queue);
+
+        match result {
+            // The remote sent close_notify, so no data was lost
+            Ok(true) => self.close(DisconnectReason::Finished, None),
+            Ok(false) if eof => self.close(DisconnectReason::Unspecified, Some("connection closed without close_notify")),
+            Ok(false) => {},
+            Err(err) => self.close(DisconnectReason::Unspecified, Some(&err.to_string())),
+        }
+    }
+
+    pub(super) fn send(&mut self, queue: &PeerMessages<Outgoing>) {
+        if self.closed.is_some() { return }
+
+        let mut header = Vec::with_capacity(16);
+        for (channel, messages) in queue.iter() {
+            for message in messages {
+                header.clear();
+                VarInt::from(channel).write(&mut header).unwrap();
+                VarInt::try_from(message.len()).unwrap().write(&mut header).unwrap();
+                self.outgoing.extend_from_slice(&header);
+                self.outgoing.extend_from_slice(message.as_slice());
+            }
+        }
+
+        self.flush();
+    }
+
+    pub(super) fn flush(&mut self) {
+        loop {
+            // Give rustls as much plaintext as it will take.
+            // Before the handshake is finished, it's buffered until it can be encrypted.
+            while !self.outgoing.is_empty() {
+                match self.connection.writer().write(&self.outgoing) {
+                    Ok(0) => break,
+                    Ok(len) => self.outgoing.advance(len),
+                    Err(err) => {
+                        self.close(DisconnectReason::Unspecified, Some(&err.to_string()));
+                        return;
+                    },
+                }
+            }
+
+            if !self.connection.wants_write() { return }
+
+            while self.connection.wants_write() {
+                match self.connection.write_tls(&mut self.stream) {
+                    Ok(_) => {},
+
+                    // Anything that wasn't written stays buffered until next time
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+
+                    Err(err) => {
+                        self.close(DisconnectReason::Unspecified, Some(&err.to_string()));
+                        return;
+                    },
+                }
+            }
+
+            // Writing to the socket made room in rustls for more plaintext
+            if self.outgoing.is_empty() { return }
+        }
+    }
+
+    pub(super) fn disconnect(&mut self, reason: DisconnectReason, comment: Option<Arc<str>>) {
+        // Nothing can be sent after close_notify, so anything rustls won't take now is lost
+        self.flush();
+        self.outgoing.clear();
+        self.connection.send_close_notify();
+        self.flush();
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+        self.closed = Some((reason, comment));
+    }
+
+    // Returns `true` if the remote sent close_notify
+    fn read_plaintext(&mut self) -> io::Result<bool> {
+        let mut reader = self.connection.reader();
+        let mut buf = [0u8; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return Ok(true),
+                Ok(len) => self.plaintext.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn decode_messages(&mut self, queue: &mut PeerMessages<Incoming>) {
+        loop {
+            match read_message(&mut self.plaintext) {
+                Ok(Some(message)) => queue.push_one(message),
+                Ok(None) => break,
+
+                Err(err) => {
+                    self.close(DisconnectReason::ProtocolViolation, Some(match err {
+                        FramingError::Malformed => "malformed message header",
+                        FramingError::TooLarge => "message too large",
+                    }));
+
+                    return;
+                },
+            }
+        }
+    }
+
+    fn close(&mut self, reason: DisconnectReason, comment: Option<&str>) {
+        // Keep the first reason, since later errors are usually caused by it
+        if self.closed.is_some() { return }
+        self.closed = Some((reason, comment.map(Arc::from)));
+    }
+}
+
+fn classify(err: &rustls::Error) -> DisconnectReason {
+    use rustls::{Error, AlertDescription};
+
+    match err {
+        Error::InvalidCertificate(_)
+        | Error::NoCertificatesPresented
+        | Error::AlertReceived(AlertDescription::BadCertificate
+            | AlertDescription::CertificateRequired
+            | AlertDescription::UnknownCA
+            | AlertDescription::CertificateExpired
+            | AlertDescription::CertificateRevoked
+            | AlertDescription::CertificateUnknown) => DisconnectReason::FailedAuthentication,
+
+        _ => DisconnectReason::ProtocolViolation,
+    }
+}
+
+
+/// Returns the subject of the remote's end-entity certificate, if it sent one.
+pub(super) fn peer_subject(connection: &TlsConnection) -> Option<String> {
+    let certificate = connection.connection.peer_certificates()?.first()?;
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate.as_ref()).ok()?;
+    return Some(certificate.subject().to_string());
+}
+
+#[test]
+fn tls_connection_test() {
+    use bytes::Bytes;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use rustls::{crypto::ring::default_provider, pki_types::PrivateKeyDer, server::WebPkiClientVerifier, RootCertStore};
+
+    // Generate a certificate authority that signs both certificates
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let server_key = KeyPair::generate().unwrap();
+    let server_cert = CertificateParams::new(vec!["localhost".to_string()]).unwrap()
+        .signed_by(&server_key, &ca, &ca_key).unwrap();
+
+    let client_key = KeyPair::generate().unwrap();
+    let mut client_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    client_params.distinguished_name.push(DnType::CommonName, "player");
+    let client_cert = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+    let provider = Arc::new(default_provider());
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone()).unwrap();
+    let roots = Arc::new(roots);
+
+    let server_config = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions().unwrap()
+        .with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone()).build().unwrap())
+        .with_single_cert(vec![server_cert.der().clone()], PrivateKeyDer::Pkcs8(server_key.serialize_der().into()))
+        .unwrap();
+
+    let client_config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions().unwrap()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(vec![client_cert.der().clone()], PrivateKeyDer::Pkcs8(client_key.serialize_der().into()))
+        .unwrap();
+
+    // Connect, and send a message each way.
+    // The client also sends a message larger than rustls will buffer at once, in the same tick.
+    let listener = TlsListener::bind("127.0.0.1:0", Arc::new(server_config)).unwrap();
+    let mut client = TlsConnection::connect(listener.local_addr().unwrap(), "localhost", Arc::new(client_config)).unwrap();
+
+    let mut server = None;
+    let mut client_queues = (PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new());
+    let mut server_queues = (PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new());
+    let large: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+    client_queues.1.push_one(ChannelMessage { channel: ChannelId::from(2), message: Message::from_static(b"Hello, server!") });
+    client_queues.1.push_one(ChannelMessage { channel: ChannelId::from(3), message: Message::from(Bytes::from(large.clone())) });
+
+    for _ in 0..1000 {
+        if server.is_none() { server = listener.accept().unwrap().map(|result| result.unwrap().0) }
+        client.send(&client_queues.1);
+        client_queues.1 = PeerMessages::new();
+        client.recv(&mut client_queues.0);
+
+        if let Some(server) = &mut server {
+            let replied = server_queues.0.count() == 2;
+            server.recv(&mut server_queues.0);
+            if !replied && server_queues.0.count() == 2 {
+                server_queues.1.push_one(ChannelMessage { channel: ChannelId::from(5), message: Message::from_static(b"Hello, client!") });
+            }
+
+            server.send(&server_queues.1);
+            server_queues.1 = PeerMessages::new();
+        }
+
+        if client_queues.0.count() > 0 { break }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+
+    let server = server.unwrap();
+    assert_eq!(peer_subject(&server).as_deref(), Some("CN=player"));
+    assert_eq!(server_queues.0.iter_channel(ChannelId::from(2)).next().unwrap().as_slice(), b"Hello, server!");
+    assert_eq!(server_queues.0.iter_channel(ChannelId::from(3)).next().unwrap().as_slice(), large.as_slice());
+    let (channel, mut messages) = client_queues.0.iter().next().unwrap();
+    assert_eq!(channel, ChannelId::from(5));
+    assert_eq!(messages.next().unwrap().as_slice(), b"Hello, client!");
+    assert!(client.closed.is_none() && server.closed.is_none());
+}
@@ -0,0 +1,187 @@
+//! A TCP transport layer secured with TLS, using [rustls].
+//!
+//! Add [`TlsTransportPlugin`] to the app. Servers add a [`TlsListener`] to any entity, which spawns
+//! a peer entity for every connection it accepts. Clients open connections with [`TlsConnection::connect`],
+//! and add the result to a new peer entity themselves. Configuration, such as certificates and the
+//! certificates that are trusted, is done with rustls's [`ServerConfig`] and [`ClientConfig`], which
+//! are re-exported from this module to ensure the versions match.
+//!
+//! Peers are in the [`Handshaking`](PeerLifestage::Handshaking) lifestage until the TLS handshake finishes.
+//! If the remote presented a certificate, such as a client certificate requested by the server's
+//! [`ClientCertVerifier`](rustls::server::danger::ClientCertVerifier), its subject is added to the peer
+//! entity as a [`PeerCertificateSubject`] once it's [`Established`](PeerLifestage::Established).
+//!
+//! If the [`PeerAuthenticatorPlugin`] is added, peers accepted by a [`TlsListener`] also stay
+//! [`Handshaking`](PeerLifestage::Handshaking) until they're authenticated and given a [`PeerUid`].
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use bevy_ecs::prelude::*;
+//! # use bevy_stardust::prelude::*;
+//! # use bevy_stardust_extras::tls::*;
+//! # fn connect(mut commands: Commands, config: Arc<ClientConfig>) -> std::io::Result<()> {
+//! commands.spawn((
+//!     Peer::new(),
+//!     PeerLifestage::Handshaking,
+//!     PeerMessages::<Incoming>::new(),
+//!     PeerMessages::<Outgoing>::new(),
+//!     TlsConnection::connect("game.example.com:7777", "game.example.com", config)?,
+//! ));
+//! # Ok(())
+//! # }
+//! ```
+
+mod connection;
+
+pub use connection::{TlsListener, TlsConnection};
+pub use rustls::{self, ClientConfig, ServerConfig};
+
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{BanList, PeerAddress, PeerAuthenticatorPlugin};
+use rustls::Connection;
+
+/// Adds a TCP transport layer secured with TLS.
+/// See the [module level documentation](self) for more information.
+pub struct TlsTransportPlugin;
+
+impl Plugin for TlsTransportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, (accept_system, recv_system, remove_closed_system)
+            .chain().in_set(NetworkRecv::Receive));
+
+        app.add_systems(PostUpdate, (send_system, disconnect_system, remove_closed_system)
+            .chain().in_set(NetworkSend::Transmit));
+    }
+
+    fn finish(&self, app: &mut App) {
+        if app.is_plugin_added::<PeerAuthenticatorPlugin>() {
+            app.insert_resource(AwaitAuthentication);
+        }
+    }
+}
+
+/// Inserted if accepted peers have to be authenticated before they're established.
+#[derive(Resource)]
+struct AwaitAuthentication;
+
+/// The subject of the certificate a peer presented during the TLS handshake,
+/// such as `CN=player, O=Example`.
+///
+/// Only added if the peer presented a certificate, which servers must request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Component)]
+pub struct PeerCertificateSubject(pub String);
+
+fn accept_system(
+    mut commands: Commands,
+    bans: Option<Res<BanList>>,
+    listeners: Query<&TlsListener>,
+    mut events: EventWriter<PeerConnectingEvent>,
+) {
+    let now = std::time::SystemTime::now();
+
+    for listener in listeners.iter() {
+        loop {
+            let (connection, address) = match listener.accept() {
+                Ok(Some(Ok(value))) => value,
+
+                // Errors for one connection shouldn't stop the listener, so they're skipped
+                Ok(Some(Err(_))) => continue,
+
+                // There are no connections left, or the listener failed, so try again next tick
+                Ok(None) | Err(_) => break,
+            };
+
+            // Banned addresses are dropped before they get a peer entity
+            if bans.as_ref().is_some_and(|bans| bans.is_address_banned(address.ip(), now).is_some()) { continue }
+
+            let peer = commands.spawn((
+                Peer::new(),
+                PeerLifestage::Handshaking,
+                PeerAddress(address.ip()),
+                PeerMessages::<Incoming>::new(),
+                PeerMessages::<Outgoing>::new(),
+                connection,
+            )).id();
+
+            events.send(PeerConnectingEvent { peer });
+        }
+    }
+}
+
+fn recv_system(
+    mut commands: Commands,
+    await_auth: Option<Res<AwaitAuthentication>>,
+    authenticated: Query<(), With<PeerUid>>,
+    mut query: Query<(Entity, &mut TlsConnection, &mut PeerMessages<Incoming>, &mut PeerLifestage), With<Peer>>,
+    mut events: EventWriter<PeerConnectedEvent>,
+) {
+    for (entity, mut connection, mut queue, mut lifestage) in query.iter_mut() {
+        connection.recv(&mut queue);
+
+        // Peers become established as soon as the handshake is done
+        if *lifestage != PeerLifestage::Handshaking { continue }
+        if connection.is_handshaking() || connection.closed.is_some() { continue }
+
+        // Accepted peers also wait to be authenticated, if the app does that
+        let accepted = matches!(connection.connection(), Connection::Server(_));
+        if await_auth.is_some() && accepted && !authenticated.contains(entity) { continue }
+
+        *lifestage = PeerLifestage::Established;
+        events.send(PeerConnectedEvent { peer: entity });
+
+        if let Some(subject) = connection::peer_subject(&connection) {
+            commands.entity(entity).insert(PeerCertificateSubject(subject));
+        }
+    }
+}
+
+fn send_system(
+    mut query: Query<(&mut TlsConnection, &PeerMessages<Outgoing>), With<Peer>>,
+) {
+    query.par_iter_mut().for_each(|(mut connection, queue)| {
+        connection.send(queue);
+    });
+}
+
+fn disconnect_system(
+    mut events: EventReader<DisconnectPeerEvent>,
+    mut query: Query<&mut TlsConnection, With<Peer>>,
+) {
+    for event in events.read() {
+        let Ok(mut connection) = query.get_mut(event.peer) else { continue };
+        if connection.closed.is_some() { continue }
+
+        // Forced disconnections don't wait for the remote to be notified
+        if event.force {
+            connection.closed = Some((event.reason.clone(), event.comment.clone()));
+            continue;
+        }
+
+        connection.disconnect(event.reason.clone(), event.comment.clone());
+    }
+}
+
+fn remove_closed_system(
+    mut commands: Commands,
+    mut query: Query<(Entity, &TlsConnection, Option<&mut PeerLifestage>)>,
+    mut events: EventWriter<PeerDisconnectedEvent>,
+) {
+    for (entity, connection, lifestage) in query.iter_mut() {
+        let Some((reason, comment)) = &connection.closed else { continue };
+
+        events.send(PeerDisconnectedEvent {
+            peer: entity,
+            reason: reason.clone(),
+            comment: comment.clone(),
+        });
+
+        // Dropping the component closes the socket
+        commands.entity(entity).remove::<TlsConnection>();
+
+        if let Some(mut lifestage) = lifestage {
+            *lifestage = PeerLifestage::Closed;
+        }
+    }
+}
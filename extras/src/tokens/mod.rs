@@ -0,0 +1,235 @@
+//! Connect token authentication, similar to [netcode.io].
+//!
+//! A backend service, like a matchmaker, uses a [`TokenIssuer`] to create signed, expiring [`ConnectToken`]s.
+//! The token is given to the client, which presents it to the server during the handshake.
+//! The server checks the token with a [`TokenVerifier`], and if it's valid, assigns the [`PeerUid`] from the token.
+//! Tokens can only be used once, and peers that present an invalid token are disconnected
+//! with [`DisconnectReason::FailedAuthentication`].
+//!
+//! Add [`ConnectTokenPlugin`] to both the client and server apps, in the same order relative to other channels.
+//! On the server, insert a [`TokenVerifier`] resource. On the client, add a [`SendConnectToken`]
+//! component to the peer entity representing the server, and the token is sent automatically.
+//!
+//! Tokens are signed, but not encrypted, so the client can read their contents.
+//!
+//! [netcode.io]: https://github.com/mas-bandwidth/netcode/blob/main/STANDARD.md
+
+mod token;
+
+pub use token::{ConnectToken, TokenIssuer, TokenKey, TokenError, MAX_USER_DATA};
+
+use std::{collections::HashMap, sync::Arc, time::{Duration, SystemTime}};
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::channels::ChannelRateLimit;
+
+/// Adds connect token authentication.
+/// See the [module level documentation](self) for more information.
+pub struct ConnectTokenPlugin;
+
+impl Plugin for ConnectTokenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_channel::<ConnectTokenChannel>(ChannelConfiguration {
+            consistency: MessageConsistency::ReliableOrdered,
+            priority: u32::MAX,
+            // Only one token is ever needed
+            rate_limit: Some(ChannelRateLimit { messages: Some(4), bytes: Some(4096) }),
+            max_message_size: Some(1024),
+            direction: ChannelDirection::ClientToServer,
+            ..Default::default()
+        });
+
+        app.add_systems(PreUpdate, verify_tokens_system
+            .run_if(resource_exists::<TokenVerifier>)
+            .in_set(NetworkRecv::Synchronise));
+
+        app.add_systems(PostUpdate, send_tokens_system
+            .before(NetworkSend::Prepare));
+    }
+}
+
+/// The channel connect tokens are sent over.
+pub struct ConnectTokenChannel;
+
+/// Verifies connect tokens presented by peers in the [`Handshaking`](PeerLifestage::Handshaking) lifestage.
+///
+/// Only used on the server. If this resource doesn't exist, tokens aren't checked.
+#[derive(Resource)]
+pub struct TokenVerifier {
+    key: TokenKey,
+    timeout: Duration,
+    // Signatures of tokens that have been used, and when they expire.
+    used: HashMap<[u8; 32], u64>,
+}
+
+impl TokenVerifier {
+    /// Creates a new `TokenVerifier` that checks tokens were signed with `key`.
+    pub fn new(key: TokenKey) -> Self {
+        Self {
+            key,
+            timeout: Duration::from_secs(10),
+            used: HashMap::new(),
+        }
+    }
+
+    /// Sets how long a peer can be handshaking before it must present a token.
+    /// Defaults to 10 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    /// Checks a serialised token, returning it if it's valid and hasn't been used.
+    /// If successful, the token is marked as used, and will fail if checked again.
+    pub fn verify(&mut self, bytes: &[u8], now: SystemTime) -> Result<ConnectToken, TokenError> {
+        let token = ConnectToken::verify(bytes, &self.key)?;
+        if token.is_expired(now) { return Err(TokenError::Expired) }
+
+        // Forget tokens that have expired, since they'd be rejected anyway
+        let secs = token::unix_secs(now);
+        self.used.retain(|_, expires_at| *expires_at > secs);
+
+        // Check the token hasn't been used before
+        if self.used.insert(*token.signature(), token.expires_at).is_some() {
+            return Err(TokenError::Replayed);
+        }
+
+        return Ok(token);
+    }
+}
+
+/// Sends a connect token to the peer this component is attached to, then removes itself.
+///
+/// Only used on the client.
+#[derive(Component)]
+pub struct SendConnectToken(pub Bytes);
+
+/// Marks peers whose token was rejected, so they aren't checked again.
+#[derive(Component)]
+struct TokenRejected;
+
+/// The user data from the [`ConnectToken`] a peer presented.
+///
+/// Added to peers on the server when their token is accepted.
+#[derive(Debug, Clone, Component)]
+pub struct ConnectTokenData(pub Bytes);
+
+fn send_tokens_system(
+    mut commands: Commands,
+    channel: ChannelData<ConnectTokenChannel>,
+    mut query: Query<(Entity, &SendConnectToken, &mut PeerMessages<Outgoing>), With<Peer>>,
+) {
+    for (entity, token, mut messages) in query.iter_mut() {
+        messages.push_one(ChannelMessage {
+            channel: channel.id(),
+            message: Message::from_bytes(token.0.clone()),
+        });
+
+        commands.entity(entity).remove::<SendConnectToken>();
+    }
+}
+
+type Unverified = (Without<PeerUid>, Without<TokenRejected>);
+
+fn verify_tokens_system(
+    mut commands: Commands,
+    channel: ChannelData<ConnectTokenChannel>,
+    mut verifier: ResMut<TokenVerifier>,
+    query: Query<(Entity, &Peer, &PeerLifestage, &PeerMessages<Incoming>), Unverified>,
+    mut disconnects: EventWriter<DisconnectPeerEvent>,
+) {
+    let now = SystemTime::now();
+
+    for (entity, peer, lifestage, messages) in query.iter() {
+        if *lifestage != PeerLifestage::Handshaking { continue }
+
+        // Only the first token the peer sends is considered
+        let result = match messages.iter_channel(channel.id()).next() {
+            Some(message) => verifier.verify(&message, now),
+            None if peer.joined.elapsed() > verifier.timeout => Err(TokenError::TimedOut),
+            None => continue,
+        };
+
+        match result {
+            Ok(token) => {
+                let mut entity = commands.entity(entity);
+                entity.insert(token.uid);
+                if !token.user_data.is_empty() {
+                    entity.insert(ConnectTokenData(token.user_data));
+                }
+            },
+
+            Err(err) => {
+                commands.entity(entity).insert(TokenRejected);
+                disconnects.send(DisconnectPeerEvent {
+                    peer: entity,
+                    reason: DisconnectReason::FailedAuthentication,
+                    comment: Some(Arc::from(err.to_string())),
+                    force: false,
+                });
+            },
+        }
+    }
+}
+
+#[test]
+fn connect_token_verification_test() {
+    use std::time::Instant;
+    use bevy_ecs::event::Events;
+    use bevy_ecs::system::RunSystemOnce;
+
+    let key = TokenKey::from_bytes([7; 32]);
+    let mut issuer = TokenIssuer::new(key.clone());
+    let valid = issuer.issue(PeerUid(1), Duration::from_secs(30), Bytes::new()).unwrap().to_bytes();
+    let expired = issuer.issue(PeerUid(2), Duration::ZERO, Bytes::new()).unwrap().to_bytes();
+
+    // Tokens can only be verified once, and not after they expire
+    let mut verifier = TokenVerifier::new(key.clone());
+    let now = SystemTime::now();
+    assert_eq!(verifier.verify(&valid, now).unwrap().uid, PeerUid(1));
+    assert_eq!(verifier.verify(&valid, now).unwrap_err(), TokenError::Replayed);
+    assert_eq!(verifier.verify(&expired, now).unwrap_err(), TokenError::Expired);
+
+    let mut app = App::new();
+    app.add_plugins((StardustPlugin, ConnectTokenPlugin));
+    app.insert_resource(TokenVerifier::new(key));
+    app.finish();
+    app.cleanup();
+
+    let channel = app.world_mut().run_system_once(|channel: ChannelData<ConnectTokenChannel>| channel.id()).unwrap();
+    let spawn = |app: &mut App, token: Option<&Bytes>, joined: Instant| {
+        let mut messages = PeerMessages::<Incoming>::new();
+        if let Some(token) = token {
+            messages.push_one(ChannelMessage { channel, message: Message::from_bytes(token.clone()) });
+        }
+
+        let mut peer = Peer::new();
+        peer.joined = joined;
+        app.world_mut().spawn((peer, PeerLifestage::Handshaking, messages)).id()
+    };
+
+    let now = Instant::now();
+    let accepted = spawn(&mut app, Some(&valid), now);
+    let expired = spawn(&mut app, Some(&expired), now);
+    let silent = spawn(&mut app, None, now);
+    let late = spawn(&mut app, None, now - Duration::from_secs(60));
+    app.update();
+
+    // The same token is rejected when a second peer presents it
+    let replayed = spawn(&mut app, Some(&valid), now);
+    app.update();
+
+    assert!(app.world().get::<PeerUid>(accepted) == Some(&PeerUid(1)));
+    assert!(app.world().get::<PeerUid>(silent).is_none());
+
+    // Rejected peers are disconnected, with the error as the comment
+    let mut disconnects = app.world_mut().resource_mut::<Events<DisconnectPeerEvent>>();
+    let disconnects: Vec<_> = disconnects.drain().map(|event| (event.peer, event.reason, event.comment)).collect();
+    assert_eq!(disconnects.len(), 3);
+    for (peer, error) in [(expired, TokenError::Expired), (late, TokenError::TimedOut), (replayed, TokenError::Replayed)] {
+        assert!(disconnects.iter().any(|(p, reason, comment)| *p == peer
+            && matches!(reason, DisconnectReason::FailedAuthentication)
+            && comment.as_deref() == Some(error.to_string().as_str())));
+    }
+}
@@ -0,0 +1,262 @@
+use std::{fmt::Display, time::{Duration, SystemTime, UNIX_EPOCH}};
+use bevy_stardust::prelude::*;
+use bytes::{Buf, BufMut};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The version of the token format.
+const VERSION: u8 = 1;
+
+/// The length of the signature at the end of a token.
+const MAC_LEN: usize = 32;
+
+/// The maximum length of the user data in a token.
+pub const MAX_USER_DATA: usize = 256;
+
+/// A secret key shared between a [`TokenIssuer`] and a [`TokenVerifier`](super::TokenVerifier).
+///
+/// Anyone with this key can create valid tokens, so it must never be given to clients.
+#[derive(Clone)]
+pub struct TokenKey([u8; 32]);
+
+impl TokenKey {
+    /// Creates a `TokenKey` from 32 bytes.
+    /// These bytes should come from a cryptographically secure random number generator.
+    #[inline]
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        // HMAC accepts keys of any length, so this can't fail
+        HmacSha256::new_from_slice(&self.0).unwrap()
+    }
+}
+
+impl std::fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Don't leak the key into logs
+        f.write_str("TokenKey(..)")
+    }
+}
+
+/// A signed, expiring token that allows a client to connect to a server.
+///
+/// Tokens are created by a [`TokenIssuer`], given to the client out-of-band (such as over HTTPS),
+/// and then presented to the server during the handshake. Tokens are not encrypted, so the
+/// user data must not contain anything the client shouldn't see.
+#[derive(Debug, Clone)]
+pub struct ConnectToken {
+    /// The [`PeerUid`] the server will assign to the peer.
+    pub uid: PeerUid,
+
+    /// When the token was issued, in seconds since the Unix epoch.
+    pub issued_at: u64,
+
+    /// When the token stops being valid, in seconds since the Unix epoch.
+    pub expires_at: u64,
+
+    /// A value unique to this token, used to detect replays.
+    pub nonce: u64,
+
+    /// Arbitrary data from the issuer, such as a match id.
+    pub user_data: Bytes,
+
+    mac: [u8; MAC_LEN],
+}
+
+impl ConnectToken {
+    /// Returns the token in its serialised form, to be sent to the server.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = Vec::with_capacity(35 + self.user_data.len() + MAC_LEN);
+        self.write_body(&mut buf);
+        buf.put_slice(&self.mac);
+        return Bytes::from(buf);
+    }
+
+    /// Parses a token and checks its signature against `key`.
+    ///
+    /// This does not check if the token has expired, or if it has been used before.
+    pub fn verify(mut bytes: &[u8], key: &TokenKey) -> Result<Self, TokenError> {
+        // Split off the signature
+        if bytes.len() < MAC_LEN { return Err(TokenError::Malformed) }
+        let (body, mac) = bytes.split_at(bytes.len() - MAC_LEN);
+
+        // Check the signature before reading anything else
+        let mut hmac = key.mac();
+        hmac.update(body);
+        hmac.verify_slice(mac).map_err(|_| TokenError::BadSignature)?;
+
+        bytes = body;
+        if bytes.remaining() < 1 { return Err(TokenError::Malformed) }
+        let version = bytes.get_u8();
+        if version != VERSION { return Err(TokenError::UnsupportedVersion(version)) }
+
+        if bytes.remaining() < 34 { return Err(TokenError::Malformed) }
+        let uid = PeerUid(bytes.get_u64());
+        let issued_at = bytes.get_u64();
+        let expires_at = bytes.get_u64();
+        let nonce = bytes.get_u64();
+        let len = bytes.get_u16() as usize;
+        if bytes.remaining() != len { return Err(TokenError::Malformed) }
+        let user_data = Bytes::copy_from_slice(bytes);
+
+        return Ok(Self {
+            uid,
+            issued_at,
+            expires_at,
+            nonce,
+            user_data,
+            mac: mac.try_into().unwrap(),
+        });
+    }
+
+    /// Returns `true` if the token has expired at the time `now`.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        unix_secs(now) >= self.expires_at
+    }
+
+    /// Returns the signature of the token.
+    /// This is unique to every token, and can be used to identify it.
+    #[inline]
+    pub fn signature(&self) -> &[u8; 32] {
+        &self.mac
+    }
+
+    fn write_body(&self, buf: &mut Vec<u8>) {
+        buf.put_u8(VERSION);
+        buf.put_u64(self.uid.0);
+        buf.put_u64(self.issued_at);
+        buf.put_u64(self.expires_at);
+        buf.put_u64(self.nonce);
+        buf.put_u16(self.user_data.len() as u16);
+        buf.put_slice(&self.user_data);
+    }
+}
+
+/// Issues [`ConnectToken`]s, signing them with a [`TokenKey`].
+///
+/// This is intended to run in a matchmaker or other backend service,
+/// which shares the key with the game servers, but not the clients.
+/// It doesn't depend on the ECS, so it can run in any process.
+pub struct TokenIssuer {
+    key: TokenKey,
+    counter: u64,
+}
+
+impl TokenIssuer {
+    /// Creates a new `TokenIssuer` that signs tokens with `key`.
+    pub fn new(key: TokenKey) -> Self {
+        // Seed the counter from the current time, so that tokens
+        // issued after a restart don't reuse the same nonces
+        let counter = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Self { key, counter }
+    }
+
+    /// Issues a token for `uid` that is valid for `valid_for`, starting now.
+    ///
+    /// Fails if `user_data` is longer than [`MAX_USER_DATA`].
+    pub fn issue(
+        &mut self,
+        uid: PeerUid,
+        valid_for: Duration,
+        user_data: Bytes,
+    ) -> Result<ConnectToken, TokenError> {
+        if user_data.len() > MAX_USER_DATA { return Err(TokenError::Malformed) }
+
+        let issued_at = unix_secs(SystemTime::now());
+        let nonce = self.counter;
+        self.counter = self.counter.wrapping_add(1);
+
+        let mut token = ConnectToken {
+            uid,
+            issued_at,
+            expires_at: issued_at.saturating_add(valid_for.as_secs()),
+            nonce,
+            user_data,
+            mac: [0; MAC_LEN],
+        };
+
+        // Sign the token
+        let mut body = Vec::with_capacity(35 + token.user_data.len());
+        token.write_body(&mut body);
+        let mut hmac = self.key.mac();
+        hmac.update(&body);
+        token.mac = hmac.finalize().into_bytes().into();
+
+        return Ok(token);
+    }
+}
+
+/// An error encountered while verifying a [`ConnectToken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TokenError {
+    /// The token couldn't be parsed.
+    Malformed,
+
+    /// The token was made with an unsupported version of the format.
+    UnsupportedVersion(u8),
+
+    /// The token wasn't signed with the right key, or was modified.
+    BadSignature,
+
+    /// The token has expired.
+    Expired,
+
+    /// The token has already been used.
+    Replayed,
+
+    /// The peer didn't present a token in time.
+    TimedOut,
+}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::Malformed => f.write_str("malformed connect token"),
+            TokenError::UnsupportedVersion(v) => f.write_fmt(format_args!("unsupported connect token version {v}")),
+            TokenError::BadSignature => f.write_str("invalid connect token signature"),
+            TokenError::Expired => f.write_str("connect token expired"),
+            TokenError::Replayed => f.write_str("connect token already used"),
+            TokenError::TimedOut => f.write_str("no connect token presented"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+pub(super) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[test]
+fn token_signature_test() {
+    let key = TokenKey::from_bytes([7; 32]);
+    let mut issuer = TokenIssuer::new(key.clone());
+
+    let token = issuer.issue(PeerUid(1234), Duration::from_secs(30), Bytes::from_static(b"lobby")).unwrap();
+    let bytes = token.to_bytes();
+
+    // A valid token should round trip
+    let verified = ConnectToken::verify(&bytes, &key).unwrap();
+    assert_eq!(verified.uid, PeerUid(1234));
+    assert_eq!(verified.nonce, token.nonce);
+    assert_eq!(&verified.user_data[..], b"lobby");
+    assert!(!verified.is_expired(SystemTime::now()));
+    assert!(verified.is_expired(SystemTime::now() + Duration::from_secs(31)));
+
+    // Changing any byte should invalidate the signature
+    let mut tampered = bytes.to_vec();
+    tampered[3] ^= 1;
+    assert_eq!(ConnectToken::verify(&tampered, &key).unwrap_err(), TokenError::BadSignature);
+
+    // Using the wrong key should fail
+    let wrong = TokenKey::from_bytes([8; 32]);
+    assert_eq!(ConnectToken::verify(&bytes, &wrong).unwrap_err(), TokenError::BadSignature);
+}
@@ -0,0 +1,524 @@
+use bevy_app::prelude::*;
+use bevy_ecs::entity::Entities;
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemState;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{Infraction, PeerReputation};
+use bytes::{Buf, Bytes, BytesMut};
+use hashbrown::HashMap;
+use crate::entities::NetworkEntityMap;
+use crate::plugin::{is_client, is_server, ReplicationSystems};
+use crate::protocol::{get_id, get_u32, put, put_id};
+use crate::receive::apply_system;
+use crate::registry::{ComponentSerialiser, ReplicationRegistry};
+use crate::{NetworkId, ReplicationPlugin};
+
+const REQUEST: u8 = 0;
+const RELEASE: u8 = 1;
+const UPDATE: u8 = 2;
+const GRANTED: u8 = 3;
+const REVOKED: u8 = 4;
+const REMOVE: u8 = 5;
+
+/// Lets clients own replicated entities, sending their changes to the server instead of receiving them.
+///
+/// Every replicated entity is owned by the server, unless it has a [`NetworkOwner`] naming a peer.
+/// When an entity's owner changes, the new owner is told it was granted authority, which adds
+/// [`HasAuthority`] to its mirror, and the old owner is told it was revoked. Clients request
+/// authority with [`RequestAuthorityEvent`] and give it up with [`ReleaseAuthorityEvent`].
+///
+/// While a client has authority over an entity, changes to its replicated components, including
+/// removals, are sent to the server and applied there, and changes to them from the server are ignored.
+/// Components registered with [`replicate_mapped_component`](crate::ReplicationAppExt::replicate_mapped_component)
+/// are never sent by clients, so they're still updated by the server. Updates from peers that don't own the entity are rejected,
+/// and reported to the peer's [`PeerReputation`] as misbehaviour, except for updates from
+/// the previous owner, which may have been sent before it knew authority was revoked.
+///
+/// [`ReplicationPlugin`] must be added first.
+pub struct AuthorityPlugin {
+    /// If `true`, requests for entities owned by the server are granted immediately.
+    /// Otherwise, the application decides by reading [`AuthorityRequestedEvent`]s.
+    pub grant_requests: bool,
+
+    /// The configuration of [`AuthorityChannel`]. Must be reliable and ordered.
+    pub channel: ChannelConfiguration,
+}
+
+impl Default for AuthorityPlugin {
+    fn default() -> Self {
+        Self {
+            grant_requests: false,
+            channel: ChannelConfiguration {
+                consistency: MessageConsistency::ReliableOrdered,
+                priority: 0,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Plugin for AuthorityPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ReplicationPlugin>() {
+            panic!("ReplicationPlugin must be added before AuthorityPlugin");
+        }
+
+        let consistency = self.channel.consistency;
+        if !consistency.is_reliable() || !consistency.is_ordered() {
+            panic!("AuthorityPlugin must use a reliable and ordered channel");
+        }
+
+        app.add_channel::<AuthorityChannel>(self.channel.clone());
+        app.insert_resource(AuthoritySettings { grant_requests: self.grant_requests });
+        app.init_resource::<Owners>();
+        app.init_resource::<OwnedUpdates>();
+
+        app.add_event::<AuthorityRequestedEvent>();
+        app.add_event::<RequestAuthorityEvent>();
+        app.add_event::<ReleaseAuthorityEvent>();
+
+        app.add_systems(PreUpdate, server_receive_system
+            .run_if(is_server)
+            .in_set(NetworkRecv::Synchronise));
+
+        app.add_systems(PreUpdate, client_receive_system
+            .after(apply_system)
+            .run_if(is_client)
+            .in_set(NetworkRecv::Synchronise));
+
+        app.add_systems(PostUpdate, ownership_system
+            .run_if(is_server)
+            .after(ReplicationSystems::Send)
+            .before(NetworkSend::Prepare));
+
+        app.add_systems(PostUpdate, client_send_system
+            .run_if(is_client)
+            .before(NetworkSend::Prepare));
+    }
+}
+
+/// The channel used for authority messages and updates from owners.
+pub struct AuthorityChannel;
+
+/// The owner of a replicated entity on the server. See [`AuthorityPlugin`].
+///
+/// Entities without this component are owned by the server.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub enum NetworkOwner {
+    /// The server owns the entity.
+    #[default]
+    Server,
+
+    /// A peer owns the entity.
+    Peer(Entity),
+}
+
+/// Added to mirrored entities on clients that have been granted authority over them.
+/// See [`AuthorityPlugin`].
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct HasAuthority;
+
+/// Sent on the server when a peer requests authority over an entity.
+///
+/// If [`grant_requests`](AuthorityPlugin::grant_requests) is `false`, the request can be
+/// granted by inserting [`NetworkOwner::Peer`] on the entity.
+#[derive(Debug, Clone, Event)]
+pub struct AuthorityRequestedEvent {
+    /// The peer that requested authority.
+    pub peer: Entity,
+    /// The entity it requested authority over.
+    pub entity: Entity,
+}
+
+/// Write this on a client to request authority over a mirrored entity.
+#[derive(Debug, Clone, Event)]
+pub struct RequestAuthorityEvent {
+    /// The mirrored entity.
+    pub entity: Entity,
+}
+
+/// Write this on a client to give up authority over a mirrored entity.
+/// [`HasAuthority`] is removed immediately.
+#[derive(Debug, Clone, Event)]
+pub struct ReleaseAuthorityEvent {
+    /// The mirrored entity.
+    pub entity: Entity,
+}
+
+#[derive(Resource)]
+struct AuthoritySettings {
+    grant_requests: bool,
+}
+
+/// The peers that own entities, as of the last time they were sent.
+#[derive(Default, Resource)]
+struct Owners {
+    current: HashMap<Entity, Entity>,
+
+    /// The last peer that lost authority over each entity, whose updates are ignored.
+    revoked: HashMap<Entity, Entity>,
+}
+
+/// Changes to components on entities the client owns, to be sent to the server.
+/// Removals have no data.
+#[derive(Default, Resource)]
+pub(crate) struct OwnedUpdates(Vec<(NetworkId, u32, Option<Bytes>)>);
+
+pub(crate) fn collect_owned_component_system<C: Component>(
+    serialiser: Res<ComponentSerialiser<C>>,
+    updates: Option<ResMut<OwnedUpdates>>,
+    query: Query<(&NetworkId, Ref<C>), With<HasAuthority>>,
+    mut removed: RemovedComponents<C>,
+    without: Query<&NetworkId, (With<HasAuthority>, Without<C>)>,
+    mut scratch: Local<BytesMut>,
+) {
+    let Some(mut updates) = updates else { removed.clear(); return };
+
+    // Despawned entities are also in removed, but aren't in the query
+    for entity in removed.read() {
+        let Ok(id) = without.get(entity) else { continue };
+        updates.0.push((*id, serialiser.index, None));
+    }
+
+    for (id, value) in query.iter() {
+        if !value.is_changed() { continue }
+        serialiser.serialiser.serialise(&value, &mut scratch);
+        updates.0.push((*id, serialiser.index, Some(scratch.split().freeze())));
+    }
+}
+
+pub(crate) fn client_send_system(
+    mut commands: Commands,
+    channel: ChannelData<AuthorityChannel>,
+    mut updates: ResMut<OwnedUpdates>,
+    mut requests: EventReader<RequestAuthorityEvent>,
+    mut releases: EventReader<ReleaseAuthorityEvent>,
+    ids: Query<&NetworkId>,
+    mut peers: NetBroadcast,
+) {
+    let mut scratch = BytesMut::new();
+
+    // Clients only have one peer, so broadcasting is the same as sending to the server
+    let mut send = |scratch: &mut BytesMut| {
+        let _ = peers.broadcast(channel.id(), Message::from_bytes(scratch.split().freeze()));
+    };
+
+    for event in requests.read() {
+        let Ok(id) = ids.get(event.entity) else { continue };
+        scratch.extend_from_slice(&[REQUEST]);
+        put_id(&mut scratch, *id);
+        send(&mut scratch);
+    }
+
+    // Updates are sent before releases, so they're still from the owner
+    for (id, component, data) in updates.0.drain(..) {
+        scratch.extend_from_slice(&[if data.is_some() { UPDATE } else { REMOVE }]);
+        put_id(&mut scratch, id);
+        put(&mut scratch, component as u64);
+        if let Some(data) = data { scratch.extend_from_slice(&data) }
+        send(&mut scratch);
+    }
+
+    for event in releases.read() {
+        let Ok(id) = ids.get(event.entity) else { continue };
+        commands.entity(event.entity).remove::<HasAuthority>();
+        scratch.extend_from_slice(&[RELEASE]);
+        put_id(&mut scratch, *id);
+        send(&mut scratch);
+    }
+}
+
+type AuthorityPeer<'a> = (&'a PeerMessages<Incoming>, &'a NetworkEntityMap, Option<&'a mut PeerReputation>);
+
+fn client_receive_system(
+    mut commands: Commands,
+    channel: ChannelData<AuthorityChannel>,
+    mut peers: Query<AuthorityPeer, With<Peer>>,
+) {
+    for (messages, map, mut reputation) in peers.iter_mut() {
+        for message in messages.iter_channel(channel.id()) {
+            let mut buf = Bytes::from(message);
+            let Ok((kind, id)) = read_header(&mut buf) else {
+                if let Some(reputation) = reputation.as_mut() {
+                    reputation.report(Infraction::MalformedMessage { channel: channel.id() });
+                }
+
+                continue;
+            };
+
+            // Grants are sent after the entity, so it's only missing if it was despawned locally
+            let Some(mut entity) = map.entity(id).and_then(|entity| commands.get_entity(entity)) else { continue };
+
+            match kind {
+                GRANTED => { entity.insert(HasAuthority); },
+                REVOKED => { entity.remove::<HasAuthority>(); },
+                _ => if let Some(reputation) = reputation.as_mut() {
+                    reputation.report(Infraction::MalformedMessage { channel: channel.id() });
+                },
+            }
+        }
+    }
+}
+
+type ServerReceiveState = SystemState<(ChannelData<'static, AuthorityChannel>, Query<'static, 'static, (Entity, &'static PeerMessages<Incoming>), With<Peer>>)>;
+
+fn server_receive_system(
+    world: &mut World,
+    state: &mut ServerReceiveState,
+) {
+    let (channel, peers) = state.get_mut(world);
+    let channel = channel.id();
+    let messages: Vec<(Entity, Bytes)> = peers.iter()
+        .flat_map(|(peer, messages)| messages.iter_channel(channel).map(move |message| (peer, Bytes::from(message))))
+        .collect();
+
+    if messages.is_empty() { return }
+
+    world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+        for (peer, buf) in messages {
+            let Some(map) = world.entity_mut(peer).take::<NetworkEntityMap>() else { continue };
+            let result = handle_message(world, &registry, channel, peer, &map, buf);
+            world.entity_mut(peer).insert(map);
+
+            if let Err(infraction) = result {
+                if let Some(mut reputation) = world.get_mut::<PeerReputation>(peer) {
+                    reputation.report(infraction);
+                }
+            }
+        }
+    });
+}
+
+fn handle_message(
+    world: &mut World,
+    registry: &ReplicationRegistry,
+    channel: ChannelId,
+    peer: Entity,
+    map: &NetworkEntityMap,
+    mut buf: Bytes,
+) -> Result<(), Infraction> {
+    let malformed = Infraction::MalformedMessage { channel };
+    let (kind, id) = read_header(&mut buf).map_err(|_| malformed.clone())?;
+
+    // The entity may have been despawned after the message was sent
+    let Some(entity) = map.local_entity(id) else { return Ok(()) };
+    let owner = world.get::<NetworkOwner>(entity).copied().unwrap_or_default();
+
+    match kind {
+        REQUEST => {
+            if owner == NetworkOwner::Server && world.resource::<AuthoritySettings>().grant_requests {
+                world.entity_mut(entity).insert(NetworkOwner::Peer(peer));
+            }
+
+            world.send_event(AuthorityRequestedEvent { peer, entity });
+        },
+
+        RELEASE => if owner == NetworkOwner::Peer(peer) {
+            world.entity_mut(entity).insert(NetworkOwner::Server);
+        },
+
+        UPDATE | REMOVE => {
+            let component = get_u32(&mut buf).map_err(|_| malformed.clone())?;
+
+            if owner != NetworkOwner::Peer(peer) {
+                if world.resource::<Owners>().revoked.get(&entity) == Some(&peer) { return Ok(()) }
+                return Err(Infraction::Misbehaving { reason: "Updated an entity it doesn't own" });
+            }
+
+            let registration = registry.get(component).filter(|registration| registration.owned).ok_or(malformed.clone())?;
+            let mut entity = world.entity_mut(entity);
+            match kind {
+                UPDATE => if !(registration.insert)(&mut entity, &buf, map) { return Err(malformed) },
+                _ => (registration.remove)(&mut entity),
+            }
+        },
+
+        _ => return Err(malformed),
+    }
+
+    return Ok(());
+}
+
+fn read_header(buf: &mut Bytes) -> Result<(u8, NetworkId), ()> {
+    if !buf.has_remaining() { return Err(()) }
+    let kind = buf.get_u8();
+    return Ok((kind, get_id(buf)?));
+}
+
+fn ownership_system(
+    mut commands: Commands,
+    channel: ChannelData<AuthorityChannel>,
+    entities: &Entities,
+    mut owners: ResMut<Owners>,
+    changed: Query<(Entity, &NetworkOwner), Changed<NetworkOwner>>,
+    mut removed: RemovedComponents<NetworkOwner>,
+    mut peers: Query<(&NetworkEntityMap, &mut PeerMessages<Outgoing>), With<Peer>>,
+) {
+    let mut scratch = BytesMut::new();
+    let owners = &mut *owners;
+    owners.revoked.retain(|entity, _| entities.contains(*entity));
+
+    // Removals are first, in case the component was removed and added again
+    let mut changes: Vec<(Entity, Option<Entity>)> = removed.read().map(|entity| (entity, None)).collect();
+    changes.extend(changed.iter().map(|(entity, owner)| (entity, match owner {
+        NetworkOwner::Server => None,
+        NetworkOwner::Peer(peer) => Some(*peer),
+    })));
+
+    let mut send = |peer: Entity, entity: Entity, kind: u8| {
+        let Ok((map, mut messages)) = peers.get_mut(peer) else { return };
+        let Some(id) = map.id(entity) else { return };
+        scratch.extend_from_slice(&[kind]);
+        put_id(&mut scratch, id);
+        messages.push_one(ChannelMessage { channel: channel.id(), message: Message::from_bytes(scratch.split().freeze()) });
+    };
+
+    for (entity, new) in changes {
+        let old = match new {
+            Some(peer) => owners.current.insert(entity, peer),
+            None => owners.current.remove(&entity),
+        };
+
+        if old == new { continue }
+
+        if let Some(old) = old {
+            owners.revoked.insert(entity, old);
+            send(old, entity, REVOKED);
+        }
+
+        if let Some(new) = new {
+            if owners.revoked.get(&entity) == Some(&new) { owners.revoked.remove(&entity); }
+            send(new, entity, GRANTED);
+        }
+    }
+
+    // Entities owned by peers that disconnected go back to the server
+    for (entity, peer) in owners.current.iter() {
+        if peers.contains(*peer) { continue }
+        if let Some(mut entity) = commands.get_entity(*entity) { entity.insert(NetworkOwner::Server); }
+    }
+}
+
+#[test]
+fn authority_test() {
+    use bevy_ecs::system::RunSystemOnce;
+    use bevy_stardust::connections::{NetworkRole, ReputationPolicy};
+    use crate::{ReplicationAppExt, Replicated, Serialiser};
+
+    #[derive(Debug, PartialEq, Component)]
+    struct Health(u32);
+
+    #[derive(Debug, Clone, PartialEq, Component)]
+    struct Target(Entity);
+
+    impl bevy_ecs::entity::MapEntities for Target {
+        fn map_entities<M: bevy_ecs::entity::EntityMapper>(&mut self, mapper: &mut M) {
+            self.0 = mapper.map_entity(self.0);
+        }
+    }
+
+    #[derive(Default, Resource)]
+    struct Sent(Vec<(Entity, ChannelMessage)>);
+
+    fn app(role: NetworkRole) -> App {
+        let mut app = App::new();
+        app.add_plugins((StardustPlugin, ReplicationPlugin::default(), AuthorityPlugin { grant_requests: true, ..Default::default() }));
+        app.replicate_component::<Health>(Serialiser::new(
+            |health, buf| buf.extend_from_slice(&health.0.to_le_bytes()),
+            |bytes| Some(Health(u32::from_le_bytes(bytes.try_into().ok()?))),
+        ));
+
+        app.replicate_mapped_component::<Target>(Serialiser::new(
+            |target, buf| buf.extend_from_slice(&target.0.to_bits().to_le_bytes()),
+            |bytes| Some(Target(Entity::from_bits(u64::from_le_bytes(bytes.try_into().ok()?)))),
+        ));
+
+        // Stand in for a transport layer, taking messages before they're cleared
+        app.init_resource::<Sent>();
+        app.add_systems(PostUpdate, (|mut sent: ResMut<Sent>, query: Query<(Entity, &PeerMessages<Outgoing>)>| {
+            for (peer, messages) in query.iter() {
+                for (channel, iter) in messages.iter() {
+                    sent.0.extend(iter.map(|message| (peer, ChannelMessage { channel, message })));
+                }
+            }
+        }).in_set(NetworkSend::Transmit));
+
+        app.insert_resource(role);
+        app.insert_resource(ReputationPolicy::default());
+        app.finish();
+        app.cleanup();
+        app
+    }
+
+    let mut server = app(NetworkRole::Server);
+    let mut client = app(NetworkRole::Client);
+
+    let peer = || (Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new(), PeerReputation::default());
+    let client_peer = server.world_mut().spawn(peer()).id();
+    let rogue_peer = server.world_mut().spawn(peer()).id();
+    let server_peer = client.world_mut().spawn(peer()).id();
+
+    // Runs a tick on both sides, passing messages between the server and the client
+    let exchange = |server: &mut App, client: &mut App| {
+        server.update();
+        let sent = std::mem::take(&mut server.world_mut().resource_mut::<Sent>().0);
+        let sent = sent.into_iter().filter(|(peer, _)| *peer == client_peer).map(|(_, message)| message);
+        client.world_mut().get_mut::<PeerMessages<Incoming>>(server_peer).unwrap().push_many(sent);
+
+        client.update();
+        let sent = std::mem::take(&mut client.world_mut().resource_mut::<Sent>().0);
+        server.world_mut().get_mut::<PeerMessages<Incoming>>(client_peer).unwrap().push_many(sent.into_iter().map(|(_, message)| message));
+    };
+
+    let entity = server.world_mut().spawn((Health(10), Replicated)).id();
+    exchange(&mut server, &mut client);
+    let mirror = client.world_mut().query_filtered::<Entity, With<Health>>().single(client.world());
+
+    // Requests are granted, since the server owns the entity
+    client.world_mut().send_event(RequestAuthorityEvent { entity: mirror });
+    exchange(&mut server, &mut client);
+    exchange(&mut server, &mut client);
+    assert_eq!(server.world().get::<NetworkOwner>(entity), Some(&NetworkOwner::Peer(client_peer)));
+    assert!(client.world().get::<HasAuthority>(mirror).is_some());
+
+    // The owner's changes are applied on the server
+    client.world_mut().get_mut::<Health>(mirror).unwrap().0 = 7;
+    exchange(&mut server, &mut client);
+    exchange(&mut server, &mut client);
+    assert_eq!(server.world().get::<Health>(entity), Some(&Health(7)));
+
+    // Mapped components aren't sent by the owner, so they're still updated by the server
+    let other = server.world_mut().spawn(Replicated).id();
+    server.world_mut().entity_mut(entity).insert(Target(other));
+    exchange(&mut server, &mut client);
+    let id = server.world().get::<NetworkEntityMap>(client_peer).unwrap().id(other).unwrap();
+    let other_mirror = client.world().get::<NetworkEntityMap>(server_peer).unwrap().entity(id).unwrap();
+    assert_eq!(client.world().get::<Target>(mirror), Some(&Target(other_mirror)));
+
+    // The owner's removals are applied on the server
+    client.world_mut().entity_mut(mirror).remove::<Health>();
+    exchange(&mut server, &mut client);
+    exchange(&mut server, &mut client);
+    assert!(server.world().get::<Health>(entity).is_none());
+    assert!(client.world().get::<Health>(mirror).is_none());
+
+    // Updates from other peers are rejected and reported
+    let id = server.world().get::<NetworkEntityMap>(rogue_peer).unwrap().id(entity).unwrap();
+    let mut buf = BytesMut::new();
+    buf.extend_from_slice(&[UPDATE]);
+    put_id(&mut buf, id);
+    put(&mut buf, 0);
+    buf.extend_from_slice(&1u32.to_le_bytes());
+    let channel = server.world_mut().run_system_once(|channel: ChannelData<AuthorityChannel>| channel.id()).unwrap();
+    server.world_mut().get_mut::<PeerMessages<Incoming>>(rogue_peer).unwrap().push_channel(channel, [Message::from_bytes(buf.freeze())]);
+    exchange(&mut server, &mut client);
+    exchange(&mut server, &mut client);
+    assert!(server.world().get::<Health>(entity).is_none());
+    let reputation = server.world().get::<PeerReputation>(rogue_peer).unwrap();
+    assert!(matches!(reputation.history().next(), Some(Infraction::Misbehaving { .. })));
+
+    // Revoking authority removes it from the client
+    server.world_mut().entity_mut(entity).insert(NetworkOwner::Server);
+    exchange(&mut server, &mut client);
+    assert!(client.world().get::<HasAuthority>(mirror).is_none());
+}
@@ -0,0 +1,231 @@
+use std::num::NonZeroU32;
+use bevy_ecs::entity::EntityMapper;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use hashbrown::HashMap;
+
+/// Marks an entity on the server to be replicated to clients.
+///
+/// When this component is added, the entity is spawned on all clients,
+/// along with any of its components that are registered for replication. When it's removed,
+/// or the entity is despawned, the entity is despawned on all clients.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct Replicated;
+
+/// An identifier for a replicated entity, shared by both sides of a connection.
+///
+/// Ids are allocated separately for each connection by its [`NetworkEntityMap`], so the same entity
+/// may have different ids on different connections. When an entity stops being replicated, its
+/// id's index may be reused, but with a different generation, so old ids never refer to new entities.
+///
+/// This is added to entities that mirror a replicated entity on the other side of the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
+pub struct NetworkId {
+    index: u32,
+    generation: NonZeroU32,
+}
+
+impl NetworkId {
+    /// The largest generation, so ids can be stored in an [`Entity`] for [`MapEntities`](bevy_ecs::entity::MapEntities).
+    const MAX_GENERATION: u32 = u32::MAX >> 1;
+
+    pub(crate) fn new(index: u32, generation: u32) -> Option<Self> {
+        if generation > Self::MAX_GENERATION { return None }
+        Some(Self { index, generation: NonZeroU32::new(generation)? })
+    }
+
+    /// Returns the index of the id, which may be reused.
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the generation of the id, which changes every time the index is reused.
+    #[inline]
+    pub fn generation(&self) -> u32 {
+        self.generation.get()
+    }
+
+    /// Stores the id in an `Entity`, so it can be serialised in place of a local entity.
+    fn to_entity(self) -> Entity {
+        Entity::from_bits((self.generation.get() as u64) << 32 | self.index as u64)
+    }
+
+    fn from_entity(entity: Entity) -> Option<Self> {
+        Self::new(entity.index(), entity.generation())
+    }
+}
+
+/// Maps entities to and from [`NetworkId`]s for a single connection.
+/// Added to all [`Peer`] entities by the replication plugin.
+///
+/// A connection has two sets of ids: ids allocated by this side, for local entities sent
+/// to the peer, and ids allocated by the peer, for its entities that are mirrored locally.
+/// Ids are only valid for the connection they came from.
+///
+/// Components that contain entities can be replicated with
+/// [`replicate_mapped_component`](crate::ReplicationAppExt::replicate_mapped_component).
+/// Any entity they refer to is replaced with its id before being serialised, and with
+/// the local mirror when deserialised, using [`local_mapper`](Self::local_mapper) and
+/// [`remote_mapper`](Self::remote_mapper). Entities that don't have an id on the connection
+/// are replaced with [`Entity::PLACEHOLDER`].
+#[derive(Debug, Default, Component)]
+pub struct NetworkEntityMap {
+    local: HashMap<Entity, NetworkId>,
+    local_entities: HashMap<u32, Entity>,
+    generations: Vec<NonZeroU32>,
+    free: Vec<u32>,
+
+    remote: HashMap<u32, (NonZeroU32, Entity)>,
+    remote_ids: HashMap<Entity, NetworkId>,
+}
+
+impl NetworkEntityMap {
+    /// Returns the id of local entity `entity`, allocating one if it doesn't have one.
+    pub fn allocate(&mut self, entity: Entity) -> NetworkId {
+        if let Some(id) = self.local.get(&entity) { return *id }
+
+        let id = match self.free.pop() {
+            Some(index) => NetworkId { index, generation: self.generations[index as usize] },
+            None => {
+                let index = u32::try_from(self.generations.len()).expect("Ran out of network ids");
+                self.generations.push(NonZeroU32::MIN);
+                NetworkId { index, generation: NonZeroU32::MIN }
+            },
+        };
+
+        self.local.insert(entity, id);
+        self.local_entities.insert(id.index, entity);
+        return id;
+    }
+
+    /// Frees the id of local entity `entity`, returning it if there was one.
+    /// The index may be reused by the next allocation, with a different generation.
+    pub fn release(&mut self, entity: Entity) -> Option<NetworkId> {
+        let id = self.local.remove(&entity)?;
+        self.local_entities.remove(&id.index);
+
+        let generation = &mut self.generations[id.index as usize];
+        *generation = match generation.get() {
+            NetworkId::MAX_GENERATION => NonZeroU32::MIN,
+            value => NonZeroU32::new(value + 1).unwrap(),
+        };
+
+        self.free.push(id.index);
+        return Some(id);
+    }
+
+    /// Returns the id allocated for local entity `entity`, if any.
+    pub fn id(&self, entity: Entity) -> Option<NetworkId> {
+        self.local.get(&entity).copied()
+    }
+
+    /// Returns the local entity that `id` was allocated for, if it hasn't been released.
+    pub fn local_entity(&self, id: NetworkId) -> Option<Entity> {
+        let entity = *self.local_entities.get(&id.index)?;
+        (self.local.get(&entity) == Some(&id)).then_some(entity)
+    }
+
+    /// Records that the peer's entity `id` is mirrored by local entity `entity`.
+    /// Fails with the entity already using the index of `id`, if there is one.
+    pub fn insert_remote(&mut self, id: NetworkId, entity: Entity) -> Result<(), Entity> {
+        if let Some((_, existing)) = self.remote.get(&id.index) { return Err(*existing) }
+        self.remote.insert(id.index, (id.generation, entity));
+        self.remote_ids.insert(entity, id);
+        return Ok(());
+    }
+
+    /// Removes the peer's entity `id`, returning the local entity that mirrored it.
+    pub fn remove_remote(&mut self, id: NetworkId) -> Option<Entity> {
+        self.entity(id)?;
+        let (_, entity) = self.remote.remove(&id.index)?;
+        self.remote_ids.remove(&entity);
+        return Some(entity);
+    }
+
+    /// Returns the local entity mirroring the peer's entity `id`, if any.
+    /// Returns `None` if the id's index has since been reused.
+    pub fn entity(&self, id: NetworkId) -> Option<Entity> {
+        self.remote.get(&id.index)
+            .filter(|(generation, _)| *generation == id.generation)
+            .map(|(_, entity)| *entity)
+    }
+
+    /// Returns the peer's id for `entity`, if it mirrors one of the peer's entities.
+    pub fn remote_id(&self, entity: Entity) -> Option<NetworkId> {
+        self.remote_ids.get(&entity).copied()
+    }
+
+    /// Returns an [`EntityMapper`] that replaces local entities with their ids,
+    /// for entities that are sent to the peer.
+    pub fn local_mapper(&self) -> impl EntityMapper + '_ {
+        LocalMapper(self)
+    }
+
+    /// Returns an [`EntityMapper`] that replaces ids with the local entities that mirror them,
+    /// for entities that are received from the peer.
+    pub fn remote_mapper(&self) -> impl EntityMapper + '_ {
+        RemoteMapper(self)
+    }
+}
+
+pub(crate) fn add_entity_map_system(
+    mut commands: Commands,
+    query: Query<Entity, (Added<Peer>, Without<NetworkEntityMap>)>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).insert(NetworkEntityMap::default());
+    }
+}
+
+struct LocalMapper<'a>(&'a NetworkEntityMap);
+
+impl EntityMapper for LocalMapper<'_> {
+    fn map_entity(&mut self, entity: Entity) -> Entity {
+        self.0.id(entity).map(NetworkId::to_entity).unwrap_or(Entity::PLACEHOLDER)
+    }
+}
+
+struct RemoteMapper<'a>(&'a NetworkEntityMap);
+
+impl EntityMapper for RemoteMapper<'_> {
+    fn map_entity(&mut self, entity: Entity) -> Entity {
+        if entity == Entity::PLACEHOLDER { return entity }
+        NetworkId::from_entity(entity)
+            .and_then(|id| self.0.entity(id))
+            .unwrap_or(Entity::PLACEHOLDER)
+    }
+}
+
+#[test]
+fn network_entity_map_test() {
+    let mut world = World::new();
+    let a = world.spawn_empty().id();
+    let b = world.spawn_empty().id();
+    let mut map = NetworkEntityMap::default();
+
+    // Released indices are reused with a new generation
+    let first = map.allocate(a);
+    assert_eq!(map.allocate(a), first);
+    assert_eq!(map.release(a), Some(first));
+    let second = map.allocate(b);
+    assert_eq!(second.index(), first.index());
+    assert_ne!(second.generation(), first.generation());
+    assert_eq!(map.local_entity(second), Some(b));
+    assert_eq!(map.local_entity(first), None);
+
+    // Stale remote ids don't resolve to the entity that reused their index
+    let mut remote = NetworkEntityMap::default();
+    let mirror = world.spawn_empty().id();
+    remote.insert_remote(second, mirror).unwrap();
+    assert_eq!(remote.entity(second), Some(mirror));
+    assert_eq!(remote.entity(first), None);
+    assert!(remote.insert_remote(first, mirror).is_err());
+    assert_eq!(remote.remove_remote(first), None);
+
+    // Entities survive a round trip through both mappers
+    let sent = map.local_mapper().map_entity(b);
+    assert_eq!(remote.remote_mapper().map_entity(sent), mirror);
+    assert_eq!(map.local_mapper().map_entity(a), Entity::PLACEHOLDER);
+    assert_eq!(remote.remote_mapper().map_entity(Entity::PLACEHOLDER), Entity::PLACEHOLDER);
+}
@@ -0,0 +1,184 @@
+use std::any::type_name;
+use std::marker::PhantomData;
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{Infraction, NetworkRole, PeerReputation};
+use bytes::BytesMut;
+use crate::Serialiser;
+
+/// Who a [`SendNetEvent`] is sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetEventTarget {
+    /// All [`Established`] peers.
+    All,
+
+    /// A single peer.
+    Peer(Entity),
+
+    /// The server, if this app is a client. Ignored on servers.
+    Server,
+}
+
+/// Sends `event` to other peers. Write this with an [`EventWriter`] to send a networked event
+/// registered with [`add_network_event`](crate::ReplicationAppExt::add_network_event).
+///
+/// Events are sent at the end of the tick, in [`PostUpdate`].
+#[derive(Debug, Clone, Event)]
+pub struct SendNetEvent<E> {
+    /// The event to send.
+    pub event: E,
+
+    /// Who to send the event to.
+    pub target: NetEventTarget,
+}
+
+/// An event received from another peer. Read this with an [`EventReader`] to receive
+/// a networked event registered with [`add_network_event`](crate::ReplicationAppExt::add_network_event).
+///
+/// Events are received in [`NetworkRecv::Synchronise`].
+#[derive(Debug, Clone, Event)]
+pub struct RecvNetEvent<E> {
+    /// The peer entity that sent the event.
+    pub sender: Entity,
+
+    /// The event that was received.
+    pub event: E,
+}
+
+/// The channel used for networked events of type `E`.
+pub struct NetEventChannel<E>(PhantomData<fn() -> E>);
+
+#[derive(Resource)]
+struct NetEventSerialiser<E>(Serialiser<E>);
+
+pub(crate) fn register_network_event<E: Send + Sync + 'static>(
+    app: &mut App,
+    serialiser: Serialiser<E>,
+    channel: ChannelConfiguration,
+) -> &mut App {
+    if app.world().contains_resource::<NetEventSerialiser<E>>() {
+        panic!("A network event was registered twice: {}", type_name::<E>());
+    }
+
+    app.add_channel::<NetEventChannel<E>>(channel);
+    app.insert_resource(NetEventSerialiser(serialiser));
+    app.add_event::<SendNetEvent<E>>();
+    app.add_event::<RecvNetEvent<E>>();
+
+    app.add_systems(PreUpdate, receive_events_system::<E>
+        .in_set(NetworkRecv::Synchronise));
+
+    app.add_systems(PostUpdate, send_events_system::<E>
+        .before(NetworkSend::Prepare));
+
+    return app;
+}
+
+fn send_events_system<E: Send + Sync + 'static>(
+    serialiser: Res<NetEventSerialiser<E>>,
+    channel: ChannelData<NetEventChannel<E>>,
+    role: Option<Res<NetworkRole>>,
+    mut events: EventReader<SendNetEvent<E>>,
+    mut peers: NetBroadcast,
+    mut scratch: Local<BytesMut>,
+) {
+    for event in events.read() {
+        if event.target == NetEventTarget::Server && role.as_deref() == Some(&NetworkRole::Server) { continue }
+
+        serialiser.0.serialise(&event.event, &mut scratch);
+        let message = Message::from_bytes(scratch.split().freeze());
+
+        // Messages that are too large would be dropped before they're sent anyway
+        let _ = match event.target {
+            NetEventTarget::All | NetEventTarget::Server => peers.broadcast(channel.id(), message),
+            NetEventTarget::Peer(peer) => peers.multicast([peer], channel.id(), message),
+        };
+    }
+}
+
+type SendingPeer<'a> = (Entity, &'a PeerMessages<Incoming>, Option<&'a mut PeerReputation>);
+
+fn receive_events_system<E: Send + Sync + 'static>(
+    serialiser: Res<NetEventSerialiser<E>>,
+    channel: ChannelData<NetEventChannel<E>>,
+    mut peers: Query<SendingPeer, With<Peer>>,
+    mut events: EventWriter<RecvNetEvent<E>>,
+) {
+    for (sender, messages, mut reputation) in peers.iter_mut() {
+        for message in messages.iter_channel(channel.id()) {
+            match serialiser.0.deserialise(message.as_slice()) {
+                Some(event) => { events.send(RecvNetEvent { sender, event }); },
+                None => if let Some(reputation) = reputation.as_mut() {
+                    reputation.report(Infraction::MalformedMessage { channel: channel.id() });
+                },
+            }
+        }
+    }
+}
+
+#[test]
+fn network_event_test() {
+    use bevy_ecs::system::RunSystemOnce;
+    use crate::ReplicationAppExt;
+
+    #[derive(Default, Resource)]
+    struct Sent(Vec<(Entity, Vec<u8>)>);
+
+    #[derive(Debug, PartialEq)]
+    struct Chat(String);
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+    app.add_network_event::<Chat>(Serialiser::new(
+        |chat, buf| buf.extend_from_slice(chat.0.as_bytes()),
+        |bytes| Some(Chat(std::str::from_utf8(bytes).ok()?.to_owned())),
+    ), ChannelConfiguration {
+        consistency: MessageConsistency::ReliableOrdered,
+        priority: 0,
+        ..Default::default()
+    });
+
+    app.insert_resource(NetworkRole::Server);
+    app.insert_resource(bevy_stardust::connections::ReputationPolicy::default());
+    app.finish();
+    app.cleanup();
+
+    let channel = app.world_mut().run_system_once(|channel: ChannelData<NetEventChannel<Chat>>| channel.id()).unwrap();
+
+    // Stand in for a transport layer, taking messages before they're cleared
+    app.init_resource::<Sent>();
+    app.add_systems(PostUpdate, (move |mut sent: ResMut<Sent>, query: Query<(Entity, &PeerMessages<Outgoing>)>| {
+        for (peer, messages) in query.iter() {
+            sent.0.extend(messages.iter_channel(channel).map(|message| (peer, message.as_slice().to_vec())));
+        }
+    }).in_set(NetworkSend::Transmit));
+
+    let mut incoming = PeerMessages::<Incoming>::new();
+    incoming.push_channel(channel, [Message::from_static(b"hello"), Message::from_static(&[0xFF])]);
+    let a = app.world_mut().spawn((Peer::new(), PeerLifestage::Established, incoming, PeerMessages::<Outgoing>::new(), PeerReputation::default())).id();
+    let b = app.world_mut().spawn((Peer::new(), PeerLifestage::Established, PeerMessages::<Outgoing>::new())).id();
+
+    // Received events are tagged with their sender, and invalid ones are reported
+    app.world_mut().run_schedule(PreUpdate);
+    let events = app.world().resource::<Events<RecvNetEvent<Chat>>>();
+    let received: Vec<_> = events.iter_current_update_events().map(|event| (event.sender, &event.event)).collect();
+    assert_eq!(received, vec![(a, &Chat("hello".into()))]);
+
+    app.world_mut().send_event(SendNetEvent { event: Chat("all".into()), target: NetEventTarget::All });
+    app.world_mut().send_event(SendNetEvent { event: Chat("one".into()), target: NetEventTarget::Peer(b) });
+    app.world_mut().send_event(SendNetEvent { event: Chat("server".into()), target: NetEventTarget::Server });
+    app.world_mut().run_schedule(PostUpdate);
+
+    // Events for the server are ignored, since this is the server
+    let mut sent = std::mem::take(&mut app.world_mut().resource_mut::<Sent>().0);
+    sent.sort();
+    let mut expected = vec![(a, b"all".to_vec()), (b, b"all".to_vec()), (b, b"one".to_vec())];
+    expected.sort();
+    assert_eq!(sent, expected);
+
+    // Reports are applied in the next tick
+    app.world_mut().run_schedule(PreUpdate);
+    let reputation = app.world().get::<PeerReputation>(a).unwrap();
+    assert_eq!(reputation.history().collect::<Vec<_>>(), vec![&Infraction::MalformedMessage { channel }]);
+}
@@ -0,0 +1,350 @@
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::{AppTypeRegistry, ReflectComponent};
+use bevy_ecs::system::SystemState;
+use bevy_reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy_reflect::{TypeRegistration, TypeRegistry};
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{Infraction, PeerReputation};
+use bytes::BufMut;
+use serde::de::DeserializeSeed;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::plugin::is_server;
+use crate::{ReplicationAppExt, RpcChannel, RpcRequestEvent, RpcResponder, Serialiser};
+
+/// Lets authorised peers inspect and modify reflected components on the server, for admin tooling.
+///
+/// Requests are [`InspectRequest`]s, made by calling the server with an
+/// [`RpcCaller<InspectRequest, InspectResponse>`](crate::RpcCaller). Components are identified
+/// by their type path, and must be registered in the [`AppTypeRegistry`] with [`ReflectComponent`].
+/// Values are sent as JSON, in the same format as reflection-based scenes.
+///
+/// Only peers with the [`InspectAuthorised`] component are answered.
+/// Requests from other peers fail with [`InspectError::Unauthorised`],
+/// and are reported to the peer's [`PeerReputation`], if it has one.
+///
+/// This plugin must be added on both sides, since it registers a procedure
+/// and so must be in the same order relative to other procedures and channels.
+pub struct InspectPlugin {
+    /// The configuration of the channel used for requests and responses. Must be reliable.
+    pub channel: ChannelConfiguration,
+}
+
+impl Default for InspectPlugin {
+    fn default() -> Self {
+        Self {
+            channel: ChannelConfiguration {
+                consistency: MessageConsistency::ReliableOrdered,
+                priority: 0,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Plugin for InspectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_rpc::<InspectRequest, InspectResponse>(
+            Serialiser::new(
+                |request, buf| serde_json::to_writer(buf.writer(), request).unwrap(),
+                |bytes| serde_json::from_slice(bytes).ok(),
+            ),
+            Serialiser::new(
+                |response, buf| serde_json::to_writer(buf.writer(), response).unwrap(),
+                |bytes| serde_json::from_slice(bytes).ok(),
+            ),
+            self.channel.clone(),
+        );
+
+        app.add_systems(Update, inspect_system
+            .run_if(is_server));
+    }
+}
+
+/// Marks a peer as allowed to make [`InspectRequest`]s. See [`InspectPlugin`].
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct InspectAuthorised;
+
+/// A request made to the server's [`InspectPlugin`].
+///
+/// Entities are identified by their [bits](Entity::to_bits) on the server,
+/// and components by their [type path](bevy_reflect::TypePath::type_path).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum InspectRequest {
+    /// Lists the type paths of the reflected components on `entity`,
+    /// or all registered reflected components if `entity` is `None`.
+    /// Responds with an array of strings.
+    List {
+        /// The entity to list the components of.
+        entity: Option<u64>,
+    },
+
+    /// Gets the values of `components` on `entity`.
+    /// Responds with an object mapping type paths to values.
+    Get {
+        /// The entity to get components from.
+        entity: u64,
+        /// The type paths of the components to get.
+        components: Vec<String>,
+    },
+
+    /// Finds all entities with all of `components`.
+    /// Responds with an object mapping entity bits to objects like those returned by [`Get`](Self::Get).
+    Query {
+        /// The type paths of the components to get.
+        components: Vec<String>,
+    },
+
+    /// Inserts or replaces components on `entity`.
+    /// Responds with `null`.
+    Insert {
+        /// The entity to insert components on.
+        entity: u64,
+        /// The values of the components to insert, keyed by type path.
+        components: Map<String, Value>,
+    },
+
+    /// Removes `components` from `entity`.
+    /// Responds with `null`.
+    Remove {
+        /// The entity to remove components from.
+        entity: u64,
+        /// The type paths of the components to remove.
+        components: Vec<String>,
+    },
+}
+
+/// The server's response to an [`InspectRequest`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InspectResponse(pub Result<Value, InspectError>);
+
+/// Why an [`InspectRequest`] failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum InspectError {
+    /// The peer doesn't have [`InspectAuthorised`].
+    Unauthorised,
+
+    /// The entity doesn't exist.
+    NoSuchEntity(u64),
+
+    /// The type path isn't registered as a reflected component.
+    UnknownComponent(String),
+
+    /// The entity doesn't have the component.
+    MissingComponent(String),
+
+    /// The value couldn't be converted to or from the component.
+    InvalidValue {
+        /// The type path of the component.
+        component: String,
+        /// What went wrong.
+        message: String,
+    },
+}
+
+type RequestReader<'w, 's> = EventReader<'w, 's, RpcRequestEvent<InspectRequest, InspectResponse>>;
+type Responder<'w> = RpcResponder<'w, InspectRequest, InspectResponse>;
+
+fn inspect_system(
+    world: &mut World,
+    reader: &mut SystemState<(RequestReader, ChannelData<RpcChannel<InspectRequest, InspectResponse>>)>,
+    responder: &mut SystemState<Responder>,
+) {
+    let (mut requests, channel) = reader.get_mut(world);
+    let channel = channel.id();
+    let requests: Vec<_> = requests.read().cloned().collect();
+    if requests.is_empty() { return }
+
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+
+    for request in requests {
+        let response = match world.get::<InspectAuthorised>(request.peer) {
+            Some(_) => handle(world, &registry, &request.request),
+            None => {
+                if let Some(mut reputation) = world.get_mut::<PeerReputation>(request.peer) {
+                    reputation.report(Infraction::ForbiddenChannel { channel });
+                }
+
+                Err(InspectError::Unauthorised)
+            },
+        };
+
+        responder.get_mut(world).respond(&request, InspectResponse(response));
+    }
+}
+
+fn handle(world: &mut World, registry: &TypeRegistry, request: &InspectRequest) -> Result<Value, InspectError> {
+    match request {
+        InspectRequest::List { entity: None } => {
+            let paths = registry.iter()
+                .filter(|registration| registration.data::<ReflectComponent>().is_some())
+                .map(|registration| Value::from(registration.type_info().type_path()));
+
+            Ok(Value::Array(paths.collect()))
+        },
+
+        InspectRequest::List { entity: Some(bits) } => {
+            let entity = world.get_entity(entity(*bits)?).map_err(|_| InspectError::NoSuchEntity(*bits))?;
+            let paths = registry.iter()
+                .filter(|registration| registration.data::<ReflectComponent>().is_some_and(|reflect| reflect.contains(entity)))
+                .map(|registration| Value::from(registration.type_info().type_path()));
+
+            Ok(Value::Array(paths.collect()))
+        },
+
+        InspectRequest::Get { entity: bits, components } => {
+            let entity = world.get_entity(entity(*bits)?).map_err(|_| InspectError::NoSuchEntity(*bits))?;
+            let mut values = Map::new();
+            for path in components {
+                let (_, reflect) = component(registry, path)?;
+                let value = reflect.reflect(entity).ok_or_else(|| InspectError::MissingComponent(path.clone()))?;
+                values.insert(path.clone(), serialise(registry, path, value.as_partial_reflect())?);
+            }
+
+            Ok(Value::Object(values))
+        },
+
+        InspectRequest::Query { components } => {
+            let reflects = components.iter()
+                .map(|path| component(registry, path).map(|(_, reflect)| (path, reflect)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut entities = Map::new();
+            for entity in world.iter_entities() {
+                if !reflects.iter().all(|(_, reflect)| reflect.contains(entity)) { continue }
+
+                let mut values = Map::new();
+                for (path, reflect) in reflects.iter() {
+                    let value = reflect.reflect(entity).unwrap();
+                    values.insert((*path).clone(), serialise(registry, path, value.as_partial_reflect())?);
+                }
+
+                entities.insert(entity.id().to_bits().to_string(), Value::Object(values));
+            }
+
+            Ok(Value::Object(entities))
+        },
+
+        InspectRequest::Insert { entity: bits, components } => {
+            // Deserialise everything first, so that nothing is inserted if any value is invalid
+            let values = components.iter().map(|(path, value)| {
+                let (registration, reflect) = component(registry, path)?;
+                let value = TypedReflectDeserializer::new(registration, registry)
+                    .deserialize(value.clone())
+                    .map_err(|err| InspectError::InvalidValue { component: path.clone(), message: err.to_string() })?;
+                Ok((reflect, value))
+            }).collect::<Result<Vec<_>, _>>()?;
+
+            let mut entity = world.get_entity_mut(entity(*bits)?).map_err(|_| InspectError::NoSuchEntity(*bits))?;
+            for (reflect, value) in values {
+                reflect.insert(&mut entity, value.as_ref(), registry);
+            }
+
+            Ok(Value::Null)
+        },
+
+        InspectRequest::Remove { entity: bits, components } => {
+            let reflects = components.iter()
+                .map(|path| component(registry, path).map(|(_, reflect)| reflect))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut entity = world.get_entity_mut(entity(*bits)?).map_err(|_| InspectError::NoSuchEntity(*bits))?;
+            for reflect in reflects {
+                reflect.remove(&mut entity);
+            }
+
+            Ok(Value::Null)
+        },
+    }
+}
+
+fn entity(bits: u64) -> Result<Entity, InspectError> {
+    Entity::try_from_bits(bits).map_err(|_| InspectError::NoSuchEntity(bits))
+}
+
+fn component<'a>(registry: &'a TypeRegistry, path: &str) -> Result<(&'a TypeRegistration, &'a ReflectComponent), InspectError> {
+    registry.get_with_type_path(path)
+        .and_then(|registration| Some((registration, registration.data::<ReflectComponent>()?)))
+        .ok_or_else(|| InspectError::UnknownComponent(path.to_owned()))
+}
+
+fn serialise(registry: &TypeRegistry, path: &str, value: &dyn bevy_reflect::PartialReflect) -> Result<Value, InspectError> {
+    serde_json::to_value(TypedReflectSerializer::new(value, registry))
+        .map_err(|err| InspectError::InvalidValue { component: path.to_owned(), message: err.to_string() })
+}
+
+#[test]
+fn inspect_test() {
+    use std::time::Duration;
+    use bevy_ecs::system::RunSystemOnce;
+    use bevy_reflect::{Reflect, TypePath};
+    use bevy_stardust::connections::NetworkRole;
+    use crate::{RpcCaller, RpcCallId};
+
+    #[derive(Debug, PartialEq, Component, Reflect)]
+    #[reflect(Component)]
+    struct Health { value: u32 }
+
+    #[derive(Default, Resource)]
+    struct Sent(Vec<(Entity, Message)>);
+
+    let mut app = App::new();
+    app.add_plugins((StardustPlugin, InspectPlugin::default()));
+    app.register_type::<Health>();
+    app.insert_resource(NetworkRole::Server);
+    app.finish();
+    app.cleanup();
+
+    let channel = app.world_mut().run_system_once(|channel: ChannelData<RpcChannel<InspectRequest, InspectResponse>>| channel.id()).unwrap();
+
+    // Stand in for a transport layer, taking messages before they're cleared
+    app.init_resource::<Sent>();
+    app.add_systems(PostUpdate, (move |mut sent: ResMut<Sent>, query: Query<(Entity, &PeerMessages<Outgoing>)>| {
+        for (peer, messages) in query.iter() {
+            sent.0.extend(messages.iter_channel(channel).map(|message| (peer, message)));
+        }
+    }).in_set(NetworkSend::Transmit));
+
+    // Loops messages back to the peer they were sent to, so the app answers its own requests
+    let loopback = |app: &mut App| {
+        app.update();
+        for (peer, message) in std::mem::take(&mut app.world_mut().resource_mut::<Sent>().0) {
+            app.world_mut().get_mut::<PeerMessages<Incoming>>(peer).unwrap().push_channel(channel, [message]);
+        }
+    };
+
+    let peer = (Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new());
+    let admin = app.world_mut().spawn((peer, InspectAuthorised)).id();
+    let peer = (Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new());
+    let player = app.world_mut().spawn(peer).id();
+    let target = app.world_mut().spawn(Health { value: 5 }).id();
+
+    let path = Health::type_path().to_owned();
+    let call = |app: &mut App, peer: Entity, request: InspectRequest| app.world_mut().run_system_once(move |mut caller: RpcCaller<InspectRequest, InspectResponse>| {
+        caller.call(peer, request.clone(), Duration::from_secs(60))
+    }).unwrap();
+
+    let get = call(&mut app, admin, InspectRequest::Get { entity: target.to_bits(), components: vec![path.clone()] });
+    let insert = call(&mut app, admin, InspectRequest::Insert { entity: target.to_bits(), components: [(path.clone(), serde_json::json!({ "value": 7 }))].into_iter().collect() });
+    let unknown = call(&mut app, admin, InspectRequest::Remove { entity: target.to_bits(), components: vec!["Unknown".into()] });
+    let denied = call(&mut app, player, InspectRequest::List { entity: None });
+
+    // Requests are sent, answered, and the responses received
+    loopback(&mut app);
+    loopback(&mut app);
+    loopback(&mut app);
+
+    let mut poll = |call: RpcCallId| app.world_mut().run_system_once(move |mut caller: RpcCaller<InspectRequest, InspectResponse>| caller.poll(call)).unwrap();
+    let mut expected = Map::new();
+    expected.insert(path.clone(), serde_json::json!({ "value": 5 }));
+    assert_eq!(poll(get), Some(Ok(InspectResponse(Ok(Value::Object(expected))))));
+    assert_eq!(poll(insert), Some(Ok(InspectResponse(Ok(Value::Null)))));
+    assert_eq!(poll(unknown), Some(Ok(InspectResponse(Err(InspectError::UnknownComponent("Unknown".into()))))));
+    assert_eq!(poll(denied), Some(Ok(InspectResponse(Err(InspectError::Unauthorised)))));
+    assert_eq!(app.world().get::<Health>(target), Some(&Health { value: 7 }));
+}
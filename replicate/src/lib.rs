@@ -0,0 +1,33 @@
+#![doc = include_str!("../README.md")]
+#![warn(missing_docs)]
+
+mod authority;
+mod entities;
+mod events;
+#[cfg(feature="reflect")]
+mod inspect;
+mod plugin;
+mod protocol;
+mod receive;
+mod registry;
+mod relevancy;
+mod resources;
+mod rpc;
+mod send;
+mod serialise;
+mod snapshot;
+
+pub mod prelude;
+
+pub use authority::{AuthorityPlugin, AuthorityChannel, NetworkOwner, HasAuthority, AuthorityRequestedEvent, RequestAuthorityEvent, ReleaseAuthorityEvent};
+pub use entities::{Replicated, NetworkId, NetworkEntityMap};
+#[cfg(feature="reflect")]
+pub use inspect::{InspectPlugin, InspectAuthorised, InspectRequest, InspectResponse, InspectError};
+pub use events::{SendNetEvent, RecvNetEvent, NetEventTarget, NetEventChannel};
+pub use plugin::{ReplicationPlugin, ReplicationChannel};
+pub use registry::ReplicationAppExt;
+pub use relevancy::{RelevancyPlugin, NetworkRoom, NetworkRooms, NetworkCell, PeerVisibility, EntityEnteredViewEvent, EntityLeftViewEvent};
+pub use resources::{ResourceReplicationPlugin, ResourceChannel};
+pub use rpc::{RpcCaller, RpcResponder, RpcCallId, RpcError, RpcRequestEvent, RpcCompletedEvent, RpcChannel};
+pub use serialise::Serialiser;
+pub use snapshot::{SnapshotPlugin, SnapshotChannel};
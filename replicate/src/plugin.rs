@@ -0,0 +1,194 @@
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::NetworkRole;
+use crate::entities::add_entity_map_system;
+use crate::receive::{apply_system, receive_system, PendingReplication};
+use crate::registry::ReplicationRegistry;
+use crate::send::{entities_system, prepare_system, send_system, ReplicationBuffer};
+
+/// Replicates entities from the server to its clients.
+///
+/// On the server, entities with [`Replicated`](crate::Replicated) are spawned on all
+/// [`Established`] peers, and changes to their registered components are sent every tick,
+/// in [`PostUpdate`]. Peers that become established are sent the entire replicated state.
+/// On clients, received changes are applied in [`NetworkRecv::Synchronise`].
+///
+/// Entities are identified by [`NetworkId`](crate::NetworkId)s, which are allocated separately
+/// for every peer by the [`NetworkEntityMap`](crate::NetworkEntityMap) added to its entity.
+///
+/// Which side the app is on is decided by the [`NetworkRole`] resource.
+/// If it doesn't exist, nothing is replicated.
+pub struct ReplicationPlugin {
+    /// The configuration of [`ReplicationChannel`].
+    ///
+    /// The channel must be reliable and ordered, or replicated state will be lost,
+    /// but other settings such as compression can be changed.
+    pub channel: ChannelConfiguration,
+}
+
+impl Default for ReplicationPlugin {
+    fn default() -> Self {
+        Self {
+            channel: ChannelConfiguration {
+                consistency: MessageConsistency::ReliableOrdered,
+                priority: 0,
+                direction: ChannelDirection::ServerToClient,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        let channel = app.add_channel::<ReplicationChannel>(self.channel.clone());
+        app.insert_resource(ReplicationChannelId(channel));
+
+        app.init_resource::<ReplicationRegistry>();
+        app.init_resource::<ReplicationBuffer>();
+        app.init_resource::<PendingReplication>();
+
+        app.configure_sets(PostUpdate, (
+            ReplicationSystems::Relevancy,
+            ReplicationSystems::Prepare,
+            ReplicationSystems::Collect,
+            ReplicationSystems::Send,
+            ReplicationSystems::Snapshot,
+        ).chain().run_if(is_server).before(NetworkSend::Prepare));
+
+        app.add_systems(PostUpdate, (prepare_system, entities_system)
+            .chain().in_set(ReplicationSystems::Prepare));
+
+        app.add_systems(PostUpdate, send_system
+            .in_set(ReplicationSystems::Send));
+
+        app.add_systems(PreUpdate, add_entity_map_system
+            .before(apply_system)
+            .in_set(NetworkRecv::Synchronise));
+
+        app.add_systems(PreUpdate, (receive_system, apply_system)
+            .chain().run_if(is_client).in_set(NetworkRecv::Synchronise));
+    }
+}
+
+/// The channel used for replication messages.
+pub struct ReplicationChannel;
+
+/// The id of [`ReplicationChannel`], for use in exclusive systems.
+#[derive(Resource)]
+pub(crate) struct ReplicationChannelId(pub ChannelId);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub(crate) enum ReplicationSystems {
+    Relevancy,
+    Prepare,
+    Collect,
+    Send,
+    Snapshot,
+}
+
+pub(crate) fn is_server(role: Option<Res<NetworkRole>>) -> bool {
+    role.is_some_and(|role| *role == NetworkRole::Server)
+}
+
+pub(crate) fn is_client(role: Option<Res<NetworkRole>>) -> bool {
+    role.is_some_and(|role| *role == NetworkRole::Client)
+}
+
+#[test]
+fn replication_test() {
+    use bevy_stardust::connections::PeerMessages;
+    use crate::prelude::*;
+
+    use bevy_ecs::entity::{EntityMapper, MapEntities};
+
+    #[derive(Debug, PartialEq, Component)]
+    struct Health(u32);
+
+    #[derive(Debug, Clone, PartialEq, Component)]
+    struct Target(Entity);
+
+    impl MapEntities for Target {
+        fn map_entities<M: EntityMapper>(&mut self, mapper: &mut M) {
+            self.0 = mapper.map_entity(self.0);
+        }
+    }
+
+    fn app(role: NetworkRole) -> App {
+        let mut app = App::new();
+        app.add_plugins((StardustPlugin, ReplicationPlugin::default()));
+        app.replicate_component::<Health>(Serialiser::new(
+            |health, buf| buf.extend_from_slice(&health.0.to_le_bytes()),
+            |bytes| Some(Health(u32::from_le_bytes(bytes.try_into().ok()?))),
+        ));
+
+        app.replicate_mapped_component::<Target>(Serialiser::new(
+            |target, buf| buf.extend_from_slice(&target.0.to_bits().to_le_bytes()),
+            |bytes| Some(Target(Entity::from_bits(u64::from_le_bytes(bytes.try_into().ok()?)))),
+        ));
+
+        app.insert_resource(role);
+        app.finish();
+        app.cleanup();
+        app
+    }
+
+    #[derive(Default, Resource)]
+    struct Sent(Vec<ChannelMessage>);
+
+    let mut server = app(NetworkRole::Server);
+    let mut client = app(NetworkRole::Client);
+
+    // Stand in for a transport layer, taking messages before they're cleared
+    server.init_resource::<Sent>();
+    server.add_systems(PostUpdate, (|mut sent: ResMut<Sent>, query: Query<&PeerMessages<Outgoing>>| {
+        for messages in query.iter() {
+            for (channel, iter) in messages.iter() {
+                sent.0.extend(iter.map(|message| ChannelMessage { channel, message }));
+            }
+        }
+    }).in_set(NetworkSend::Transmit));
+
+    server.world_mut().spawn((Peer::new(), PeerLifestage::Established, PeerMessages::<Outgoing>::new()));
+    let server_peer = client.world_mut().spawn((Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new())).id();
+
+    let transfer = |server: &mut App, client: &mut App| {
+        server.update();
+        let sent = std::mem::take(&mut server.world_mut().resource_mut::<Sent>().0);
+        client.world_mut().get_mut::<PeerMessages<Incoming>>(server_peer).unwrap().push_many(sent);
+        client.update();
+    };
+
+    let entity = server.world_mut().spawn((Health(10), Replicated)).id();
+    transfer(&mut server, &mut client);
+
+    let mut query = client.world_mut().query::<(&NetworkId, &Health)>();
+    let mirrored: Vec<_> = query.iter(client.world()).collect();
+    assert_eq!(mirrored.len(), 1);
+    assert_eq!(mirrored[0].1, &Health(10));
+
+    server.world_mut().get_mut::<Health>(entity).unwrap().0 = 5;
+    transfer(&mut server, &mut client);
+    assert_eq!(query.single(client.world()).1, &Health(5));
+
+    // Entities in mapped components refer to the client's mirrors
+    let targeting = server.world_mut().spawn((Target(entity), Replicated)).id();
+    transfer(&mut server, &mut client);
+    let mirror = *query.single(client.world()).0;
+    let target = client.world_mut().query::<&Target>().single(client.world()).0;
+    let map = client.world().get::<NetworkEntityMap>(server_peer).unwrap();
+    assert_eq!(map.remote_id(target), Some(mirror));
+
+    server.world_mut().despawn(entity);
+    transfer(&mut server, &mut client);
+    assert!(query.iter(client.world()).next().is_none());
+
+    // The freed id is reused with a new generation, so the stale reference isn't resolved
+    server.world_mut().entity_mut(targeting).insert(Target(targeting));
+    server.world_mut().spawn((Health(1), Replicated));
+    transfer(&mut server, &mut client);
+    let new_id = *query.single(client.world()).0;
+    assert_eq!(new_id.index(), mirror.index());
+    assert_ne!(new_id.generation(), mirror.generation());
+}
@@ -0,0 +1,7 @@
+//! Common imports for using replication.
+
+pub use crate::{ReplicationPlugin, SnapshotPlugin, ReplicationAppExt, Replicated, NetworkId, NetworkEntityMap, Serialiser};
+pub use crate::{ResourceReplicationPlugin, RelevancyPlugin, NetworkRoom, NetworkRooms, NetworkCell, PeerVisibility};
+pub use crate::{SendNetEvent, RecvNetEvent, NetEventTarget};
+pub use crate::{RpcCaller, RpcResponder, RpcCallId, RpcError, RpcRequestEvent, RpcCompletedEvent};
+pub use crate::{AuthorityPlugin, NetworkOwner, HasAuthority, AuthorityRequestedEvent, RequestAuthorityEvent, ReleaseAuthorityEvent};
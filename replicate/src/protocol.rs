@@ -0,0 +1,152 @@
+//! The format of replication messages.
+//!
+//! Each message is a list of despawns, spawns, component updates, and component removals,
+//! in that order, so that components are never applied to an entity that hasn't been spawned yet,
+//! and indices of despawned entities can be reused by spawns in the same message.
+//! All integers are encoded as [`VarInt`]s, [`NetworkId`]s are encoded as their index and generation,
+//! and component data is prefixed with its length.
+
+use bytes::{Buf, Bytes, BytesMut};
+use bevy_stardust_extras::numbers::VarInt;
+use crate::NetworkId;
+
+#[derive(Default)]
+pub(crate) struct ReplicationOps {
+    pub despawns: Vec<NetworkId>,
+    pub spawns: Vec<NetworkId>,
+    pub updates: Vec<ComponentUpdate>,
+    pub removals: Vec<(NetworkId, u32)>,
+}
+
+#[derive(Clone)]
+pub(crate) struct ComponentUpdate {
+    pub entity: NetworkId,
+    pub component: u32,
+    pub data: Bytes,
+}
+
+impl ReplicationOps {
+    pub fn is_empty(&self) -> bool {
+        self.spawns.is_empty() && self.updates.is_empty() && self.removals.is_empty() && self.despawns.is_empty()
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let data: usize = self.updates.iter().map(|u| u.data.len()).sum();
+        let mut buf = BytesMut::with_capacity(data + 4 * (self.despawns.len() + self.spawns.len() + self.updates.len() * 4 + self.removals.len() * 3) + 4);
+
+        put(&mut buf, self.despawns.len() as u64);
+        for id in &self.despawns { put_id(&mut buf, *id) }
+
+        put(&mut buf, self.spawns.len() as u64);
+        for id in &self.spawns { put_id(&mut buf, *id) }
+
+        put(&mut buf, self.updates.len() as u64);
+        for update in &self.updates {
+            put_id(&mut buf, update.entity);
+            put(&mut buf, update.component as u64);
+            put(&mut buf, update.data.len() as u64);
+            buf.extend_from_slice(&update.data);
+        }
+
+        put(&mut buf, self.removals.len() as u64);
+        for (id, component) in &self.removals {
+            put_id(&mut buf, *id);
+            put(&mut buf, *component as u64);
+        }
+
+        return buf.freeze();
+    }
+
+    pub fn decode(mut buf: Bytes) -> Result<Self, ()> {
+        let mut ops = Self::default();
+
+        // Counts aren't used to preallocate, since they can't be trusted
+        for _ in 0..get(&mut buf)? {
+            ops.despawns.push(get_id(&mut buf)?);
+        }
+
+        for _ in 0..get(&mut buf)? {
+            ops.spawns.push(get_id(&mut buf)?);
+        }
+
+        for _ in 0..get(&mut buf)? {
+            let entity = get_id(&mut buf)?;
+            let component = get_u32(&mut buf)?;
+            let len = get(&mut buf)?;
+            if (buf.remaining() as u64) < len { return Err(()) }
+            let data = buf.split_to(len as usize);
+            ops.updates.push(ComponentUpdate { entity, component, data });
+        }
+
+        for _ in 0..get(&mut buf)? {
+            ops.removals.push((get_id(&mut buf)?, get_u32(&mut buf)?));
+        }
+
+        if buf.has_remaining() { return Err(()) }
+        return Ok(ops);
+    }
+}
+
+pub(crate) fn put(buf: &mut BytesMut, value: u64) {
+    // BytesMut grows as needed, and all values are far below VarInt::MAX
+    VarInt::try_from(value).unwrap().write(buf).unwrap();
+}
+
+pub(crate) fn get(buf: &mut Bytes) -> Result<u64, ()> {
+    VarInt::read(buf).map(u64::from)
+}
+
+pub(crate) fn get_u32(buf: &mut Bytes) -> Result<u32, ()> {
+    VarInt::read(buf).and_then(u32::try_from)
+}
+
+pub(crate) fn put_id(buf: &mut BytesMut, id: NetworkId) {
+    put(buf, id.index() as u64);
+    put(buf, id.generation() as u64);
+}
+
+pub(crate) fn get_id(buf: &mut Bytes) -> Result<NetworkId, ()> {
+    NetworkId::new(get_u32(buf)?, get_u32(buf)?).ok_or(())
+}
+
+/// Unchanged gaps shorter than this are included in a run, since a new run would cost more.
+const MERGE_GAP: usize = 3;
+
+/// Encodes the bytes of `new` that differ from `old`, which must be the same length.
+pub(crate) fn patch(old: &[u8], new: &[u8]) -> BytesMut {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for index in (0..new.len()).filter(|index| old[*index] != new[*index]) {
+        match runs.last_mut() {
+            Some((_, end)) if index - *end < MERGE_GAP => *end = index + 1,
+            _ => runs.push((index, index + 1)),
+        }
+    }
+
+    let mut buf = BytesMut::new();
+    put(&mut buf, runs.len() as u64);
+    let mut offset = 0;
+    for (start, end) in runs {
+        put(&mut buf, (start - offset) as u64);
+        put(&mut buf, (end - start) as u64);
+        buf.extend_from_slice(&new[start..end]);
+        offset = end;
+    }
+
+    return buf;
+}
+
+/// Applies a patch made by [`patch`] from the front of `buf` to `data`.
+pub(crate) fn apply_patch(data: &mut [u8], buf: &mut Bytes) -> Result<(), ()> {
+    let mut offset = 0usize;
+
+    for _ in 0..get(buf)? {
+        let start = offset.checked_add(get(buf)? as usize).ok_or(())?;
+        let len = get(buf)? as usize;
+        let end = start.checked_add(len).ok_or(())?;
+        if end > data.len() || buf.remaining() < len { return Err(()) }
+        data[start..end].copy_from_slice(&buf.split_to(len));
+        offset = end;
+    }
+
+    return Ok(());
+}
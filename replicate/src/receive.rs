@@ -0,0 +1,97 @@
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{Infraction, PeerReputation};
+use bytes::Bytes;
+use crate::authority::HasAuthority;
+use crate::entities::NetworkEntityMap;
+use crate::plugin::{ReplicationChannel, ReplicationChannelId};
+use crate::protocol::ReplicationOps;
+use crate::registry::ReplicationRegistry;
+
+/// Replication messages that have been received, but not applied yet.
+#[derive(Default, Resource)]
+pub(crate) struct PendingReplication(Vec<(Entity, ReplicationOps)>);
+
+type SendingPeer<'a> = (Entity, &'a PeerMessages<Incoming>, Option<&'a mut PeerReputation>);
+
+pub(crate) fn receive_system(
+    channel: ChannelData<ReplicationChannel>,
+    mut pending: ResMut<PendingReplication>,
+    mut peers: Query<SendingPeer, With<Peer>>,
+) {
+    for (entity, messages, mut reputation) in peers.iter_mut() {
+        for message in messages.iter_channel(channel.id()) {
+            match ReplicationOps::decode(Bytes::from(message)) {
+                Ok(ops) => pending.0.push((entity, ops)),
+                Err(()) => if let Some(reputation) = reputation.as_mut() {
+                    reputation.report(Infraction::MalformedMessage { channel: channel.id() });
+                },
+            }
+        }
+    }
+}
+
+pub(crate) fn apply_system(world: &mut World) {
+    let pending = std::mem::take(&mut world.resource_mut::<PendingReplication>().0);
+    if pending.is_empty() { return }
+
+    let channel = world.resource::<ReplicationChannelId>().0;
+    world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+        for (peer, ops) in pending {
+            // The peer may have been despawned since its messages were received
+            let Ok(mut peer_entity) = world.get_entity_mut(peer) else { continue };
+            let mut map = peer_entity.take::<NetworkEntityMap>().unwrap_or_default();
+
+            if apply_ops(world, &registry, &mut map, ops).is_err() {
+                if let Some(mut reputation) = world.get_mut::<PeerReputation>(peer) {
+                    reputation.report(Infraction::MalformedMessage { channel });
+                }
+            }
+
+            world.entity_mut(peer).insert(map);
+        }
+    });
+}
+
+/// Applies `ops` to the world, stopping at the first invalid operation.
+fn apply_ops(
+    world: &mut World,
+    registry: &ReplicationRegistry,
+    map: &mut NetworkEntityMap,
+    ops: ReplicationOps,
+) -> Result<(), ()> {
+    for id in ops.despawns {
+        let entity = map.remove_remote(id).ok_or(())?;
+
+        // The entity may have been despawned locally
+        if let Ok(entity) = world.get_entity_mut(entity) { entity.despawn() }
+    }
+
+    for id in ops.spawns {
+        let entity = world.spawn(id).id();
+        if map.insert_remote(id, entity).is_err() {
+            world.despawn(entity);
+            return Err(());
+        }
+    }
+
+    for update in ops.updates {
+        let entity = map.entity(update.entity).ok_or(())?;
+        let registration = registry.get(update.component).ok_or(())?;
+        let Ok(mut entity) = world.get_entity_mut(entity) else { continue };
+
+        // Components the client sends itself are only changed locally
+        if registration.owned && entity.contains::<HasAuthority>() { continue }
+        if !(registration.insert)(&mut entity, &update.data, map) { return Err(()) }
+    }
+
+    for (id, component) in ops.removals {
+        let entity = map.entity(id).ok_or(())?;
+        let registration = registry.get(component).ok_or(())?;
+        let Ok(mut entity) = world.get_entity_mut(entity) else { continue };
+        if registration.owned && entity.contains::<HasAuthority>() { continue }
+        (registration.remove)(&mut entity);
+    }
+
+    return Ok(());
+}
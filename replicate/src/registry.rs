@@ -0,0 +1,199 @@
+use std::any::type_name;
+use std::sync::Arc;
+use bevy_app::prelude::*;
+use bevy_ecs::entity::MapEntities;
+use bevy_ecs::prelude::*;
+use bevy_ecs::world::EntityWorldMut;
+use bytes::BytesMut;
+use bevy_stardust::prelude::ChannelConfiguration;
+use crate::authority::{client_send_system, collect_owned_component_system};
+use crate::entities::NetworkEntityMap;
+use crate::events::register_network_event;
+use crate::plugin::{is_client, ReplicationSystems};
+use crate::resources::register_resource;
+use crate::rpc::register_rpc;
+use crate::send::{collect_component_system, MappedSerialise};
+use crate::snapshot::{register_snapshot_component, SnapshotSerialiser};
+use crate::Serialiser;
+
+/// Components registered for replication, in the order they were registered.
+#[derive(Default, Resource)]
+pub(crate) struct ReplicationRegistry {
+    components: Vec<ComponentRegistration>,
+}
+
+pub(crate) struct ComponentRegistration {
+    pub insert: fn(&mut EntityWorldMut, &[u8], &NetworkEntityMap) -> bool,
+    pub remove: fn(&mut EntityWorldMut),
+
+    /// Whether clients with authority over an entity send changes to this component.
+    pub owned: bool,
+}
+
+impl ReplicationRegistry {
+    pub fn get(&self, index: u32) -> Option<&ComponentRegistration> {
+        self.components.get(index as usize)
+    }
+}
+
+#[derive(Resource)]
+pub(crate) struct ComponentSerialiser<C> {
+    pub index: u32,
+    pub serialiser: Serialiser<C>,
+
+    /// Set if the component refers to other entities, which must be mapped for each peer.
+    pub mapped: Option<fn(&C, Serialiser<C>) -> MappedSerialise>,
+}
+
+fn insert_component<C: Component>(entity: &mut EntityWorldMut, bytes: &[u8], _map: &NetworkEntityMap) -> bool {
+    let serialiser = entity.world().resource::<ComponentSerialiser<C>>().serialiser;
+    let Some(component) = serialiser.deserialise(bytes) else { return false };
+    entity.insert(component);
+    return true;
+}
+
+fn insert_mapped_component<C: Component + MapEntities>(entity: &mut EntityWorldMut, bytes: &[u8], map: &NetworkEntityMap) -> bool {
+    let serialiser = entity.world().resource::<ComponentSerialiser<C>>().serialiser;
+    let Some(mut component) = serialiser.deserialise(bytes) else { return false };
+    component.map_entities(&mut map.remote_mapper());
+    entity.insert(component);
+    return true;
+}
+
+pub(crate) fn remove_component<C: Component>(entity: &mut EntityWorldMut) {
+    entity.remove::<C>();
+}
+
+fn map_component<C: Component + MapEntities + Clone>(value: &C, serialiser: Serialiser<C>) -> MappedSerialise {
+    let value = value.clone();
+    Arc::new(move |map| {
+        let mut value = value.clone();
+        value.map_entities(&mut map.local_mapper());
+        let mut buf = BytesMut::new();
+        serialiser.serialise(&value, &mut buf);
+        buf.freeze()
+    })
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for bevy_app::App {}
+}
+
+/// Adds replication-related functions to the `App`.
+pub trait ReplicationAppExt: sealed::Sealed {
+    /// Registers component `C` for replication, using `serialiser` to convert it to and from bytes.
+    ///
+    /// Components are identified by the order they're registered in,
+    /// so they must be registered in the same order on the server and its clients.
+    fn replicate_component<C: Component>(&mut self, serialiser: Serialiser<C>) -> &mut Self;
+
+    /// Registers component `C` for replication, like [`replicate_component`](Self::replicate_component),
+    /// but for components that refer to other entities.
+    ///
+    /// Entities in the component are mapped to their ids with [`MapEntities`] before it's serialised,
+    /// and back to local entities after it's deserialised, using the peer's [`NetworkEntityMap`].
+    /// Since ids are different for every peer, the component is serialised separately for each one.
+    fn replicate_mapped_component<C: Component + MapEntities + Clone>(&mut self, serialiser: Serialiser<C>) -> &mut Self;
+
+    /// Registers component `C` to be replicated with snapshots, using `serialiser` to convert it to and from bytes.
+    /// Requires the [`SnapshotPlugin`](crate::SnapshotPlugin).
+    ///
+    /// Unlike [`replicate_component`](Self::replicate_component), changes may be lost,
+    /// but are superseded by newer snapshots. This suits components that change every tick,
+    /// like positions. Components are identified by the order they're registered in, separately
+    /// from other replicated components, so they must be registered in the same order on both sides.
+    fn snapshot_component<C: Component>(&mut self, serialiser: Serialiser<C>) -> &mut Self;
+
+    /// Registers resource `R` to be mirrored from the server to its clients, using `serialiser` to convert it
+    /// to and from bytes. Requires the [`ResourceReplicationPlugin`](crate::ResourceReplicationPlugin).
+    ///
+    /// Resources are identified by the order they're registered in, so they must be registered
+    /// in the same order on the server and its clients.
+    fn replicate_resource<R: Resource>(&mut self, serialiser: Serialiser<R>) -> &mut Self;
+
+    /// Registers `E` as a networked event, sent over its own channel configured with `channel`.
+    ///
+    /// Events written as [`SendNetEvent<E>`](crate::SendNetEvent) are serialised with `serialiser`
+    /// and sent to their target, and events received from other peers are written as
+    /// [`RecvNetEvent<E>`](crate::RecvNetEvent). Like channels, networked events must be
+    /// registered in the same order on both sides.
+    fn add_network_event<E: Send + Sync + 'static>(&mut self, serialiser: Serialiser<E>, channel: ChannelConfiguration) -> &mut Self;
+
+    /// Registers a procedure taking `Req` and returning `Resp`, sent over its own channel configured with `channel`.
+    ///
+    /// Calls are made with [`RpcCaller`](crate::RpcCaller), and requests from other peers are written as
+    /// [`RpcRequestEvent`](crate::RpcRequestEvent)s, to be answered with [`RpcResponder`](crate::RpcResponder).
+    /// Like channels, procedures must be registered in the same order on both sides.
+    ///
+    /// Panics if `channel` isn't reliable, since lost requests and responses are never resent.
+    fn add_rpc<Req, Resp>(&mut self, request: Serialiser<Req>, response: Serialiser<Resp>, channel: ChannelConfiguration) -> &mut Self
+    where
+        Req: Send + Sync + 'static,
+        Resp: Send + Sync + 'static;
+}
+
+impl ReplicationAppExt for App {
+    fn replicate_component<C: Component>(&mut self, serialiser: Serialiser<C>) -> &mut Self {
+        register::<C>(self, serialiser, insert_component::<C>, None)
+    }
+
+    fn replicate_mapped_component<C: Component + MapEntities + Clone>(&mut self, serialiser: Serialiser<C>) -> &mut Self {
+        register::<C>(self, serialiser, insert_mapped_component::<C>, Some(map_component::<C>))
+    }
+
+    fn snapshot_component<C: Component>(&mut self, serialiser: Serialiser<C>) -> &mut Self {
+        register_snapshot_component::<C>(self, serialiser)
+    }
+
+    fn replicate_resource<R: Resource>(&mut self, serialiser: Serialiser<R>) -> &mut Self {
+        register_resource::<R>(self, serialiser)
+    }
+
+    fn add_network_event<E: Send + Sync + 'static>(&mut self, serialiser: Serialiser<E>, channel: ChannelConfiguration) -> &mut Self {
+        register_network_event::<E>(self, serialiser, channel)
+    }
+
+    fn add_rpc<Req, Resp>(&mut self, request: Serialiser<Req>, response: Serialiser<Resp>, channel: ChannelConfiguration) -> &mut Self
+    where
+        Req: Send + Sync + 'static,
+        Resp: Send + Sync + 'static,
+    {
+        register_rpc::<Req, Resp>(self, request, response, channel)
+    }
+}
+
+fn register<C: Component>(
+    app: &mut App,
+    serialiser: Serialiser<C>,
+    insert: fn(&mut EntityWorldMut, &[u8], &NetworkEntityMap) -> bool,
+    mapped: Option<fn(&C, Serialiser<C>) -> MappedSerialise>,
+) -> &mut App {
+    if app.world().contains_resource::<ComponentSerialiser<C>>() || app.world().contains_resource::<SnapshotSerialiser<C>>() {
+        panic!("A component was registered for replication twice: {}", type_name::<C>());
+    }
+
+    let mut registry = app.world_mut()
+        .get_resource_mut::<ReplicationRegistry>()
+        .expect("ReplicationPlugin must be added before registering components");
+
+    let index = u32::try_from(registry.components.len()).unwrap();
+    registry.components.push(ComponentRegistration {
+        insert,
+        remove: remove_component::<C>,
+        owned: mapped.is_none(),
+    });
+
+    app.insert_resource(ComponentSerialiser { index, serialiser, mapped });
+    app.add_systems(PostUpdate, collect_component_system::<C>
+        .in_set(ReplicationSystems::Collect));
+
+    // Clients with authority over an entity send its changes, but can't map entities for the server
+    if mapped.is_none() {
+        app.add_systems(PostUpdate, collect_owned_component_system::<C>
+            .run_if(is_client)
+            .before(client_send_system));
+    }
+
+    return app;
+}
@@ -0,0 +1,301 @@
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use hashbrown::{HashMap, HashSet};
+use crate::plugin::{is_server, ReplicationSystems};
+use crate::{Replicated, ReplicationPlugin};
+
+/// Limits which peers replicated entities are sent to.
+///
+/// Every tick, each peer's [`PeerVisibility`] is computed from the [`NetworkRooms`] and
+/// [`NetworkCell`] components of the peer and of all [`Replicated`] entities.
+/// A peer can see an entity if:
+/// - the entity has neither component, making it visible to everyone,
+/// - they share at least one room, or
+/// - their cells are at most `cell_radius` cells apart on every axis.
+///
+/// Entities are spawned on peers when they enter their view, and despawned when they leave it.
+/// The same information is sent as [`EntityEnteredViewEvent`] and [`EntityLeftViewEvent`] events.
+pub struct RelevancyPlugin {
+    /// How many cells away from a peer's cell entities can be seen.
+    pub cell_radius: u32,
+}
+
+impl Default for RelevancyPlugin {
+    fn default() -> Self {
+        Self {
+            cell_radius: 1,
+        }
+    }
+}
+
+impl Plugin for RelevancyPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ReplicationPlugin>() {
+            panic!("ReplicationPlugin must be added before RelevancyPlugin");
+        }
+
+        app.insert_resource(RelevancySettings { cell_radius: self.cell_radius });
+        app.add_event::<EntityEnteredViewEvent>();
+        app.add_event::<EntityLeftViewEvent>();
+
+        app.add_systems(PreUpdate, add_visibility_system
+            .run_if(is_server)
+            .in_set(NetworkRecv::Synchronise));
+
+        app.add_systems(PostUpdate, visibility_system
+            .in_set(ReplicationSystems::Relevancy));
+    }
+}
+
+#[derive(Resource)]
+struct RelevancySettings {
+    cell_radius: u32,
+}
+
+/// A room that peers and replicated entities can be in. See [`RelevancyPlugin`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetworkRoom(pub u32);
+
+/// The rooms a peer or replicated entity is in. See [`RelevancyPlugin`].
+#[derive(Debug, Default, Clone, Component)]
+pub struct NetworkRooms(HashSet<NetworkRoom>);
+
+impl NetworkRooms {
+    /// Creates a new set of rooms.
+    pub fn new(rooms: impl IntoIterator<Item = NetworkRoom>) -> Self {
+        Self(rooms.into_iter().collect())
+    }
+
+    /// Adds a room, returning `false` if it was already present.
+    pub fn insert(&mut self, room: NetworkRoom) -> bool {
+        self.0.insert(room)
+    }
+
+    /// Removes a room, returning `false` if it wasn't present.
+    pub fn remove(&mut self, room: NetworkRoom) -> bool {
+        self.0.remove(&room)
+    }
+
+    /// Returns `true` if `room` is present.
+    pub fn contains(&self, room: NetworkRoom) -> bool {
+        self.0.contains(&room)
+    }
+
+    /// Returns an iterator over all rooms.
+    pub fn iter(&self) -> impl Iterator<Item = NetworkRoom> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+/// The spatial cell a peer or replicated entity is in. See [`RelevancyPlugin`].
+///
+/// The size of cells is up to the application, which is responsible for
+/// updating this component as entities move. Two dimensional games can leave `z` as `0`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub struct NetworkCell {
+    /// The position of the cell on the X axis.
+    pub x: i32,
+    /// The position of the cell on the Y axis.
+    pub y: i32,
+    /// The position of the cell on the Z axis.
+    pub z: i32,
+}
+
+/// The replicated entities a peer can see, added to all peers by the [`RelevancyPlugin`].
+///
+/// Systems that send messages about entities can use this to skip peers that can't see them.
+///
+/// ```
+/// # use bevy_ecs::prelude::*;
+/// # use bevy_stardust::prelude::*;
+/// # use bevy_stardust_replicate::prelude::*;
+/// # #[derive(Component)] struct Explosion;
+/// fn send_explosions(
+///     explosions: Query<Entity, Added<Explosion>>,
+///     mut peers: Query<(&PeerVisibility, &mut PeerMessages<Outgoing>)>,
+/// ) {
+///     for explosion in explosions.iter() {
+///         for (visibility, mut messages) in peers.iter_mut() {
+///             if !visibility.contains(explosion) { continue }
+///             // ...
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Default, Component)]
+pub struct PeerVisibility {
+    visible: HashSet<Entity>,
+    entered: Vec<Entity>,
+    left: Vec<Entity>,
+}
+
+impl PeerVisibility {
+    /// Returns `true` if the peer can see `entity`.
+    #[inline]
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.visible.contains(&entity)
+    }
+
+    /// Returns an iterator over all entities the peer can see.
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.visible.iter().copied()
+    }
+
+    /// Returns the entities that became visible this tick.
+    pub fn entered(&self) -> &[Entity] {
+        &self.entered
+    }
+
+    /// Returns the entities that stopped being visible this tick, including despawned entities.
+    pub fn left(&self) -> &[Entity] {
+        &self.left
+    }
+}
+
+/// Sent when a replicated entity becomes visible to a peer.
+#[derive(Debug, Clone, Event)]
+pub struct EntityEnteredViewEvent {
+    /// The peer that can now see the entity.
+    pub peer: Entity,
+    /// The entity that became visible.
+    pub entity: Entity,
+}
+
+/// Sent when a replicated entity stops being visible to a peer, including when it's despawned.
+#[derive(Debug, Clone, Event)]
+pub struct EntityLeftViewEvent {
+    /// The peer that can no longer see the entity.
+    pub peer: Entity,
+    /// The entity that stopped being visible.
+    pub entity: Entity,
+}
+
+fn add_visibility_system(
+    mut commands: Commands,
+    query: Query<Entity, (Added<Peer>, Without<PeerVisibility>)>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).insert(PeerVisibility::default());
+    }
+}
+
+type RelevantEntity<'a> = (Entity, Option<&'a NetworkRooms>, Option<&'a NetworkCell>);
+type ViewingPeer<'a> = (Entity, &'a mut PeerVisibility, Option<&'a NetworkRooms>, Option<&'a NetworkCell>);
+
+fn visibility_system(
+    settings: Res<RelevancySettings>,
+    entities: Query<RelevantEntity, With<Replicated>>,
+    mut peers: Query<ViewingPeer, With<Peer>>,
+    mut entered_events: EventWriter<EntityEnteredViewEvent>,
+    mut left_events: EventWriter<EntityLeftViewEvent>,
+) {
+    // Index entities so that peers only look at the rooms and cells they're in
+    let mut global = Vec::new();
+    let mut rooms: HashMap<NetworkRoom, Vec<Entity>> = HashMap::new();
+    let mut cells: HashMap<NetworkCell, Vec<Entity>> = HashMap::new();
+
+    for (entity, entity_rooms, cell) in entities.iter() {
+        if entity_rooms.is_none() && cell.is_none() { global.push(entity) }
+        for room in entity_rooms.iter().flat_map(|rooms| rooms.iter()) {
+            rooms.entry(room).or_default().push(entity);
+        }
+
+        if let Some(cell) = cell { cells.entry(*cell).or_default().push(entity) }
+    }
+
+    // Large radii cover more cells than are occupied, in which case it's faster to check every occupied cell
+    let radius = settings.cell_radius;
+    let scan_occupied = (2 * radius as u64 + 1).saturating_pow(3) > cells.len() as u64;
+    let mut visible = HashSet::new();
+
+    for (peer, mut visibility, peer_rooms, peer_cell) in peers.iter_mut() {
+        visible.extend(global.iter().copied());
+
+        for room in peer_rooms.iter().flat_map(|rooms| rooms.iter()) {
+            visible.extend(rooms.get(&room).into_iter().flatten().copied());
+        }
+
+        if let Some(cell) = peer_cell {
+            if scan_occupied {
+                for (other, entities) in cells.iter() {
+                    if cell.x.abs_diff(other.x) > radius || cell.y.abs_diff(other.y) > radius || cell.z.abs_diff(other.z) > radius { continue }
+                    visible.extend(entities.iter().copied());
+                }
+            } else {
+                let range = |v: i32| v.saturating_sub_unsigned(radius)..=v.saturating_add_unsigned(radius);
+                for x in range(cell.x) { for y in range(cell.y) { for z in range(cell.z) {
+                    visible.extend(cells.get(&NetworkCell { x, y, z }).into_iter().flatten().copied());
+                }}}
+            }
+        }
+
+        let visibility = &mut *visibility;
+        visibility.entered.clear();
+        visibility.left.clear();
+        visibility.entered.extend(visible.iter().filter(|entity| !visibility.visible.contains(*entity)));
+        visibility.left.extend(visibility.visible.iter().filter(|entity| !visible.contains(*entity)));
+        std::mem::swap(&mut visibility.visible, &mut visible);
+        visible.clear();
+
+        entered_events.send_batch(visibility.entered.iter().map(|entity| EntityEnteredViewEvent { peer, entity: *entity }));
+        left_events.send_batch(visibility.left.iter().map(|entity| EntityLeftViewEvent { peer, entity: *entity }));
+    }
+}
+
+#[test]
+fn relevancy_test() {
+    use bevy_stardust::connections::NetworkRole;
+    use crate::NetworkEntityMap;
+
+    let mut app = App::new();
+    app.add_plugins((StardustPlugin, ReplicationPlugin::default(), RelevancyPlugin::default()));
+    app.insert_resource(NetworkRole::Server);
+    app.finish();
+    app.cleanup();
+
+    let peer = app.world_mut().spawn((Peer::new(), PeerLifestage::Established, PeerMessages::<Outgoing>::new(), NetworkCell::default())).id();
+    let global = app.world_mut().spawn(Replicated).id();
+    let roomed = app.world_mut().spawn((Replicated, NetworkRooms::new([NetworkRoom(1)]))).id();
+    let near = app.world_mut().spawn((Replicated, NetworkCell { x: 1, y: -1, z: 0 })).id();
+    let far = app.world_mut().spawn((Replicated, NetworkCell { x: 2, y: 0, z: 0 })).id();
+    app.update();
+
+    // Only entities the peer can see are given ids, and so spawned on the peer
+    let spawned = |app: &App, entity| app.world().get::<NetworkEntityMap>(peer).unwrap().id(entity).is_some();
+    assert!(spawned(&app, global) && spawned(&app, near));
+    assert!(!spawned(&app, roomed) && !spawned(&app, far));
+
+    app.world_mut().entity_mut(peer).insert(NetworkRooms::new([NetworkRoom(1)]));
+    app.world_mut().entity_mut(near).insert(NetworkCell { x: -2, y: 0, z: 0 });
+    app.update();
+
+    assert!(spawned(&app, roomed) && !spawned(&app, near));
+    let visibility = app.world().get::<PeerVisibility>(peer).unwrap();
+    assert_eq!(visibility.entered(), &[roomed]);
+    assert_eq!(visibility.left(), &[near]);
+
+    let events = app.world().resource::<Events<EntityLeftViewEvent>>();
+    let left: Vec<_> = events.iter_current_update_events().map(|event| (event.peer, event.entity)).collect();
+    assert_eq!(left, vec![(peer, near)]);
+}
+
+#[test]
+fn relevancy_radius_test() {
+    use bevy_stardust::connections::NetworkRole;
+
+    let mut app = App::new();
+    app.add_plugins((StardustPlugin, ReplicationPlugin::default(), RelevancyPlugin { cell_radius: u32::MAX }));
+    app.insert_resource(NetworkRole::Server);
+    app.finish();
+    app.cleanup();
+
+    let peer = app.world_mut().spawn((Peer::new(), PeerLifestage::Established, PeerMessages::<Outgoing>::new(), NetworkCell::default())).id();
+    let min = app.world_mut().spawn((Replicated, NetworkCell { x: i32::MIN, y: i32::MIN, z: i32::MIN })).id();
+    let max = app.world_mut().spawn((Replicated, NetworkCell { x: i32::MAX, y: i32::MAX, z: i32::MAX })).id();
+    app.update();
+
+    // Every cell is in range, without visiting every cell in range
+    let visibility = app.world().get::<PeerVisibility>(peer).unwrap();
+    assert!(visibility.contains(min) && visibility.contains(max));
+}
@@ -0,0 +1,289 @@
+use std::any::type_name;
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{Infraction, PeerReputation};
+use bytes::{Buf, Bytes, BytesMut};
+use crate::plugin::{is_client, is_server};
+use crate::protocol::{apply_patch, get_u32, patch, put};
+use crate::Serialiser;
+
+const FULL: u8 = 0;
+const PATCH: u8 = 1;
+
+/// Mirrors resources registered with [`replicate_resource`](crate::ReplicationAppExt::replicate_resource)
+/// from the server to its clients, such as match settings and scores.
+///
+/// Peers are sent the full state of every resource when they connect, and whenever a resource
+/// changes afterwards, only the bytes that changed are sent, if its length didn't change.
+/// Resources are read-only on clients: local changes are overwritten by the server's state
+/// at the end of the tick.
+pub struct ResourceReplicationPlugin {
+    /// The configuration of [`ResourceChannel`]. Must be reliable and ordered,
+    /// since changes are relative to the previous state of the resource.
+    pub channel: ChannelConfiguration,
+}
+
+impl Default for ResourceReplicationPlugin {
+    fn default() -> Self {
+        Self {
+            channel: ChannelConfiguration {
+                consistency: MessageConsistency::ReliableOrdered,
+                priority: 0,
+                direction: ChannelDirection::ServerToClient,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Plugin for ResourceReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        let consistency = self.channel.consistency;
+        if !consistency.is_reliable() || !consistency.is_ordered() {
+            panic!("ResourceReplicationPlugin must use a reliable and ordered channel");
+        }
+
+        app.add_channel::<ResourceChannel>(self.channel.clone());
+        app.init_resource::<ResourceRegistry>();
+    }
+}
+
+/// The channel used for replicated resources.
+pub struct ResourceChannel;
+
+#[derive(Default, Resource)]
+struct ResourceRegistry {
+    count: u32,
+}
+
+/// The state of a replicated resource, as it was last sent or received.
+#[derive(Resource)]
+struct ResourceState<R> {
+    index: u32,
+    serialiser: Serialiser<R>,
+    last: Option<Bytes>,
+}
+
+pub(crate) fn register_resource<R: Resource>(app: &mut App, serialiser: Serialiser<R>) -> &mut App {
+    if app.world().contains_resource::<ResourceState<R>>() {
+        panic!("A resource was registered for replication twice: {}", type_name::<R>());
+    }
+
+    let mut registry = app.world_mut()
+        .get_resource_mut::<ResourceRegistry>()
+        .expect("ResourceReplicationPlugin must be added before registering resources");
+
+    let index = registry.count;
+    registry.count += 1;
+
+    app.insert_resource(ResourceState::<R> { index, serialiser, last: None });
+
+    app.add_systems(PreUpdate, receive_resource_system::<R>
+        .run_if(is_client)
+        .in_set(NetworkRecv::Synchronise));
+
+    app.add_systems(PostUpdate, send_resource_system::<R>
+        .run_if(is_server)
+        .before(NetworkSend::Prepare));
+
+    app.add_systems(PostUpdate, read_only_system::<R>
+        .run_if(is_client)
+        .before(NetworkSend::Prepare));
+
+    return app;
+}
+
+fn send_resource_system<R: Resource>(
+    mut state: ResMut<ResourceState<R>>,
+    resource: Option<Res<R>>,
+    channel: ChannelData<ResourceChannel>,
+    mut connected: EventReader<PeerConnectedEvent>,
+    mut peers: NetBroadcast,
+    mut scratch: Local<BytesMut>,
+) {
+    let state = &mut *state;
+    let Some(resource) = resource else { connected.clear(); return };
+
+    // The previous state, if the resource changed
+    let mut previous = None;
+    if resource.is_changed() || state.last.is_none() {
+        state.serialiser.serialise(&resource, &mut scratch);
+        let new = scratch.split().freeze();
+        if state.last.as_ref() != Some(&new) {
+            previous = Some(state.last.replace(new));
+        }
+    }
+
+    let Some(current) = state.last.as_ref() else { return };
+
+    // Peers that just connected are sent the full state first, since they can't apply patches
+    // without it, and applying this tick's patch to the new state doesn't change anything
+    let new_peers: Vec<Entity> = connected.read().map(|event| event.peer).collect();
+    if !new_peers.is_empty() {
+        let _ = peers.multicast(new_peers, channel.id(), message(state.index, FULL, current, &mut scratch));
+    }
+
+    let _ = match previous {
+        Some(Some(old)) if old.len() == current.len() => peers.broadcast(channel.id(), message(state.index, PATCH, &patch(&old, current), &mut scratch)),
+        Some(_) => peers.broadcast(channel.id(), message(state.index, FULL, current, &mut scratch)),
+        None => Ok(()),
+    };
+}
+
+fn message(index: u32, kind: u8, payload: &[u8], scratch: &mut BytesMut) -> Message {
+    put(scratch, index as u64);
+    scratch.extend_from_slice(&[kind]);
+    scratch.extend_from_slice(payload);
+    Message::from_bytes(scratch.split().freeze())
+}
+
+fn receive_resource_system<R: Resource>(
+    mut commands: Commands,
+    mut state: ResMut<ResourceState<R>>,
+    resource: Option<ResMut<R>>,
+    channel: ChannelData<ResourceChannel>,
+    mut peers: Query<(&PeerMessages<Incoming>, Option<&mut PeerReputation>), With<Peer>>,
+) {
+    let state = &mut *state;
+    let mut value = None;
+
+    for (messages, mut reputation) in peers.iter_mut() {
+        for message in messages.iter_channel(channel.id()) {
+            let mut buf = Bytes::from(message);
+            let result = match get_u32(&mut buf) {
+                Ok(index) if index != state.index => continue,
+                Ok(_) => apply(state.last.as_ref(), buf)
+                    .and_then(|bytes| Ok((state.serialiser.deserialise(&bytes).ok_or(())?, bytes))),
+                Err(()) => Err(()),
+            };
+
+            match result {
+                Ok((new, bytes)) => {
+                    state.last = Some(bytes);
+                    value = Some(new);
+                },
+
+                Err(()) => if let Some(reputation) = reputation.as_mut() {
+                    reputation.report(Infraction::MalformedMessage { channel: channel.id() });
+                },
+            }
+        }
+    }
+
+    let Some(value) = value else { return };
+    match resource {
+        Some(mut resource) => *resource = value,
+        None => commands.insert_resource(value),
+    }
+}
+
+/// Applies the rest of a message to the previous state of a resource.
+fn apply(last: Option<&Bytes>, mut buf: Bytes) -> Result<Bytes, ()> {
+    if !buf.has_remaining() { return Err(()) }
+
+    match buf.get_u8() {
+        FULL => Ok(buf),
+
+        PATCH => {
+            let mut data = BytesMut::from(&last.ok_or(())?[..]);
+            apply_patch(&mut data, &mut buf)?;
+            if buf.has_remaining() { return Err(()) }
+            Ok(data.freeze())
+        },
+
+        _ => Err(()),
+    }
+}
+
+fn read_only_system<R: Resource>(
+    state: Res<ResourceState<R>>,
+    resource: Option<ResMut<R>>,
+    mut scratch: Local<BytesMut>,
+) {
+    let (Some(mut resource), Some(last)) = (resource, state.last.as_ref()) else { return };
+    if !resource.is_changed() { return }
+
+    // Changes made by receive_resource_system serialise to the same bytes
+    state.serialiser.serialise(&resource, &mut scratch);
+    if scratch.split() == last[..] { return }
+    *resource = state.serialiser.deserialise(last).unwrap();
+}
+
+#[test]
+fn resource_replication_test() {
+    use bevy_stardust::connections::NetworkRole;
+    use crate::ReplicationAppExt;
+
+    #[derive(Debug, PartialEq, Resource)]
+    struct Score { red: u32, blue: u32 }
+
+    #[derive(Default, Resource)]
+    struct Sent(Vec<ChannelMessage>);
+
+    fn app(role: NetworkRole) -> App {
+        let mut app = App::new();
+        app.add_plugins((StardustPlugin, ResourceReplicationPlugin::default()));
+        app.replicate_resource::<Score>(Serialiser::new(
+            |score, buf| {
+                buf.extend_from_slice(&score.red.to_le_bytes());
+                buf.extend_from_slice(&score.blue.to_le_bytes());
+            },
+            |bytes| Some(Score {
+                red: u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?),
+                blue: u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?),
+            }),
+        ));
+
+        // Stand in for a transport layer, taking messages before they're cleared
+        app.init_resource::<Sent>();
+        app.add_systems(PostUpdate, (|mut sent: ResMut<Sent>, query: Query<&PeerMessages<Outgoing>>| {
+            for messages in query.iter() {
+                for (channel, iter) in messages.iter() {
+                    sent.0.extend(iter.map(|message| ChannelMessage { channel, message }));
+                }
+            }
+        }).in_set(NetworkSend::Transmit));
+
+        app.insert_resource(role);
+        app.finish();
+        app.cleanup();
+        app
+    }
+
+    let mut server = app(NetworkRole::Server);
+    let mut client = app(NetworkRole::Client);
+    server.insert_resource(Score { red: 1, blue: 0 });
+    server.update();
+
+    let peer = (Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new());
+    let client_peer = server.world_mut().spawn(peer).id();
+    let peer = (Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new());
+    let server_peer = client.world_mut().spawn(peer).id();
+    server.world_mut().send_event(PeerConnectedEvent { peer: client_peer });
+
+    // Returns the sizes of the messages that were sent
+    let transfer = |server: &mut App, client: &mut App| {
+        server.update();
+        let sent = std::mem::take(&mut server.world_mut().resource_mut::<Sent>().0);
+        let sizes = sent.iter().map(|message| message.message.len()).collect::<Vec<_>>();
+        client.world_mut().get_mut::<PeerMessages<Incoming>>(server_peer).unwrap().push_many(sent);
+        client.update();
+        sizes
+    };
+
+    // The full state is sent when the peer connects
+    let full = transfer(&mut server, &mut client);
+    assert_eq!(client.world().resource::<Score>(), &Score { red: 1, blue: 0 });
+
+    // Only the changed bytes are sent afterwards
+    server.world_mut().resource_mut::<Score>().blue = 2;
+    let diff = transfer(&mut server, &mut client);
+    assert!(diff[0] < full[0]);
+    assert_eq!(client.world().resource::<Score>(), &Score { red: 1, blue: 2 });
+
+    // Nothing is sent while nothing changes, and local changes on the client are reverted
+    client.world_mut().resource_mut::<Score>().red = 99;
+    assert!(transfer(&mut server, &mut client).is_empty());
+    assert_eq!(client.world().resource::<Score>(), &Score { red: 1, blue: 2 });
+}
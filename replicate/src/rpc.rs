@@ -0,0 +1,403 @@
+use std::any::type_name;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{Infraction, PeerReputation};
+use bytes::{Buf, Bytes, BytesMut};
+use hashbrown::HashMap;
+use crate::protocol::{get_u32, put};
+use crate::Serialiser;
+
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+
+/// Identifies a call made with [`RpcCaller::call`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RpcCallId {
+    peer: Entity,
+    id: u32,
+}
+
+impl RpcCallId {
+    /// Returns the peer the call was made to.
+    #[inline]
+    pub fn peer(&self) -> Entity {
+        self.peer
+    }
+}
+
+/// Why a call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RpcError {
+    /// The peer didn't respond before the call's timeout.
+    TimedOut,
+
+    /// The peer disconnected before responding.
+    Disconnected,
+}
+
+/// A request received from another peer. Read this with an [`EventReader`] and
+/// answer it with an [`RpcResponder`] to handle calls registered with
+/// [`add_rpc`](crate::ReplicationAppExt::add_rpc).
+///
+/// Requests are received in [`NetworkRecv::Synchronise`].
+#[derive(Debug, Clone, Event)]
+pub struct RpcRequestEvent<Req, Resp> {
+    /// The peer entity that made the request.
+    pub peer: Entity,
+
+    /// The request.
+    pub request: Req,
+
+    id: u32,
+    phantom: PhantomData<fn() -> Resp>,
+}
+
+/// Sent when a call made with [`RpcCaller::call`] succeeds or fails.
+/// Its result can then be taken with [`RpcCaller::poll`].
+#[derive(Debug, Clone, Event)]
+pub struct RpcCompletedEvent<Req, Resp> {
+    /// The call that completed.
+    pub call: RpcCallId,
+
+    phantom: PhantomData<fn() -> (Req, Resp)>,
+}
+
+/// The channel used for calls with request `Req` and response `Resp`.
+pub struct RpcChannel<Req, Resp>(PhantomData<fn() -> (Req, Resp)>);
+
+#[derive(Resource)]
+struct RpcSerialisers<Req, Resp> {
+    request: Serialiser<Req>,
+    response: Serialiser<Resp>,
+}
+
+#[derive(Resource)]
+struct RpcCalls<Req, Resp> {
+    next: u32,
+    outgoing: Vec<(RpcCallId, Req)>,
+    pending: HashMap<RpcCallId, Instant>,
+
+    /// Results, and whether they've been kept for a full tick.
+    results: HashMap<RpcCallId, (Result<Resp, RpcError>, bool)>,
+}
+
+impl<Req, Resp> RpcCalls<Req, Resp> {
+    fn complete(&mut self, call: RpcCallId, result: Result<Resp, RpcError>) {
+        self.results.insert(call, (result, false));
+    }
+}
+
+impl<Req, Resp> Default for RpcCalls<Req, Resp> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            outgoing: Vec::new(),
+            pending: HashMap::new(),
+            results: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Resource)]
+struct RpcReplies<Req, Resp> {
+    outgoing: Vec<(Entity, u32, Resp)>,
+    phantom: PhantomData<fn() -> Req>,
+}
+
+impl<Req, Resp> Default for RpcReplies<Req, Resp> {
+    fn default() -> Self {
+        Self {
+            outgoing: Vec::new(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// A [`SystemParam`] for calling procedures registered with
+/// [`add_rpc`](crate::ReplicationAppExt::add_rpc) on other peers.
+///
+/// Requests are sent at the end of the tick, and calls complete in [`NetworkRecv::Synchronise`],
+/// when an [`RpcCompletedEvent`] is sent. Results are kept until the end of the tick after the
+/// call completed, so they can be taken by polling every tick or by reading the event.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use bevy_ecs::prelude::*;
+/// # use bevy_stardust_replicate::*;
+/// # struct JoinLobby(u32);
+/// # struct Joined(bool);
+/// # #[derive(Resource)] struct Lobby(Entity);
+/// fn join_lobby(lobby: Res<Lobby>, mut caller: RpcCaller<JoinLobby, Joined>, mut call: Local<Option<RpcCallId>>) {
+///     let id = *call.get_or_insert_with(|| caller.call(lobby.0, JoinLobby(3), Duration::from_secs(5)));
+///     match caller.poll(id) {
+///         Some(Ok(Joined(true))) => { /* ... */ },
+///         Some(_) => { /* ... */ },
+///         None => {},
+///     }
+/// }
+/// ```
+#[derive(SystemParam)]
+pub struct RpcCaller<'w, Req: Send + Sync + 'static, Resp: Send + Sync + 'static> {
+    calls: ResMut<'w, RpcCalls<Req, Resp>>,
+}
+
+impl<Req: Send + Sync + 'static, Resp: Send + Sync + 'static> RpcCaller<'_, Req, Resp> {
+    /// Calls `peer` with `request`, failing if it doesn't respond within `timeout`.
+    pub fn call(&mut self, peer: Entity, request: Req, timeout: Duration) -> RpcCallId {
+        let call = RpcCallId { peer, id: self.calls.next };
+        self.calls.next = self.calls.next.wrapping_add(1);
+        self.calls.outgoing.push((call, request));
+        self.calls.pending.insert(call, Instant::now() + timeout);
+        return call;
+    }
+
+    /// Returns `true` if `call` hasn't completed yet.
+    pub fn is_pending(&self, call: RpcCallId) -> bool {
+        self.calls.pending.contains_key(&call)
+    }
+
+    /// Takes the result of `call`, if it has completed.
+    pub fn poll(&mut self, call: RpcCallId) -> Option<Result<Resp, RpcError>> {
+        self.calls.results.remove(&call).map(|(result, _)| result)
+    }
+
+    /// Cancels `call`, ignoring its response, and returns `true` if it was pending.
+    pub fn cancel(&mut self, call: RpcCallId) -> bool {
+        self.calls.pending.remove(&call).is_some()
+    }
+}
+
+/// A [`SystemParam`] for responding to [`RpcRequestEvent`]s.
+#[derive(SystemParam)]
+pub struct RpcResponder<'w, Req: Send + Sync + 'static, Resp: Send + Sync + 'static> {
+    replies: ResMut<'w, RpcReplies<Req, Resp>>,
+}
+
+impl<Req: Send + Sync + 'static, Resp: Send + Sync + 'static> RpcResponder<'_, Req, Resp> {
+    /// Responds to `request` with `response`, which is sent at the end of the tick.
+    ///
+    /// Requests don't have to be answered in the tick they're received,
+    /// but the caller may have timed out by the time the response arrives.
+    pub fn respond(&mut self, request: &RpcRequestEvent<Req, Resp>, response: Resp) {
+        self.replies.outgoing.push((request.peer, request.id, response));
+    }
+}
+
+pub(crate) fn register_rpc<Req: Send + Sync + 'static, Resp: Send + Sync + 'static>(
+    app: &mut App,
+    request: Serialiser<Req>,
+    response: Serialiser<Resp>,
+    channel: ChannelConfiguration,
+) -> &mut App {
+    if app.world().contains_resource::<RpcSerialisers<Req, Resp>>() {
+        panic!("A procedure was registered twice: {} -> {}", type_name::<Req>(), type_name::<Resp>());
+    }
+
+    if !channel.consistency.is_reliable() {
+        panic!("Procedures must use a reliable channel: {} -> {}", type_name::<Req>(), type_name::<Resp>());
+    }
+
+    app.add_channel::<RpcChannel<Req, Resp>>(channel);
+    app.insert_resource(RpcSerialisers { request, response });
+    app.init_resource::<RpcCalls<Req, Resp>>();
+    app.init_resource::<RpcReplies<Req, Resp>>();
+    app.add_event::<RpcRequestEvent<Req, Resp>>();
+    app.add_event::<RpcCompletedEvent<Req, Resp>>();
+
+    app.add_systems(PreUpdate, receive_rpc_system::<Req, Resp>
+        .in_set(NetworkRecv::Synchronise));
+
+    app.add_systems(PostUpdate, send_rpc_system::<Req, Resp>
+        .before(NetworkSend::Prepare));
+
+    return app;
+}
+
+fn send_rpc_system<Req: Send + Sync + 'static, Resp: Send + Sync + 'static>(
+    serialisers: Res<RpcSerialisers<Req, Resp>>,
+    channel: ChannelData<RpcChannel<Req, Resp>>,
+    mut calls: ResMut<RpcCalls<Req, Resp>>,
+    mut replies: ResMut<RpcReplies<Req, Resp>>,
+    mut peers: Query<&mut PeerMessages<Outgoing>, With<Peer>>,
+    mut scratch: Local<BytesMut>,
+) {
+    let calls = &mut *calls;
+    for (call, request) in calls.outgoing.drain(..) {
+        // Calls that were cancelled or already failed aren't sent
+        if !calls.pending.contains_key(&call) { continue }
+        let Ok(mut messages) = peers.get_mut(call.peer) else { continue };
+
+        scratch.extend_from_slice(&[REQUEST]);
+        put(&mut scratch, call.id as u64);
+        serialisers.request.serialise(&request, &mut scratch);
+        messages.push_one(ChannelMessage { channel: channel.id(), message: Message::from_bytes(scratch.split().freeze()) });
+    }
+
+    for (peer, id, response) in replies.outgoing.drain(..) {
+        let Ok(mut messages) = peers.get_mut(peer) else { continue };
+
+        scratch.extend_from_slice(&[RESPONSE]);
+        put(&mut scratch, id as u64);
+        serialisers.response.serialise(&response, &mut scratch);
+        messages.push_one(ChannelMessage { channel: channel.id(), message: Message::from_bytes(scratch.split().freeze()) });
+    }
+}
+
+type CallingPeer<'a> = (Entity, &'a PeerMessages<Incoming>, Option<&'a PeerLifestage>, Option<&'a mut PeerReputation>);
+
+fn receive_rpc_system<Req: Send + Sync + 'static, Resp: Send + Sync + 'static>(
+    serialisers: Res<RpcSerialisers<Req, Resp>>,
+    channel: ChannelData<RpcChannel<Req, Resp>>,
+    mut calls: ResMut<RpcCalls<Req, Resp>>,
+    mut peers: Query<CallingPeer, With<Peer>>,
+    mut requests: EventWriter<RpcRequestEvent<Req, Resp>>,
+    mut completed: EventWriter<RpcCompletedEvent<Req, Resp>>,
+) {
+    // Results are dropped at the end of the tick after they were completed
+    calls.results.retain(|_, (_, stale)| !*stale);
+    calls.results.values_mut().for_each(|(_, stale)| *stale = true);
+
+    for (peer, messages, _, mut reputation) in peers.iter_mut() {
+        for message in messages.iter_channel(channel.id()) {
+            let valid = match parse(message) {
+                Some((REQUEST, id, payload)) => match serialisers.request.deserialise(&payload) {
+                    Some(request) => { requests.send(RpcRequestEvent { peer, request, id, phantom: PhantomData }); true },
+                    None => false,
+                },
+
+                Some((RESPONSE, id, payload)) => {
+                    // Responses to calls that were cancelled or timed out are ignored
+                    let call = RpcCallId { peer, id };
+                    if !calls.pending.contains_key(&call) { continue }
+
+                    match serialisers.response.deserialise(&payload) {
+                        Some(response) => {
+                            calls.pending.remove(&call);
+                            calls.complete(call, Ok(response));
+                            completed.send(RpcCompletedEvent { call, phantom: PhantomData });
+                            true
+                        },
+                        None => false,
+                    }
+                },
+
+                _ => false,
+            };
+
+            if !valid { if let Some(reputation) = reputation.as_mut() {
+                reputation.report(Infraction::MalformedMessage { channel: channel.id() });
+            }}
+        }
+    }
+
+    let now = Instant::now();
+    let mut failed = Vec::new();
+    calls.pending.retain(|call, deadline| {
+        let connected = peers.get(call.peer).is_ok_and(|(_, _, lifestage, _)| lifestage.is_none_or(|lifestage| *lifestage < PeerLifestage::Closing));
+        let error = match (connected, now >= *deadline) {
+            (false, _) => RpcError::Disconnected,
+            (true, true) => RpcError::TimedOut,
+            (true, false) => return true,
+        };
+
+        failed.push((*call, error));
+        return false;
+    });
+
+    for (call, error) in failed {
+        calls.complete(call, Err(error));
+        completed.send(RpcCompletedEvent { call, phantom: PhantomData });
+    }
+}
+
+/// Splits a message into its kind, call id, and payload.
+fn parse(message: Message) -> Option<(u8, u32, Bytes)> {
+    let mut bytes = Bytes::from(message);
+    if !bytes.has_remaining() { return None }
+    let kind = bytes.get_u8();
+    let id = get_u32(&mut bytes).ok()?;
+    return Some((kind, id, bytes));
+}
+
+#[test]
+fn rpc_test() {
+    use bevy_ecs::system::RunSystemOnce;
+    use crate::ReplicationAppExt;
+
+    #[derive(Default, Resource)]
+    struct Sent(Vec<Message>);
+
+    #[derive(Debug, PartialEq)]
+    struct Ping(u8);
+
+    #[derive(Debug, PartialEq)]
+    struct Pong(u8);
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+    app.add_rpc::<Ping, Pong>(
+        Serialiser::new(|ping, buf| buf.extend_from_slice(&[ping.0]), |bytes| Some(Ping(*bytes.first()?))),
+        Serialiser::new(|pong, buf| buf.extend_from_slice(&[pong.0]), |bytes| Some(Pong(*bytes.first()?))),
+        ChannelConfiguration {
+            consistency: MessageConsistency::ReliableOrdered,
+            priority: 0,
+            ..Default::default()
+        },
+    );
+
+    app.finish();
+    app.cleanup();
+
+    let channel = app.world_mut().run_system_once(|channel: ChannelData<RpcChannel<Ping, Pong>>| channel.id()).unwrap();
+
+    // Answers every request with the next number
+    app.add_systems(Update, |mut requests: EventReader<RpcRequestEvent<Ping, Pong>>, mut responder: RpcResponder<Ping, Pong>| {
+        for request in requests.read() { responder.respond(request, Pong(request.request.0 + 1)) }
+    });
+
+    // Stand in for a transport layer, taking messages before they're cleared
+    app.init_resource::<Sent>();
+    app.add_systems(PostUpdate, (move |mut sent: ResMut<Sent>, query: Query<&PeerMessages<Outgoing>>| {
+        for messages in query.iter() { sent.0.extend(messages.iter_channel(channel)) }
+    }).in_set(NetworkSend::Transmit));
+
+    let a = app.world_mut().spawn((Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new())).id();
+    let b = app.world_mut().spawn((Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new())).id();
+
+    let (ok, timeout, disconnect, cancelled) = app.world_mut().run_system_once(move |mut caller: RpcCaller<Ping, Pong>| (
+        caller.call(a, Ping(1), Duration::from_secs(60)),
+        caller.call(a, Ping(2), Duration::ZERO),
+        caller.call(b, Ping(3), Duration::from_secs(60)),
+        caller.call(a, Ping(4), Duration::from_secs(60)),
+    )).unwrap();
+
+    app.world_mut().run_system_once(move |mut caller: RpcCaller<Ping, Pong>| assert!(caller.cancel(cancelled))).unwrap();
+    app.world_mut().despawn(b);
+
+    // Loops messages sent to `a` back to the app, as if `a` were calling it
+    let loopback = |app: &mut App| {
+        let sent = std::mem::take(&mut app.world_mut().resource_mut::<Sent>().0);
+        app.world_mut().get_mut::<PeerMessages<Incoming>>(a).unwrap().push_channel(channel, sent);
+        app.update();
+    };
+
+    // Calls fail when they time out or the peer disconnects, and aren't sent
+    app.update();
+    assert_eq!(app.world().resource::<Sent>().0.len(), 1);
+    let poll = move |app: &mut App, call| app.world_mut().run_system_once(move |mut caller: RpcCaller<Ping, Pong>| caller.poll(call)).unwrap();
+    assert_eq!(poll(&mut app, timeout), Some(Err(RpcError::TimedOut)));
+    assert_eq!(poll(&mut app, disconnect), Some(Err(RpcError::Disconnected)));
+
+    // The request is answered, and the response completes the call
+    loopback(&mut app);
+    loopback(&mut app);
+    let events = app.world().resource::<Events<RpcCompletedEvent<Ping, Pong>>>();
+    assert_eq!(events.iter_current_update_events().map(|event| event.call).collect::<Vec<_>>(), vec![ok]);
+    assert_eq!(poll(&mut app, ok), Some(Ok(Pong(2))));
+    assert_eq!(poll(&mut app, cancelled), None);
+}
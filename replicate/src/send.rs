@@ -0,0 +1,232 @@
+use std::sync::Arc;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bytes::{Bytes, BytesMut};
+use hashbrown::HashSet;
+use crate::entities::NetworkEntityMap;
+use crate::plugin::ReplicationChannel;
+use crate::protocol::{ComponentUpdate, ReplicationOps};
+use crate::registry::ComponentSerialiser;
+use crate::relevancy::PeerVisibility;
+use crate::Replicated;
+
+/// Changes to replicated entities that are sent to peers this tick.
+#[derive(Default, Resource)]
+pub(crate) struct ReplicationBuffer {
+    /// Changes since the last tick, for peers that are already synchronised.
+    changes: PendingOps,
+
+    /// The entire state of some or all replicated entities, if any peers need it.
+    full: Option<PendingOps>,
+
+    /// Whether `full` contains all replicated entities, for peers that aren't synchronised.
+    all: bool,
+
+    /// Entities that became visible to a peer this tick, which are included in `full`.
+    entering: HashSet<Entity>,
+}
+
+impl ReplicationBuffer {
+    fn wants_full(&self, entity: Entity) -> bool {
+        self.all || self.entering.contains(&entity)
+    }
+
+    /// Converts the operations to ids for the peer that `map` belongs to,
+    /// allocating ids for spawned entities and freeing ids of despawned ones.
+    /// Returns `None` if the peer needs the full state, but it wasn't collected.
+    fn translate(
+        &self,
+        map: &mut NetworkEntityMap,
+        visibility: Option<&PeerVisibility>,
+        synced: bool,
+    ) -> Option<ReplicationOps> {
+        let visible = |entity: Entity| visibility.is_none_or(|visibility| visibility.contains(entity));
+        let mut ops = ReplicationOps::default();
+
+        if !synced {
+            let full = self.full.as_ref().filter(|_| self.all)?;
+            ops.spawns.extend(full.spawns.iter().filter(|entity| visible(**entity)).map(|entity| map.allocate(*entity)));
+            ops.updates.extend(full.updates.iter().filter_map(|update| update.translate(map)));
+            return Some(ops);
+        }
+
+        // Despawns are first so that their indices can be reused
+        let left = visibility.into_iter().flat_map(|visibility| visibility.left());
+        ops.despawns.extend(self.changes.despawns.iter().chain(left).filter_map(|entity| map.release(*entity)));
+
+        // Entities that entered the peer's view need their full state,
+        // which is also the case for newly replicated entities if anyone else can see them
+        let entered = visibility.into_iter().flat_map(|visibility| visibility.entered());
+        let mut spawned = HashSet::new();
+        for entity in self.changes.spawns.iter().chain(entered) {
+            if !visible(*entity) || map.id(*entity).is_some() { continue }
+            ops.spawns.push(map.allocate(*entity));
+            spawned.insert(*entity);
+        }
+
+        let from_full = |entity: &Entity| self.full.is_some() && self.entering.contains(entity);
+        ops.updates.extend(self.changes.updates.iter()
+            .filter(|update| visible(update.entity) && !(spawned.contains(&update.entity) && from_full(&update.entity)))
+            .filter_map(|update| update.translate(map)));
+
+        if let Some(full) = self.full.as_ref() {
+            ops.updates.extend(full.updates.iter()
+                .filter(|update| spawned.contains(&update.entity) && from_full(&update.entity))
+                .filter_map(|update| update.translate(map)));
+        }
+
+        ops.removals.extend(self.changes.removals.iter()
+            .filter(|(entity, _)| visible(*entity) && !spawned.contains(entity))
+            .filter_map(|(entity, component)| Some((map.id(*entity)?, *component))));
+
+        return Some(ops);
+    }
+}
+
+/// Like [`ReplicationOps`], but with local entities, since ids are different for every peer.
+#[derive(Default)]
+struct PendingOps {
+    despawns: Vec<Entity>,
+    spawns: Vec<Entity>,
+    updates: Vec<PendingUpdate>,
+    removals: Vec<(Entity, u32)>,
+}
+
+impl PendingOps {
+    fn clear(&mut self) {
+        self.despawns.clear();
+        self.spawns.clear();
+        self.updates.clear();
+        self.removals.clear();
+    }
+}
+
+struct PendingUpdate {
+    entity: Entity,
+    component: u32,
+    data: UpdateData,
+}
+
+impl PendingUpdate {
+    fn translate(&self, map: &NetworkEntityMap) -> Option<ComponentUpdate> {
+        Some(ComponentUpdate {
+            entity: map.id(self.entity)?,
+            component: self.component,
+            data: match &self.data {
+                UpdateData::Shared(data) => data.clone(),
+                UpdateData::Mapped(serialise) => serialise(map),
+            },
+        })
+    }
+}
+
+#[derive(Clone)]
+enum UpdateData {
+    /// The same data is sent to every peer.
+    Shared(Bytes),
+
+    /// The component refers to other entities, and is serialised separately for every peer.
+    Mapped(MappedSerialise),
+}
+
+/// Serialises a component that refers to other entities, using the ids of the peer the map belongs to.
+pub(crate) type MappedSerialise = Arc<dyn Fn(&NetworkEntityMap) -> Bytes + Send + Sync>;
+
+/// Marks peers that have been sent the full replicated state.
+#[derive(Component)]
+pub(crate) struct ReplicaSynced;
+
+pub(crate) fn prepare_system(
+    mut buffer: ResMut<ReplicationBuffer>,
+    unsynced: Query<(), (With<Peer>, Established, Without<ReplicaSynced>)>,
+    visibility: Query<&PeerVisibility, (With<Peer>, Established, With<ReplicaSynced>)>,
+) {
+    let buffer = &mut *buffer;
+    buffer.changes.clear();
+    buffer.all = !unsynced.is_empty();
+
+    buffer.entering.clear();
+    for visibility in visibility.iter() {
+        buffer.entering.extend(visibility.entered().iter().copied());
+    }
+
+    buffer.full = (buffer.all || !buffer.entering.is_empty()).then(PendingOps::default);
+}
+
+pub(crate) fn entities_system(
+    mut buffer: ResMut<ReplicationBuffer>,
+    query: Query<(Entity, Ref<Replicated>)>,
+    mut removed: RemovedComponents<Replicated>,
+) {
+    let buffer = &mut *buffer;
+
+    // The entity may have been despawned, or only had Replicated removed
+    buffer.changes.despawns.extend(removed.read());
+
+    for (entity, replicated) in query.iter() {
+        if replicated.is_added() { buffer.changes.spawns.push(entity) }
+        if buffer.all { buffer.full.as_mut().unwrap().spawns.push(entity) }
+    }
+}
+
+pub(crate) fn collect_component_system<C: Component>(
+    serialiser: Res<ComponentSerialiser<C>>,
+    mut buffer: ResMut<ReplicationBuffer>,
+    query: Query<(Entity, Ref<C>, Ref<Replicated>)>,
+    replicated: Query<(), With<Replicated>>,
+    mut removed: RemovedComponents<C>,
+    mut scratch: Local<BytesMut>,
+) {
+    let buffer = &mut *buffer;
+    let component = serialiser.index;
+
+    for (entity, value, marker) in query.iter() {
+        // Newly replicated entities need all their components, not just changed ones
+        let changed = value.is_changed() || marker.is_added();
+        let full = buffer.wants_full(entity);
+        if !changed && !full { continue }
+
+        let data = match serialiser.mapped {
+            Some(mapped) => UpdateData::Mapped(mapped(&value, serialiser.serialiser)),
+            None => {
+                serialiser.serialiser.serialise(&value, &mut scratch);
+                UpdateData::Shared(scratch.split().freeze())
+            },
+        };
+
+        if full {
+            buffer.full.as_mut().unwrap().updates.push(PendingUpdate { entity, component, data: data.clone() });
+        }
+
+        if changed {
+            buffer.changes.updates.push(PendingUpdate { entity, component, data });
+        }
+    }
+
+    for entity in removed.read() {
+        // Despawned entities don't need their components removed
+        if !replicated.contains(entity) { continue }
+        buffer.changes.removals.push((entity, component));
+    }
+}
+
+type ReplicaPeer<'a> = (Entity, &'a mut PeerMessages<Outgoing>, &'a mut NetworkEntityMap, Option<&'a PeerVisibility>, Has<ReplicaSynced>);
+
+pub(crate) fn send_system(
+    mut commands: Commands,
+    channel: ChannelData<ReplicationChannel>,
+    buffer: Res<ReplicationBuffer>,
+    mut peers: Query<ReplicaPeer, (With<Peer>, Established)>,
+) {
+    for (entity, mut messages, mut map, visibility, synced) in peers.iter_mut() {
+        // The peer was established after the buffer was prepared
+        let Some(ops) = buffer.translate(&mut map, visibility, synced) else { continue };
+        if !synced { commands.entity(entity).insert(ReplicaSynced); }
+        if ops.is_empty() { continue }
+
+        messages.push_one(ChannelMessage {
+            channel: channel.id(),
+            message: Message::from_bytes(ops.encode()),
+        });
+    }
+}
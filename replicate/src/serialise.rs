@@ -0,0 +1,42 @@
+use bytes::BytesMut;
+
+/// Functions for converting values of type `T` to and from bytes.
+///
+/// Both functions must agree on the format, and deserialisation must not panic,
+/// since the bytes come from an untrusted peer. Returning `None` from the
+/// deserialiser counts as a malformed message from the peer that sent it.
+pub struct Serialiser<T> {
+    serialise: fn(&T, &mut BytesMut),
+    deserialise: fn(&[u8]) -> Option<T>,
+}
+
+impl<T> Serialiser<T> {
+    /// Creates a new `Serialiser` from a pair of functions.
+    pub const fn new(
+        serialise: fn(&T, &mut BytesMut),
+        deserialise: fn(&[u8]) -> Option<T>,
+    ) -> Self {
+        Self { serialise, deserialise }
+    }
+
+    /// Appends `value` to `buf`.
+    #[inline]
+    pub fn serialise(&self, value: &T, buf: &mut BytesMut) {
+        (self.serialise)(value, buf)
+    }
+
+    /// Reads a value from `bytes`, returning `None` if the bytes are invalid.
+    #[inline]
+    pub fn deserialise(&self, bytes: &[u8]) -> Option<T> {
+        (self.deserialise)(bytes)
+    }
+}
+
+impl<T> Clone for Serialiser<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Serialiser<T> {}
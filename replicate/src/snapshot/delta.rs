@@ -0,0 +1,160 @@
+//! The format of snapshot messages.
+//!
+//! Each message starts with its sequence number, followed by how far back its baseline is,
+//! as two little-endian `u16`s. A distance of `0` means there is no baseline, and the message
+//! contains the full snapshot. It's followed by a list of entries that differ from the baseline,
+//! each being an entity, a component, and one of:
+//! - the full component data, prefixed with its length,
+//! - a patch of the baseline's data, as runs of changed bytes, if the length didn't change,
+//! - nothing, if the component is no longer in the snapshot.
+
+use bevy_stardust_extras::numbers::Sequence;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hashbrown::HashMap;
+use crate::protocol::{apply_patch, get, get_id, get_u32, patch, put, put_id};
+use crate::NetworkId;
+
+/// The state of all snapshot components of all replicated entities, at one point in time.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Snapshot(pub HashMap<(NetworkId, u32), Bytes>);
+
+const FULL: u8 = 0;
+const PATCH: u8 = 1;
+const REMOVED: u8 = 2;
+
+pub(crate) struct SnapshotHeader {
+    pub sequence: Sequence<u16>,
+    pub baseline: Option<Sequence<u16>>,
+}
+
+/// Encodes `snapshot` relative to `baseline`, or in full if there is no baseline.
+/// Returns `None` if nothing changed since the baseline.
+pub(crate) fn encode(
+    sequence: Sequence<u16>,
+    baseline: Option<(Sequence<u16>, &Snapshot)>,
+    snapshot: &Snapshot,
+) -> Option<Bytes> {
+    let empty = Snapshot::default();
+    let (distance, base) = match baseline {
+        Some((baseline, base)) => (sequence.inner().wrapping_sub(baseline.inner()), base),
+        None => (0, &empty),
+    };
+
+    let mut entries = BytesMut::new();
+    let mut count = 0;
+
+    for (key, data) in snapshot.0.iter() {
+        let old = base.0.get(key);
+        if old == Some(data) { continue }
+
+        count += 1;
+        put_id(&mut entries, key.0);
+        put(&mut entries, key.1 as u64);
+
+        let patch = old.filter(|old| old.len() == data.len()).map(|old| patch(old, data));
+        match patch {
+            Some(patch) if patch.len() < data.len() => {
+                entries.put_u8(PATCH);
+                entries.extend_from_slice(&patch);
+            },
+
+            _ => {
+                entries.put_u8(FULL);
+                put(&mut entries, data.len() as u64);
+                entries.extend_from_slice(data);
+            },
+        }
+    }
+
+    for key in base.0.keys().filter(|key| !snapshot.0.contains_key(*key)) {
+        count += 1;
+        put_id(&mut entries, key.0);
+        put(&mut entries, key.1 as u64);
+        entries.put_u8(REMOVED);
+    }
+
+    if count == 0 && baseline.is_some() { return None }
+
+    let mut buf = BytesMut::with_capacity(entries.len() + 8);
+    buf.put_u16_le(sequence.inner());
+    buf.put_u16_le(distance);
+    put(&mut buf, count);
+    buf.extend_from_slice(&entries);
+    return Some(buf.freeze());
+}
+
+pub(crate) fn read_header(buf: &mut Bytes) -> Result<SnapshotHeader, ()> {
+    if buf.remaining() < 4 { return Err(()) }
+    let sequence = Sequence::from(buf.get_u16_le());
+    let distance = buf.get_u16_le();
+
+    return Ok(SnapshotHeader {
+        sequence,
+        baseline: (distance != 0).then(|| sequence - distance),
+    });
+}
+
+/// Decodes the rest of a message after [`read_header`], applying it to `base`.
+/// If the message has no baseline, `base` must be empty.
+pub(crate) fn decode(mut buf: Bytes, base: &Snapshot) -> Result<Snapshot, ()> {
+    let mut snapshot = base.clone();
+
+    for _ in 0..get(&mut buf)? {
+        let key = (get_id(&mut buf)?, get_u32(&mut buf)?);
+        if !buf.has_remaining() { return Err(()) }
+
+        match buf.get_u8() {
+            FULL => {
+                let len = get(&mut buf)?;
+                if (buf.remaining() as u64) < len { return Err(()) }
+                snapshot.0.insert(key, buf.split_to(len as usize));
+            },
+
+            PATCH => {
+                let mut data = BytesMut::from(&base.0.get(&key).ok_or(())?[..]);
+                apply_patch(&mut data, &mut buf)?;
+                snapshot.0.insert(key, data.freeze());
+            },
+
+            REMOVED => {
+                snapshot.0.remove(&key).ok_or(())?;
+            },
+
+            _ => return Err(()),
+        }
+    }
+
+    if buf.has_remaining() { return Err(()) }
+    return Ok(snapshot);
+}
+
+#[test]
+fn snapshot_delta_test() {
+    let id = |index| NetworkId::new(index, 1).unwrap();
+    let snapshot = |entries: &[(u32, &'static [u8])]| Snapshot(entries.iter()
+        .map(|(index, data)| ((id(*index), 0), Bytes::from_static(data)))
+        .collect());
+
+    let first = snapshot(&[(0, b"aaaaaaaaaaaaaaaa"), (1, b"bbbb")]);
+    let second = snapshot(&[(0, b"aaaazaaaaaaaaaaa"), (2, b"cccc")]);
+
+    // Full snapshots don't need a baseline
+    let mut full = encode(Sequence::from(u16::MAX), None, &first).unwrap();
+    let header = read_header(&mut full).unwrap();
+    assert!(header.baseline.is_none());
+    assert_eq!(decode(full, &Snapshot::default()).unwrap(), first);
+
+    // The changed component is patched, and the baseline wraps around
+    let mut delta = encode(Sequence::from(1), Some((Sequence::from(u16::MAX), &first)), &second).unwrap();
+    assert!(delta.len() < encode(Sequence::from(1), None, &second).unwrap().len());
+    let header = read_header(&mut delta).unwrap();
+    assert_eq!(header.sequence, 1);
+    assert_eq!(header.baseline, Some(Sequence::from(u16::MAX)));
+    assert_eq!(decode(delta.clone(), &first).unwrap(), second);
+
+    // Patches against the wrong baseline are rejected
+    assert!(decode(delta, &Snapshot::default()).is_err());
+
+    // Nothing is sent if nothing changed
+    assert!(encode(Sequence::from(2), Some((Sequence::from(1), &second)), &second).is_none());
+}
@@ -0,0 +1,238 @@
+//! Delta-compressed snapshots of rapidly changing components.
+
+mod delta;
+mod receive;
+mod send;
+
+use std::any::type_name;
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::world::EntityWorldMut;
+use bevy_stardust::prelude::*;
+use crate::entities::NetworkEntityMap;
+use crate::plugin::{is_client, is_server, ReplicationSystems};
+use crate::receive::apply_system;
+use crate::registry::{remove_component, ComponentRegistration, ComponentSerialiser};
+use crate::{ReplicationPlugin, Serialiser};
+use receive::apply_snapshots_system;
+use send::*;
+
+/// How many snapshots are kept to be used as baselines.
+///
+/// If a peer doesn't acknowledge any of the last this many snapshots,
+/// it's sent a full snapshot instead of a delta.
+const HISTORY_LEN: usize = 32;
+
+/// Replicates components with snapshots, for state that changes too often to be sent reliably.
+///
+/// Every tick, the server takes a snapshot of all components registered with
+/// [`snapshot_component`](crate::ReplicationAppExt::snapshot_component) on entities with
+/// [`Replicated`](crate::Replicated), and sends each peer only what changed since the newest
+/// snapshot it acknowledged. Components whose serialised form has the same length as before
+/// are sent as a patch of the changed bytes, so serialisers that write fields at fixed offsets
+/// only send the fields that changed. Peers that haven't acknowledged a snapshot recently,
+/// such as after heavy packet loss, are sent a full snapshot.
+///
+/// Snapshots are sent over [`SnapshotChannel`], which only keeps the newest message,
+/// so lost snapshots are never resent. Entities are still spawned and despawned
+/// by [`ReplicationPlugin`], which must be added first.
+pub struct SnapshotPlugin {
+    /// The configuration of [`SnapshotChannel`].
+    ///
+    /// The channel should be [`UnreliableSequenced`](MessageConsistency::UnreliableSequenced),
+    /// since old snapshots are useless once a newer one arrives.
+    pub channel: ChannelConfiguration,
+}
+
+impl Default for SnapshotPlugin {
+    fn default() -> Self {
+        Self {
+            channel: ChannelConfiguration {
+                consistency: MessageConsistency::UnreliableSequenced,
+                priority: 0,
+                direction: ChannelDirection::ServerToClient,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<ReplicationPlugin>() {
+            panic!("ReplicationPlugin must be added before SnapshotPlugin");
+        }
+
+        let snapshots = app.add_channel::<SnapshotChannel>(self.channel.clone());
+        let acks = app.add_channel::<SnapshotAckChannel>(ChannelConfiguration {
+            consistency: MessageConsistency::UnreliableSequenced,
+            priority: 0,
+            max_message_size: Some(2),
+            direction: ChannelDirection::ClientToServer,
+            ..Default::default()
+        });
+
+        app.insert_resource(SnapshotChannelIds { snapshots, acks });
+        app.init_resource::<SnapshotRegistry>();
+        app.init_resource::<SnapshotBuffer>();
+
+        app.add_systems(PostUpdate, prepare_snapshot_system
+            .in_set(ReplicationSystems::Prepare));
+
+        app.add_systems(PostUpdate, send_snapshot_system
+            .in_set(ReplicationSystems::Snapshot));
+
+        app.add_systems(PreUpdate, (add_sent_snapshots_system, receive_acks_system)
+            .chain().run_if(is_server).in_set(NetworkRecv::Synchronise));
+
+        app.add_systems(PreUpdate, apply_snapshots_system
+            .after(apply_system).run_if(is_client).in_set(NetworkRecv::Synchronise));
+    }
+}
+
+/// The channel used for snapshots.
+pub struct SnapshotChannel;
+
+/// The channel used by clients to acknowledge snapshots.
+pub(crate) struct SnapshotAckChannel;
+
+#[derive(Clone, Copy, Resource)]
+pub(crate) struct SnapshotChannelIds {
+    pub snapshots: ChannelId,
+    pub acks: ChannelId,
+}
+
+/// Components registered for snapshots, in the order they were registered.
+#[derive(Default, Resource)]
+pub(crate) struct SnapshotRegistry {
+    components: Vec<ComponentRegistration>,
+}
+
+impl SnapshotRegistry {
+    pub fn get(&self, index: u32) -> Option<&ComponentRegistration> {
+        self.components.get(index as usize)
+    }
+}
+
+#[derive(Resource)]
+pub(crate) struct SnapshotSerialiser<C> {
+    pub index: u32,
+    pub serialiser: Serialiser<C>,
+}
+
+fn insert_component<C: Component>(entity: &mut EntityWorldMut, bytes: &[u8], _map: &NetworkEntityMap) -> bool {
+    let serialiser = entity.world().resource::<SnapshotSerialiser<C>>().serialiser;
+    let Some(component) = serialiser.deserialise(bytes) else { return false };
+    entity.insert(component);
+    return true;
+}
+
+pub(crate) fn register_snapshot_component<C: Component>(app: &mut App, serialiser: Serialiser<C>) -> &mut App {
+    if app.world().contains_resource::<SnapshotSerialiser<C>>() || app.world().contains_resource::<ComponentSerialiser<C>>() {
+        panic!("A component was registered for replication twice: {}", type_name::<C>());
+    }
+
+    let mut registry = app.world_mut()
+        .get_resource_mut::<SnapshotRegistry>()
+        .expect("SnapshotPlugin must be added before registering snapshot components");
+
+    let index = u32::try_from(registry.components.len()).unwrap();
+    registry.components.push(ComponentRegistration {
+        insert: insert_component::<C>,
+        remove: remove_component::<C>,
+        owned: false,
+    });
+
+    app.insert_resource(SnapshotSerialiser { index, serialiser });
+    app.add_systems(PostUpdate, collect_snapshot_system::<C>
+        .in_set(ReplicationSystems::Collect));
+
+    return app;
+}
+
+#[test]
+fn snapshot_test() {
+    use bevy_stardust::connections::{NetworkRole, PeerMessages};
+    use crate::prelude::*;
+
+    #[derive(Debug, PartialEq, Component)]
+    struct Position { x: u32, y: u32 }
+
+    fn app(role: NetworkRole) -> App {
+        let mut app = App::new();
+        app.add_plugins((StardustPlugin, ReplicationPlugin::default(), SnapshotPlugin::default()));
+        app.snapshot_component::<Position>(Serialiser::new(
+            |position, buf| {
+                buf.extend_from_slice(&position.x.to_le_bytes());
+                buf.extend_from_slice(&position.y.to_le_bytes());
+            },
+            |bytes| Some(Position {
+                x: u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?),
+                y: u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?),
+            }),
+        ));
+
+        // Stand in for a transport layer, taking messages before they're cleared
+        app.init_resource::<Sent>();
+        app.add_systems(PostUpdate, (|mut sent: ResMut<Sent>, query: Query<&PeerMessages<Outgoing>>| {
+            for messages in query.iter() {
+                for (channel, iter) in messages.iter() {
+                    sent.0.extend(iter.map(|message| ChannelMessage { channel, message }));
+                }
+            }
+        }).in_set(NetworkSend::Transmit));
+
+        app.insert_resource(role);
+        app.finish();
+        app.cleanup();
+        app
+    }
+
+    #[derive(Default, Resource)]
+    struct Sent(Vec<ChannelMessage>);
+
+    let mut server = app(NetworkRole::Server);
+    let mut client = app(NetworkRole::Client);
+
+    let peer = (Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new());
+    let client_peer = server.world_mut().spawn(peer).id();
+    let peer = (Peer::new(), PeerLifestage::Established, PeerMessages::<Incoming>::new(), PeerMessages::<Outgoing>::new());
+    let server_peer = client.world_mut().spawn(peer).id();
+
+    let snapshot_channel = server.world().resource::<SnapshotChannelIds>().snapshots;
+
+    // Returns the snapshot messages that were sent, optionally losing them
+    let transfer = |server: &mut App, client: &mut App, lose: bool| {
+        server.update();
+        let mut sent = std::mem::take(&mut server.world_mut().resource_mut::<Sent>().0);
+        let snapshots = sent.iter().filter(|message| message.channel == snapshot_channel).map(|message| message.message.len()).collect::<Vec<_>>();
+        if lose { sent.retain(|message| message.channel != snapshot_channel) }
+        client.world_mut().get_mut::<PeerMessages<Incoming>>(server_peer).unwrap().push_many(sent);
+        client.update();
+
+        let acks = std::mem::take(&mut client.world_mut().resource_mut::<Sent>().0);
+        server.world_mut().get_mut::<PeerMessages<Incoming>>(client_peer).unwrap().push_many(acks);
+        snapshots
+    };
+
+    let entity = server.world_mut().spawn((Position { x: 1, y: 2 }, Replicated)).id();
+    let full = transfer(&mut server, &mut client, false);
+    let mut query = client.world_mut().query::<&Position>();
+    assert_eq!(query.single(client.world()), &Position { x: 1, y: 2 });
+
+    // Only the changed field is sent once the first snapshot was acknowledged
+    server.world_mut().get_mut::<Position>(entity).unwrap().y = 3;
+    let delta = transfer(&mut server, &mut client, false);
+    assert!(delta[0] < full[0]);
+    assert_eq!(query.single(client.world()), &Position { x: 1, y: 3 });
+
+    // Nothing is sent while nothing changes
+    assert!(transfer(&mut server, &mut client, false).is_empty());
+
+    // Lost snapshots are covered by the next one, since it's relative to the last acknowledged one
+    server.world_mut().get_mut::<Position>(entity).unwrap().x = 4;
+    transfer(&mut server, &mut client, true);
+    server.world_mut().get_mut::<Position>(entity).unwrap().y = 5;
+    transfer(&mut server, &mut client, false);
+    assert_eq!(query.single(client.world()), &Position { x: 4, y: 5 });
+}
@@ -0,0 +1,136 @@
+use std::collections::VecDeque;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{Infraction, PeerReputation};
+use bevy_stardust_extras::numbers::Sequence;
+use bytes::Bytes;
+use crate::entities::NetworkEntityMap;
+use super::delta::{decode, read_header, Snapshot};
+use super::{SnapshotChannelIds, SnapshotRegistry, HISTORY_LEN};
+
+/// Snapshots received from a peer, kept so that later snapshots can be decoded relative to them.
+#[derive(Default, Component)]
+pub(crate) struct ReceivedSnapshots {
+    history: VecDeque<(Sequence<u16>, Snapshot)>,
+
+    /// The state that has been applied to the world, which can differ from the newest snapshot
+    /// if some of its entities haven't been spawned yet.
+    applied: Snapshot,
+
+    /// Set if some of the newest snapshot couldn't be applied yet.
+    incomplete: bool,
+}
+
+impl ReceivedSnapshots {
+    /// Decodes a snapshot message, returning its sequence number if it was newer than any before it.
+    fn receive(&mut self, mut message: Bytes) -> Result<Option<Sequence<u16>>, ()> {
+        let header = read_header(&mut message)?;
+        if self.history.back().is_some_and(|(newest, _)| header.sequence <= *newest) { return Ok(None) }
+
+        let empty = Snapshot::default();
+        let base = match header.baseline {
+            None => &empty,
+            Some(baseline) => match self.history.iter().find(|(sequence, _)| *sequence == baseline) {
+                Some((_, snapshot)) => snapshot,
+
+                // The baseline is too old, so the server will send a full snapshot when it notices
+                None => return Ok(None),
+            },
+        };
+
+        let snapshot = decode(message, base)?;
+        if self.history.len() == HISTORY_LEN { self.history.pop_front(); }
+        self.history.push_back((header.sequence, snapshot));
+        return Ok(Some(header.sequence));
+    }
+}
+
+pub(crate) fn apply_snapshots_system(world: &mut World) {
+    let channels = *world.resource::<SnapshotChannelIds>();
+
+    let mut query = world.query_filtered::<(Entity, &PeerMessages<Incoming>, Option<&ReceivedSnapshots>), With<Peer>>();
+    let received: Vec<(Entity, Vec<Bytes>)> = query.iter(world)
+        .filter(|(_, messages, snapshots)| messages.iter_channel(channels.snapshots).len() > 0
+            || snapshots.is_some_and(|snapshots| snapshots.incomplete))
+        .map(|(entity, messages, _)| (entity, messages.iter_channel(channels.snapshots).map(Bytes::from).collect()))
+        .collect();
+
+    if received.is_empty() { return }
+
+    world.resource_scope(|world, registry: Mut<SnapshotRegistry>| {
+        for (peer, messages) in received {
+            let mut peer_entity = world.entity_mut(peer);
+            let mut snapshots = peer_entity.take::<ReceivedSnapshots>().unwrap_or_default();
+            let map = peer_entity.take::<NetworkEntityMap>().unwrap_or_default();
+
+            // Only the newest snapshot is applied, but all of them can be baselines
+            let mut newest = None;
+            let mut malformed = false;
+            for message in messages {
+                match snapshots.receive(message) {
+                    Ok(Some(sequence)) => newest = Some(sequence),
+                    Ok(None) => {},
+                    Err(()) => malformed = true,
+                }
+            }
+
+            if newest.is_some() || snapshots.incomplete {
+                let ReceivedSnapshots { history, applied, incomplete } = &mut snapshots;
+                let (_, snapshot) = history.back().unwrap();
+                match apply_snapshot(world, &registry, &map, applied, snapshot) {
+                    Ok(complete) => *incomplete = !complete,
+                    Err(()) => malformed = true,
+                }
+            }
+
+            if malformed {
+                if let Some(mut reputation) = world.get_mut::<PeerReputation>(peer) {
+                    reputation.report(Infraction::MalformedMessage { channel: channels.snapshots });
+                }
+            }
+
+            if let (Some(sequence), Some(mut outgoing)) = (newest, world.get_mut::<PeerMessages<Outgoing>>(peer)) {
+                outgoing.push_one(ChannelMessage {
+                    channel: channels.acks,
+                    message: Message::from_bytes(Bytes::copy_from_slice(&sequence.inner().to_le_bytes())),
+                });
+            }
+
+            world.entity_mut(peer).insert((snapshots, map));
+        }
+    });
+}
+
+/// Applies the differences between `applied` and `snapshot` to the world.
+/// Returns `false` if some of the snapshot's entities don't exist yet.
+fn apply_snapshot(
+    world: &mut World,
+    registry: &SnapshotRegistry,
+    map: &NetworkEntityMap,
+    applied: &mut Snapshot,
+    snapshot: &Snapshot,
+) -> Result<bool, ()> {
+    let mut complete = true;
+    let removed: Vec<_> = applied.0.keys().filter(|key| !snapshot.0.contains_key(*key)).copied().collect();
+    for key in removed {
+        applied.0.remove(&key);
+        let registration = registry.get(key.1).ok_or(())?;
+        let Some(entity) = map.entity(key.0) else { continue };
+        let Ok(mut entity) = world.get_entity_mut(entity) else { continue };
+        (registration.remove)(&mut entity);
+    }
+
+    for (key, data) in snapshot.0.iter() {
+        if applied.0.get(key) == Some(data) { continue }
+        let registration = registry.get(key.1).ok_or(())?;
+
+        // The entity may not have been spawned by the replication channel yet,
+        // in which case it's tried again next tick
+        let Some(entity) = map.entity(key.0) else { complete = false; continue };
+        let Ok(mut entity) = world.get_entity_mut(entity) else { continue };
+        if !(registration.insert)(&mut entity, data, map) { return Err(()) }
+        applied.0.insert(*key, data.clone());
+    }
+
+    return Ok(complete);
+}
@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+use bevy_ecs::prelude::*;
+use bevy_stardust::prelude::*;
+use bevy_stardust::connections::{Infraction, PeerReputation};
+use bevy_stardust_extras::numbers::Sequence;
+use bytes::{Bytes, BytesMut};
+use crate::entities::NetworkEntityMap;
+use crate::Replicated;
+use super::delta::{encode, Snapshot};
+use super::{SnapshotAckChannel, SnapshotChannel, SnapshotSerialiser, HISTORY_LEN};
+
+/// The snapshot components of all replicated entities this tick.
+#[derive(Default, Resource)]
+pub(crate) struct SnapshotBuffer(Vec<(Entity, u32, Bytes)>);
+
+/// Snapshots sent to a peer, kept until they're too old to be used as a baseline.
+#[derive(Default, Component)]
+pub(crate) struct SentSnapshots {
+    next: Sequence<u16>,
+    history: VecDeque<(Sequence<u16>, Snapshot)>,
+    acked: Option<Sequence<u16>>,
+}
+
+impl SentSnapshots {
+    /// Encodes `snapshot` relative to the newest snapshot the peer acknowledged.
+    /// Returns `None` if nothing changed since then.
+    fn push(&mut self, snapshot: Snapshot) -> Option<Bytes> {
+        // If the acknowledged snapshot is no longer in the history, too many were lost,
+        // and the peer is sent the full snapshot instead
+        let baseline = self.acked
+            .and_then(|acked| self.history.iter().find(|(sequence, _)| *sequence == acked))
+            .map(|(sequence, snapshot)| (*sequence, snapshot));
+
+        let message = encode(self.next, baseline, &snapshot)?;
+
+        if self.history.len() == HISTORY_LEN { self.history.pop_front(); }
+        self.history.push_back((self.next, snapshot));
+        self.next.increment();
+
+        return Some(message);
+    }
+
+    fn acknowledge(&mut self, sequence: Sequence<u16>) {
+        if self.acked.is_some_and(|acked| acked >= sequence) { return }
+        if !self.history.iter().any(|(sent, _)| *sent == sequence) { return }
+        self.acked = Some(sequence);
+    }
+}
+
+pub(crate) fn add_sent_snapshots_system(
+    mut commands: Commands,
+    query: Query<Entity, (Added<Peer>, Without<SentSnapshots>)>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).insert(SentSnapshots::default());
+    }
+}
+
+pub(crate) fn receive_acks_system(
+    channel: ChannelData<SnapshotAckChannel>,
+    mut peers: Query<(&PeerMessages<Incoming>, &mut SentSnapshots, Option<&mut PeerReputation>)>,
+) {
+    for (messages, mut sent, mut reputation) in peers.iter_mut() {
+        for message in messages.iter_channel(channel.id()) {
+            match <[u8; 2]>::try_from(message.as_slice()) {
+                Ok(bytes) => sent.acknowledge(Sequence::from(u16::from_le_bytes(bytes))),
+                Err(_) => if let Some(reputation) = reputation.as_mut() {
+                    reputation.report(Infraction::MalformedMessage { channel: channel.id() });
+                },
+            }
+        }
+    }
+}
+
+pub(crate) fn prepare_snapshot_system(
+    mut buffer: ResMut<SnapshotBuffer>,
+) {
+    buffer.0.clear();
+}
+
+pub(crate) fn collect_snapshot_system<C: Component>(
+    serialiser: Res<SnapshotSerialiser<C>>,
+    mut buffer: ResMut<SnapshotBuffer>,
+    query: Query<(Entity, &C), With<Replicated>>,
+    mut scratch: Local<BytesMut>,
+) {
+    for (entity, value) in query.iter() {
+        serialiser.serialiser.serialise(value, &mut scratch);
+        buffer.0.push((entity, serialiser.index, scratch.split().freeze()));
+    }
+}
+
+type SnapshotPeer<'a> = (&'a mut PeerMessages<Outgoing>, &'a NetworkEntityMap, &'a mut SentSnapshots);
+
+pub(crate) fn send_snapshot_system(
+    channel: ChannelData<SnapshotChannel>,
+    buffer: Res<SnapshotBuffer>,
+    mut peers: Query<SnapshotPeer, (With<Peer>, Established)>,
+) {
+    for (mut messages, map, mut sent) in peers.iter_mut() {
+        // Entities that haven't been spawned on the peer yet don't have an id
+        let snapshot = Snapshot(buffer.0.iter()
+            .filter_map(|(entity, component, data)| Some(((map.id(*entity)?, *component), data.clone())))
+            .collect());
+
+        let Some(message) = sent.push(snapshot) else { continue };
+        messages.push_one(ChannelMessage {
+            channel: channel.id(),
+            message: Message::from_bytes(message),
+        });
+    }
+}
@@ -0,0 +1,322 @@
+use std::sync::Arc;
+
+#[cfg(feature="reflect")]
+use bevy_reflect::Reflect;
+
+/// Compression applied to messages on a channel.
+///
+/// Messages are compressed in [`NetworkSend::Prepare`](crate::scheduling::NetworkSend::Prepare),
+/// and decompressed in [`NetworkRecv::Validate`](crate::scheduling::NetworkRecv::Validate),
+/// so compressed messages are only seen by transport layers, and by diagnostics
+/// that run in [`NetworkSend::Diagnostics`](crate::scheduling::NetworkSend::Diagnostics) or before `Validate`.
+/// Limits such as [`max_message_size`](super::ChannelConfiguration::max_message_size)
+/// apply to the uncompressed size.
+///
+/// Each message has a one byte header saying whether it's compressed. Messages smaller than
+/// `threshold`, or that don't get any smaller when compressed, are sent as they are.
+///
+/// Compression requires the `compression` feature. Adding a channel that uses compression
+/// without the feature enabled panics when the app is finished.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature="reflect", derive(Reflect), reflect(Debug, PartialEq, Hash))]
+pub struct ChannelCompression {
+    /// The compression algorithm to use.
+    pub algorithm: CompressionAlgorithm,
+
+    /// The size, in bytes, below which messages are sent uncompressed.
+    pub threshold: usize,
+
+    /// A dictionary shared by both sides of the connection, if any.
+    /// Both sides must use exactly the same dictionary.
+    #[cfg_attr(feature="reflect", reflect(ignore))]
+    pub dictionary: Option<CompressionDictionary>,
+}
+
+/// A compression algorithm used by [`ChannelCompression`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature="reflect", derive(Reflect), reflect(Debug, PartialEq, Hash))]
+#[non_exhaustive]
+pub enum CompressionAlgorithm {
+    /// LZ4, which is very fast, but doesn't compress as well.
+    Lz4,
+
+    /// Zstandard, which compresses better, but is slower.
+    Zstd {
+        /// The compression level, from `1` to `22`.
+        /// Higher levels compress better, but are slower.
+        level: i32,
+    },
+}
+
+/// A dictionary used by [`ChannelCompression`].
+///
+/// Dictionaries let small messages compress much better, by providing common
+/// sequences of bytes up front, instead of them being learned from each message.
+/// A good dictionary can be made with [`train`](Self::train), from samples of real messages.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompressionDictionary(Arc<[u8]>);
+
+impl CompressionDictionary {
+    /// Creates a dictionary from raw bytes.
+    pub fn from_bytes(bytes: impl Into<Arc<[u8]>>) -> Self {
+        Self(bytes.into())
+    }
+
+    /// Trains a dictionary of up to `max_size` bytes from `samples` of typical messages.
+    ///
+    /// This works best with hundreds of samples, and a `max_size` of around 100 times
+    /// smaller than the total size of the samples.
+    #[cfg(feature="compression")]
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> std::io::Result<Self> {
+        zstd::dict::from_samples(samples, max_size).map(Self::from_bytes)
+    }
+
+    /// Returns the dictionary as a slice of bytes.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(feature="compression")]
+pub(crate) use systems::{compress_outgoing_system, decompress_incoming_system};
+
+#[cfg(feature="compression")]
+mod systems {
+    use std::io;
+    use bevy_ecs::prelude::*;
+    use bytes::{BufMut, Bytes, BytesMut};
+    use hashbrown::HashMap;
+    use crate::prelude::*;
+    use crate::connections::{Infraction, PeerReputation};
+    use super::*;
+
+    /// The header of messages sent as they are.
+    const RAW: u8 = 0;
+
+    /// The header of compressed messages, followed by the uncompressed length as a little-endian `u32`.
+    const COMPRESSED: u8 = 1;
+
+    /// The largest uncompressed size that is accepted on channels without a
+    /// [`max_message_size`](ChannelConfiguration::max_message_size).
+    /// Small messages can't claim sizes this large, since claimed sizes are also
+    /// limited by how much the codec could possibly expand the compressed body.
+    const DEFAULT_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+    enum Codec {
+        Lz4 {
+            dictionary: Option<CompressionDictionary>,
+        },
+        Zstd {
+            compressor: zstd::bulk::Compressor<'static>,
+            decompressor: zstd::bulk::Decompressor<'static>,
+        },
+    }
+
+    impl Codec {
+        fn new(config: &ChannelCompression) -> io::Result<Self> {
+            let dictionary = config.dictionary.as_ref().map(|d| d.as_bytes()).unwrap_or(&[]);
+            match config.algorithm {
+                CompressionAlgorithm::Lz4 => Ok(Self::Lz4 { dictionary: config.dictionary.clone() }),
+                CompressionAlgorithm::Zstd { level } => Ok(Self::Zstd {
+                    compressor: zstd::bulk::Compressor::with_dictionary(level, dictionary)?,
+                    decompressor: zstd::bulk::Decompressor::with_dictionary(dictionary)?,
+                }),
+            }
+        }
+
+        fn compress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+            match self {
+                Codec::Lz4 { dictionary: Some(dictionary) } => Ok(lz4_flex::block::compress_with_dict(data, dictionary.as_bytes())),
+                Codec::Lz4 { dictionary: None } => Ok(lz4_flex::block::compress(data)),
+                Codec::Zstd { compressor, .. } => compressor.compress(data),
+            }
+        }
+
+        /// The most that any input can grow by when it's decompressed.
+        /// Uncompressed lengths that are larger than this allows are lies.
+        fn max_expansion(&self) -> usize {
+            match self {
+                // Each extra byte of a match's length adds at most 255 bytes
+                Codec::Lz4 { .. } => 255,
+
+                // A 4 byte RLE block can repeat one byte up to 128 KiB times
+                Codec::Zstd { .. } => 32 * 1024,
+            }
+        }
+
+        fn decompress(&mut self, data: &[u8], len: usize) -> Option<Vec<u8>> {
+            let decompressed = match self {
+                Codec::Lz4 { dictionary: Some(dictionary) } => lz4_flex::block::decompress_with_dict(data, len, dictionary.as_bytes()).ok()?,
+                Codec::Lz4 { dictionary: None } => lz4_flex::block::decompress(data, len).ok()?,
+                Codec::Zstd { decompressor, .. } => decompressor.decompress(data, len).ok()?,
+            };
+
+            // The length is part of the header, so it has to match exactly
+            if decompressed.len() != len { return None }
+            return Some(decompressed);
+        }
+    }
+
+    pub(crate) struct ChannelCodec {
+        codec: Codec,
+        threshold: usize,
+        max_size: usize,
+    }
+
+    fn collect_codecs(channels: &Channels) -> HashMap<ChannelId, ChannelCodec> {
+        (0..channels.count())
+            .map(ChannelId::from)
+            .filter_map(|id| {
+                let config = channels.config(id)?;
+                let compression = config.compression.as_ref()?;
+                let codec = Codec::new(compression)
+                    .unwrap_or_else(|err| panic!("Failed to create compressor for {}: {err}", channels.metadata(id).unwrap().type_name));
+
+                Some((id, ChannelCodec {
+                    codec,
+                    threshold: compression.threshold,
+                    max_size: config.max_message_size.unwrap_or(DEFAULT_MAX_SIZE),
+                }))
+            })
+            .collect()
+    }
+
+    fn encode(channel: &mut ChannelCodec, message: &Message) -> Message {
+        if message.len() >= channel.threshold && message.len() <= u32::MAX as usize {
+            if let Ok(compressed) = channel.codec.compress(message.as_slice()) {
+                // Only use the compressed form if it's smaller, including the length
+                if compressed.len() + 4 < message.len() {
+                    let mut buf = BytesMut::with_capacity(5 + compressed.len());
+                    buf.put_u8(COMPRESSED);
+                    buf.put_u32_le(message.len() as u32);
+                    buf.put_slice(&compressed);
+                    return Message::from_bytes(buf.freeze());
+                }
+            }
+        }
+
+        let mut buf = BytesMut::with_capacity(1 + message.len());
+        buf.put_u8(RAW);
+        buf.put_slice(message.as_slice());
+        return Message::from_bytes(buf.freeze());
+    }
+
+    enum DecodeError {
+        Malformed,
+        TooLarge,
+    }
+
+    fn decode(channel: &mut ChannelCodec, message: &Message) -> Result<Message, DecodeError> {
+        let (&header, body) = message.as_slice().split_first().ok_or(DecodeError::Malformed)?;
+
+        match header {
+            RAW => Ok(Message::from(Bytes::from(message.clone()).slice(1..))),
+            COMPRESSED => {
+                let (len, body) = body.split_first_chunk::<4>().ok_or(DecodeError::Malformed)?;
+                let len = u32::from_le_bytes(*len) as usize;
+                if len > channel.max_size { return Err(DecodeError::TooLarge) }
+
+                // The buffer is allocated up front, so don't trust lengths the body can't reach
+                if len > body.len().saturating_mul(channel.codec.max_expansion()) { return Err(DecodeError::TooLarge) }
+
+                let decompressed = channel.codec.decompress(body, len).ok_or(DecodeError::Malformed)?;
+                Ok(Message::from(Bytes::from(decompressed)))
+            },
+            _ => Err(DecodeError::Malformed),
+        }
+    }
+
+    pub(crate) fn compress_outgoing_system(
+        channels: Channels,
+        mut codecs: Local<Option<HashMap<ChannelId, ChannelCodec>>>,
+        mut query: Query<&mut PeerMessages<Outgoing>>,
+    ) {
+        // Channels can't change after startup, so the codecs are only created once
+        let codecs = codecs.get_or_insert_with(|| collect_codecs(&channels));
+        if codecs.is_empty() { return }
+
+        for mut messages in query.iter_mut() {
+            for (channel, codec) in codecs.iter_mut() {
+                let iter = messages.iter_channel(*channel);
+                if iter.len() == 0 { continue }
+
+                let encoded: Vec<_> = iter.map(|message| encode(codec, &message)).collect();
+                messages.retain(|c, _| c != *channel);
+                messages.push_channel(*channel, encoded);
+            }
+        }
+    }
+
+    pub(crate) fn decompress_incoming_system(
+        channels: Channels,
+        mut codecs: Local<Option<HashMap<ChannelId, ChannelCodec>>>,
+        mut query: Query<(&mut PeerMessages<Incoming>, Option<&mut PeerReputation>)>,
+    ) {
+        let codecs = codecs.get_or_insert_with(|| collect_codecs(&channels));
+        if codecs.is_empty() { return }
+
+        for (mut messages, mut reputation) in query.iter_mut() {
+            for (channel, codec) in codecs.iter_mut() {
+                let iter = messages.iter_channel(*channel);
+                if iter.len() == 0 { continue }
+
+                let mut infraction = None;
+                let decoded: Vec<_> = iter.filter_map(|message| match decode(codec, &message) {
+                    Ok(message) => Some(message),
+                    Err(err) => { infraction.get_or_insert(err); None },
+                }).collect();
+
+                messages.retain(|c, _| c != *channel);
+                messages.push_channel(*channel, decoded);
+
+                // Only report each channel once per update
+                if let (Some(infraction), Some(reputation)) = (infraction, reputation.as_mut()) {
+                    reputation.report(match infraction {
+                        DecodeError::TooLarge => Infraction::OversizedMessage { channel: *channel },
+                        DecodeError::Malformed => Infraction::MalformedMessage { channel: *channel },
+                    });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compression_round_trip_test() {
+        let samples: Vec<Vec<u8>> = (0..256u32)
+            .map(|i| format!("{{\"entity\":{i},\"position\":[{},{},0.0],\"health\":100}}", i * 3, i * 7).into_bytes())
+            .collect();
+
+        let dictionary = CompressionDictionary::train(&samples, 1024).unwrap();
+        let large = Message::from(Bytes::from(b"world state ".repeat(64)));
+        let small = Message::from_static(b"tiny");
+
+        for algorithm in [CompressionAlgorithm::Lz4, CompressionAlgorithm::Zstd { level: 3 }] {
+            for dictionary in [None, Some(dictionary.clone())] {
+                let config = ChannelCompression { algorithm, threshold: 16, dictionary };
+                let mut channel = ChannelCodec { codec: Codec::new(&config).unwrap(), threshold: 16, max_size: 1024 };
+
+                // Large messages are compressed, and small ones are sent as they are
+                let encoded = encode(&mut channel, &large);
+                assert_eq!(encoded.as_slice()[0], COMPRESSED);
+                assert!(encoded.len() < large.len() / 4);
+                assert_eq!(decode(&mut channel, &encoded).ok().unwrap().as_slice(), large.as_slice());
+
+                let encoded = encode(&mut channel, &small);
+                assert_eq!(encoded.as_slice(), b"\x00tiny");
+                assert_eq!(decode(&mut channel, &encoded).ok().unwrap().as_slice(), b"tiny");
+
+                // Small messages that claim to decompress to huge sizes are rejected before decompressing
+                channel.max_size = DEFAULT_MAX_SIZE;
+                assert!(matches!(decode(&mut channel, &Message::from_static(b"\x01\x00\x00\x00\x01garbage")), Err(DecodeError::TooLarge)));
+
+                // Messages that would decompress past the limit are rejected before decompressing
+                channel.max_size = large.len() - 1;
+                let encoded = encode(&mut channel, &large);
+                assert!(matches!(decode(&mut channel, &encoded), Err(DecodeError::TooLarge)));
+                assert!(matches!(decode(&mut channel, &Message::from_static(b"\x01\x04\x00\x00\x00garbage")), Err(DecodeError::Malformed)));
+                assert!(matches!(decode(&mut channel, &Message::from_static(b"")), Err(DecodeError::Malformed)));
+            }
+        }
+    }
+}
@@ -1,9 +1,25 @@
 #[cfg(feature="reflect")]
-use bevy_reflect::Reflect;
+use bevy_reflect::{Reflect, std_traits::ReflectDefault};
+use crate::connections::NetworkRole;
+use super::ChannelCompression;
 
 /// Configuration for a channel.
+///
+/// The [`Default`] configuration is reliable and ordered, with a priority of `0`,
+/// no limits or compression, and can be sent on by both sides. Most channels only need to set
+/// some fields, and can use [`Default::default`] for the rest:
+///
+/// ```
+/// # use bevy_stardust::prelude::*;
+/// # use bevy_stardust::channels::ChannelRateLimit;
+/// let config = ChannelConfiguration {
+///     consistency: MessageConsistency::UnreliableUnordered,
+///     rate_limit: Some(ChannelRateLimit { messages: Some(60), bytes: None }),
+///     ..Default::default()
+/// };
+/// ```
 #[derive(Debug, Clone, Hash)]
-#[cfg_attr(feature="reflect", derive(Reflect), reflect(Debug, Hash))]
+#[cfg_attr(feature="reflect", derive(Reflect), reflect(Debug, Default, Hash))]
 pub struct ChannelConfiguration {
     /// Guarantees that the transport layer must make
     /// for messages sent on this channel. See the
@@ -14,6 +30,87 @@ pub struct ChannelConfiguration {
     /// Transport values will try to send messages on
     /// channels with higher `priority` values first.
     pub priority: u32,
+
+    /// Limits on how quickly each peer can send messages on this channel.
+    /// Messages over the limit are dropped, and reported to the peer's
+    /// [`PeerReputation`](crate::connections::PeerReputation) as spam.
+    pub rate_limit: Option<ChannelRateLimit>,
+
+    /// The largest message, in bytes, that can be sent on this channel, if any.
+    ///
+    /// Incoming messages over the limit are dropped, and reported to the peer's
+    /// [`PeerReputation`](crate::connections::PeerReputation) as a protocol violation.
+    /// Outgoing messages over the limit are rejected by
+    /// [`PeerMessages::try_push`](crate::connections::PeerMessages::try_push),
+    /// and dropped before being transmitted if they were pushed some other way.
+    pub max_message_size: Option<usize>,
+
+    /// Which side of the connection may send messages on this channel.
+    /// See the documentation of [`ChannelDirection`].
+    pub direction: ChannelDirection,
+
+    /// Compression applied to messages on this channel, if any.
+    /// See the documentation of [`ChannelCompression`].
+    pub compression: Option<ChannelCompression>,
+}
+
+impl Default for ChannelConfiguration {
+    fn default() -> Self {
+        Self {
+            consistency: MessageConsistency::ReliableOrdered,
+            priority: 0,
+            rate_limit: None,
+            max_message_size: None,
+            direction: ChannelDirection::Bidirectional,
+            compression: None,
+        }
+    }
+}
+
+/// Limits on how quickly each peer can send messages on a channel.
+///
+/// Each limit allows a burst of up to one second's worth of messages,
+/// after which messages are accepted at the given rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature="reflect", derive(Reflect), reflect(Debug, PartialEq, Hash))]
+pub struct ChannelRateLimit {
+    /// The maximum number of messages per second, if any.
+    pub messages: Option<u32>,
+
+    /// The maximum number of bytes per second, if any.
+    pub bytes: Option<u32>,
+}
+
+/// Which side of a connection may send messages on a channel.
+///
+/// Directions are only enforced if the [`NetworkRole`] resource exists, since
+/// otherwise there's no way to tell which side the app is on. Incoming messages
+/// on a channel the remote side isn't allowed to send on are dropped, and reported to the peer's
+/// [`PeerReputation`](crate::connections::PeerReputation) as a protocol violation.
+/// In debug builds, pushing outgoing messages to a channel the app isn't allowed to send on panics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature="reflect", derive(Reflect), reflect(Debug, Default, PartialEq, Hash))]
+pub enum ChannelDirection {
+    /// Both sides may send messages.
+    #[default]
+    Bidirectional,
+
+    /// Only the server may send messages.
+    ServerToClient,
+
+    /// Only the client may send messages.
+    ClientToServer,
+}
+
+impl ChannelDirection {
+    /// Returns `true` if `role` is allowed to send messages on the channel.
+    pub fn allows(&self, role: NetworkRole) -> bool {
+        match self {
+            ChannelDirection::Bidirectional => true,
+            ChannelDirection::ServerToClient => role == NetworkRole::Server,
+            ChannelDirection::ClientToServer => role == NetworkRole::Client,
+        }
+    }
 }
 
 /// Reliability and ordering guarantees.
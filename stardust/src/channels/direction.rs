@@ -0,0 +1,104 @@
+use bevy_ecs::prelude::*;
+use hashbrown::HashSet;
+use crate::prelude::*;
+use crate::connections::{Infraction, NetworkRole, PeerReputation};
+
+/// Returns the channels that `role` isn't allowed to send on.
+fn forbidden_channels(channels: &Channels, role: NetworkRole) -> HashSet<ChannelId> {
+    (0..channels.count())
+        .map(ChannelId::from)
+        .filter(|id| channels.config(*id).is_some_and(|config| !config.direction.allows(role)))
+        .collect()
+}
+
+pub(crate) fn incoming_direction_system(
+    role: Res<NetworkRole>,
+    channels: Channels,
+    mut forbidden: Local<Option<(NetworkRole, HashSet<ChannelId>)>>,
+    mut query: Query<(&mut PeerMessages<Incoming>, Option<&mut PeerReputation>)>,
+) {
+    // The role can change, but channels can't, so the set is only rebuilt when the role does
+    if forbidden.as_ref().is_none_or(|(cached, _)| *cached != *role) {
+        *forbidden = Some((*role, forbidden_channels(&channels, role.remote())));
+    }
+
+    let (_, forbidden) = forbidden.as_ref().unwrap();
+    if forbidden.is_empty() { return }
+
+    for (mut messages, reputation) in query.iter_mut() {
+        if messages.count() == 0 { continue }
+
+        let mut violated = Vec::new();
+        messages.retain(|channel, _| {
+            if !forbidden.contains(&channel) { return true }
+            if !violated.contains(&channel) { violated.push(channel) }
+            return false;
+        });
+
+        if let Some(mut reputation) = reputation {
+            for channel in violated {
+                reputation.report(Infraction::ForbiddenChannel { channel });
+            }
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+pub(crate) fn outgoing_direction_system(
+    role: Res<NetworkRole>,
+    channels: Channels,
+    query: Query<&PeerMessages<Outgoing>>,
+) {
+    for messages in query.iter() {
+        for (channel, _) in messages.iter() {
+            let Some(config) = channels.config(channel) else { continue };
+            assert!(config.direction.allows(*role),
+                "{:?} tried to send a message on {}, which only allows {:?}",
+                *role, channels.metadata(channel).unwrap().type_name, config.direction);
+        }
+    }
+}
+
+#[test]
+fn channel_direction_test() {
+    use bevy_app::prelude::*;
+    use super::ChannelDirection;
+
+    struct FromServer;
+    struct FromClient;
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+    let config = |direction| ChannelConfiguration {
+        consistency: MessageConsistency::UnreliableUnordered,
+        priority: 0,
+        direction,
+        ..Default::default()
+    };
+
+    app.add_channel::<FromServer>(config(ChannelDirection::ServerToClient));
+    app.add_channel::<FromClient>(config(ChannelDirection::ClientToServer));
+
+    app.insert_resource(NetworkRole::Server);
+    app.insert_resource(crate::connections::ReputationPolicy::default());
+    app.finish();
+    app.cleanup();
+
+    let (from_server, from_client) = {
+        let channels = app.world().resource::<super::registry::ChannelRegistryFinished>();
+        (channels.id(std::any::TypeId::of::<FromServer>()).unwrap(), channels.id(std::any::TypeId::of::<FromClient>()).unwrap())
+    };
+
+    let mut incoming = PeerMessages::<Incoming>::new();
+    incoming.push_channel(from_server, [Message::from_static(b"forbidden")]);
+    incoming.push_channel(from_client, [Message::from_static(b"allowed")]);
+    let peer = app.world_mut().spawn((Peer::new(), incoming, PeerReputation::default())).id();
+
+    // Only run the receiving half of the update, since the queues are cleared afterwards
+    app.world_mut().run_schedule(PreUpdate);
+    let incoming = app.world().get::<PeerMessages<Incoming>>(peer).unwrap();
+    assert_eq!(incoming.iter_channel(from_server).len(), 0);
+    assert_eq!(incoming.iter_channel(from_client).len(), 1);
+    let reputation = app.world().get::<PeerReputation>(peer).unwrap();
+    assert_eq!(reputation.history().collect::<Vec<_>>(), vec![&Infraction::ForbiddenChannel { channel: from_server }]);
+}
@@ -46,6 +46,7 @@
 //! let config = ChannelConfiguration {
 //!     consistency: MessageConsistency::ReliableOrdered,
 //!     priority: 128,
+//!     ..Default::default()
 //! };
 //! # }
 //! ```
@@ -79,6 +80,7 @@
 //!     app.add_channel::<MyChannel>(ChannelConfiguration {
 //!         consistency: MessageConsistency::ReliableOrdered,
 //!         priority: 128,
+//!         ..Default::default()
 //!     });
 //! }
 //! ```
@@ -129,6 +131,7 @@
 //!     app.add_channel::<MovementEvent>(ChannelConfiguration {
 //!         consistency: MessageConsistency::UnreliableUnordered,
 //!         priority: 32,
+//!         ..Default::default()
 //!     });
 //! }
 //! ```
@@ -149,20 +152,31 @@
 //! pub struct MyGenericChannel<C: Channel>(PhantomData<C>);
 //! ```
 
+mod compression;
 mod config;
+mod direction;
 mod extension;
 mod id;
 mod params;
+mod rate;
 mod registry;
+mod size;
 
-pub use config::{ChannelConfiguration, MessageConsistency};
+pub use compression::{ChannelCompression, CompressionAlgorithm, CompressionDictionary};
+pub use config::{ChannelConfiguration, ChannelDirection, ChannelRateLimit, MessageConsistency};
 pub use id::{Channel, ChannelId, ToChannelId};
 pub use registry::{ChannelRegistry, ChannelMetadata};
 pub use params::{Channels, ChannelData};
 pub use extension::ChannelSetupAppExt;
+pub use size::MessageTooLarge;
 
-use bevy_app::App;
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
 use registry::ChannelRegistryBuilder;
+use crate::connections::NetworkRole;
+use crate::scheduling::{NetworkRecv, NetworkSend};
+
+pub(crate) use size::check_message_size;
 
 pub(crate) fn plugin_build(app: &mut App) {
     app.insert_resource(ChannelRegistryBuilder(ChannelRegistry::new()));
@@ -172,5 +186,63 @@ pub(crate) fn plugin_cleanup(app: &mut App) {
     let world = app.world_mut();
     let mut builder = world.remove_resource::<ChannelRegistryBuilder>().unwrap();
     builder.0.channel_data.shrink_to_fit();
+
+    #[cfg(not(feature="compression"))]
+    for registration in builder.0.channel_data.iter() {
+        if registration.config.compression.is_some() {
+            panic!("Channel {} uses compression, but the compression feature is disabled", registration.metadata.type_name);
+        }
+    }
+
+    // Checks are only added if a channel uses them, since they need the finished registry,
+    // which doesn't exist in apps that are updated without being finished
+    let uses = |check: fn(&ChannelConfiguration) -> bool| builder.0.channel_data.iter().any(|registration| check(&registration.config));
+    let directions = uses(|config| config.direction != ChannelDirection::Bidirectional);
+    let sizes = uses(|config| config.max_message_size.is_some());
+    let rates = uses(|config| config.rate_limit.is_some());
+    #[cfg(feature="compression")]
+    let compression = uses(|config| config.compression.is_some());
+
     world.insert_resource(builder.finish());
-}
\ No newline at end of file
+
+    if directions {
+        app.add_systems(PreUpdate, direction::incoming_direction_system
+            .run_if(resource_exists::<NetworkRole>)
+            .in_set(NetworkRecv::Validate));
+
+        #[cfg(debug_assertions)]
+        app.add_systems(PostUpdate, direction::outgoing_direction_system
+            .run_if(resource_exists::<NetworkRole>)
+            .in_set(NetworkSend::Prepare));
+    }
+
+    #[cfg(feature="compression")]
+    if compression {
+        app.add_systems(PreUpdate, compression::decompress_incoming_system
+            .after(direction::incoming_direction_system)
+            .in_set(NetworkRecv::Validate));
+
+        app.add_systems(PostUpdate, compression::compress_outgoing_system
+            .after(size::outgoing_size_system)
+            .in_set(NetworkSend::Prepare));
+    }
+
+    if sizes {
+        let system = size::incoming_size_system.after(direction::incoming_direction_system);
+        #[cfg(feature="compression")]
+        let system = system.after(compression::decompress_incoming_system);
+        app.add_systems(PreUpdate, system.in_set(NetworkRecv::Validate));
+
+        app.add_systems(PostUpdate, size::outgoing_size_system
+            .in_set(NetworkSend::Prepare));
+    }
+
+    if rates {
+        let system = rate::rate_limit_system
+            .after(direction::incoming_direction_system)
+            .after(size::incoming_size_system);
+        #[cfg(feature="compression")]
+        let system = system.after(compression::decompress_incoming_system);
+        app.add_systems(PreUpdate, system.in_set(NetworkRecv::Validate));
+    }
+}
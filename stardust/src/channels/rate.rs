@@ -0,0 +1,153 @@
+use std::time::Instant;
+use bevy_ecs::prelude::*;
+use hashbrown::HashMap;
+use crate::prelude::*;
+use crate::connections::{Infraction, PeerReputation};
+use super::ChannelRateLimit;
+
+/// How much of each channel's rate limit a peer has left.
+#[derive(Component, Default)]
+pub(crate) struct PeerChannelBudgets(HashMap<ChannelId, Budget>);
+
+struct Budget {
+    messages: f32,
+    bytes: f32,
+    updated: Instant,
+}
+
+impl Budget {
+    fn full(limit: &ChannelRateLimit, now: Instant) -> Self {
+        Self {
+            messages: limit.messages.unwrap_or(0) as f32,
+            bytes: limit.bytes.unwrap_or(0) as f32,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &ChannelRateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f32();
+        self.updated = now;
+
+        if let Some(max) = limit.messages {
+            self.messages = (self.messages + elapsed * max as f32).min(max as f32);
+        }
+
+        if let Some(max) = limit.bytes {
+            self.bytes = (self.bytes + elapsed * max as f32).min(max as f32);
+        }
+    }
+
+    fn take(&mut self, limit: &ChannelRateLimit, len: usize) -> bool {
+        let len = len as f32;
+        if limit.messages.is_some() && self.messages < 1.0 { return false }
+        if limit.bytes.is_some() && self.bytes < len { return false }
+
+        if limit.messages.is_some() { self.messages -= 1.0 }
+        if limit.bytes.is_some() { self.bytes -= len }
+        return true;
+    }
+}
+
+type RateLimitedPeer<'a> = (Entity, &'a mut PeerMessages<Incoming>, Option<&'a mut PeerChannelBudgets>, Option<&'a mut PeerReputation>);
+
+pub(crate) fn rate_limit_system(
+    mut commands: Commands,
+    channels: Channels,
+    mut limits: Local<Option<HashMap<ChannelId, ChannelRateLimit>>>,
+    mut query: Query<RateLimitedPeer>,
+) {
+    // Channels can't change after startup, so the limits are only collected once
+    let limits = limits.get_or_insert_with(|| (0..channels.count())
+        .map(ChannelId::from)
+        .filter_map(|id| Some((id, channels.config(id)?.rate_limit?)))
+        .collect());
+
+    if limits.is_empty() { return }
+    let now = Instant::now();
+
+    for (entity, mut messages, budgets, reputation) in query.iter_mut() {
+        if messages.count() == 0 { continue }
+
+        let mut new_budgets = None;
+        let budgets = match budgets {
+            Some(budgets) => &mut budgets.into_inner().0,
+            None => &mut new_budgets.insert(PeerChannelBudgets::default()).0,
+        };
+
+        // Refill each channel once, before any of its messages are checked
+        for (channel, limit) in limits.iter() {
+            budgets.entry(*channel)
+                .or_insert_with(|| Budget::full(limit, now))
+                .refill(limit, now);
+        }
+
+        let mut spammed = Vec::new();
+        messages.retain(|channel, message| {
+            let Some(limit) = limits.get(&channel) else { return true };
+            if budgets.get_mut(&channel).unwrap().take(limit, message.len()) { return true }
+            if !spammed.contains(&channel) { spammed.push(channel) }
+            return false;
+        });
+
+        if let Some(mut reputation) = reputation {
+            for channel in spammed {
+                reputation.report(Infraction::Spam { channel });
+            }
+        }
+
+        if let Some(budgets) = new_budgets {
+            commands.entity(entity).insert(budgets);
+        }
+    }
+}
+
+#[test]
+fn channel_rate_limit_test() {
+    use bevy_app::prelude::*;
+    use crate::connections::ReputationPolicy;
+
+    struct Limited;
+    struct Unlimited;
+
+    #[derive(Resource, Default)]
+    struct Received(usize);
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+    app.add_channel::<Limited>(ChannelConfiguration {
+        consistency: MessageConsistency::UnreliableUnordered,
+        priority: 0,
+        rate_limit: Some(ChannelRateLimit { messages: Some(3), bytes: None }),
+        ..Default::default()
+    });
+    app.add_channel::<Unlimited>(ChannelConfiguration {
+        consistency: MessageConsistency::UnreliableUnordered,
+        priority: 0,
+        ..Default::default()
+    });
+
+    app.init_resource::<Received>();
+    app.insert_resource(ReputationPolicy::default());
+    app.add_systems(Update, |mut received: ResMut<Received>, query: Query<&PeerMessages<Incoming>>| {
+        received.0 += query.iter().map(|m| m.count()).sum::<usize>();
+    });
+
+    app.finish();
+    app.cleanup();
+
+    let (limited, unlimited) = {
+        let channels = app.world().resource::<super::registry::ChannelRegistryFinished>();
+        (channels.id(std::any::TypeId::of::<Limited>()).unwrap(), channels.id(std::any::TypeId::of::<Unlimited>()).unwrap())
+    };
+
+    let mut messages = PeerMessages::<Incoming>::new();
+    messages.push_channel(limited, (0..5).map(|_| Message::from_static(b"spam")));
+    messages.push_channel(unlimited, (0..5).map(|_| Message::from_static(b"fine")));
+    let peer = app.world_mut().spawn((Peer::new(), messages, PeerReputation::default())).id();
+    app.update();
+
+    // Two messages over the limit are dropped, and reported once
+    assert_eq!(app.world().resource::<Received>().0, 8);
+    let reputation = app.world().get::<PeerReputation>(peer).unwrap();
+    assert_eq!(reputation.history().collect::<Vec<_>>(), vec![&Infraction::Spam { channel: limited }]);
+}
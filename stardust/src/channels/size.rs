@@ -0,0 +1,137 @@
+use std::fmt::Display;
+use bevy_ecs::prelude::*;
+use hashbrown::HashMap;
+use crate::prelude::*;
+use crate::connections::{Infraction, PeerReputation};
+use super::ChannelRegistry;
+
+/// An error returned when a message is larger than its channel's
+/// [`max_message_size`](ChannelConfiguration::max_message_size).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageTooLarge {
+    /// The channel the message was pushed to.
+    pub channel: ChannelId,
+
+    /// The size of the message, in bytes.
+    pub size: usize,
+
+    /// The channel's maximum message size, in bytes.
+    pub limit: usize,
+}
+
+impl Display for MessageTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("message of {} bytes exceeds the limit of {} bytes on channel {:?}",
+            self.size, self.limit, self.channel))
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+/// Returns an error if `message` is larger than its channel allows.
+pub(crate) fn check_message_size(registry: &ChannelRegistry, message: &ChannelMessage) -> Result<(), MessageTooLarge> {
+    let limit = registry.config(message.channel).and_then(|config| config.max_message_size);
+    match limit {
+        Some(limit) if message.message.len() > limit => Err(MessageTooLarge {
+            channel: message.channel,
+            size: message.message.len(),
+            limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn collect_limits(channels: &Channels) -> HashMap<ChannelId, usize> {
+    (0..channels.count())
+        .map(ChannelId::from)
+        .filter_map(|id| Some((id, channels.config(id)?.max_message_size?)))
+        .collect()
+}
+
+pub(crate) fn incoming_size_system(
+    channels: Channels,
+    mut limits: Local<Option<HashMap<ChannelId, usize>>>,
+    mut query: Query<(&mut PeerMessages<Incoming>, Option<&mut PeerReputation>)>,
+) {
+    // Channels can't change after startup, so the limits are only collected once
+    let limits = limits.get_or_insert_with(|| collect_limits(&channels));
+    if limits.is_empty() { return }
+
+    for (mut messages, reputation) in query.iter_mut() {
+        if messages.count() == 0 { continue }
+
+        let mut oversized = Vec::new();
+        messages.retain(|channel, message| {
+            if limits.get(&channel).is_none_or(|limit| message.len() <= *limit) { return true }
+            if !oversized.contains(&channel) { oversized.push(channel) }
+            return false;
+        });
+
+        if let Some(mut reputation) = reputation {
+            for channel in oversized {
+                reputation.report(Infraction::OversizedMessage { channel });
+            }
+        }
+    }
+}
+
+pub(crate) fn outgoing_size_system(
+    channels: Channels,
+    mut limits: Local<Option<HashMap<ChannelId, usize>>>,
+    mut query: Query<&mut PeerMessages<Outgoing>>,
+) {
+    let limits = limits.get_or_insert_with(|| collect_limits(&channels));
+    if limits.is_empty() { return }
+
+    // The remote peer would drop these anyway, and count them against us
+    for mut messages in query.iter_mut() {
+        if messages.count() == 0 { continue }
+        messages.retain(|channel, message| {
+            limits.get(&channel).is_none_or(|limit| message.len() <= *limit)
+        });
+    }
+}
+
+#[test]
+fn max_message_size_test() {
+    use bevy_app::prelude::*;
+    use crate::connections::ReputationPolicy;
+
+    struct Small;
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+    app.add_channel::<Small>(ChannelConfiguration {
+        consistency: MessageConsistency::UnreliableUnordered,
+        priority: 0,
+        max_message_size: Some(4),
+        ..Default::default()
+    });
+
+    app.insert_resource(ReputationPolicy::default());
+    app.finish();
+    app.cleanup();
+
+    let small = app.world().resource::<super::registry::ChannelRegistryFinished>()
+        .id(std::any::TypeId::of::<Small>()).unwrap();
+
+    // Outgoing messages over the limit are rejected when pushed
+    let mut outgoing = PeerMessages::<Outgoing>::new();
+    let registry = app.world().resource::<super::registry::ChannelRegistryFinished>();
+    assert!(outgoing.try_push(registry, ChannelMessage { channel: small, message: Message::from_static(b"fine") }).is_ok());
+    assert_eq!(
+        outgoing.try_push(registry, ChannelMessage { channel: small, message: Message::from_static(b"too large") }),
+        Err(MessageTooLarge { channel: small, size: 9, limit: 4 }),
+    );
+
+    let mut incoming = PeerMessages::<Incoming>::new();
+    incoming.push_channel(small, [Message::from_static(b"fine"), Message::from_static(b"too large")]);
+    let peer = app.world_mut().spawn((Peer::new(), incoming, PeerReputation::default())).id();
+
+    // Only run the receiving half of the update, since the queues are cleared afterwards
+    app.world_mut().run_schedule(PreUpdate);
+    let incoming = app.world().get::<PeerMessages<Incoming>>(peer).unwrap();
+    assert_eq!(incoming.count(), 1);
+    let reputation = app.world().get::<PeerReputation>(peer).unwrap();
+    assert_eq!(reputation.history().collect::<Vec<_>>(), vec![&Infraction::OversizedMessage { channel: small }]);
+}
@@ -0,0 +1,366 @@
+//! Pluggable authentication of peers during the handshake.
+
+use std::{future::Future, pin::Pin, sync::{Arc, Mutex}, time::Duration};
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::{BoxedSystem, SystemId};
+use bevy_tasks::{block_on, poll_once};
+use crate::prelude::*;
+use crate::channels::ChannelRateLimit;
+
+type BoxedFuture = Pin<Box<dyn Future<Output = AuthDecision> + Send>>;
+type AsyncAuthenticator = dyn Fn(AuthRequest) -> BoxedFuture + Send + Sync;
+type BoxedInsert = Box<dyn FnOnce(EntityWorldMut) + Send + Sync>;
+
+/// Authenticates peers in the [`Handshaking`](PeerLifestage::Handshaking) lifestage
+/// with an application-provided system or async closure.
+///
+/// The first message a handshaking peer sends on [`AuthenticationChannel`] is passed to the authenticator
+/// as an [`AuthRequest`], which decides whether to accept or reject the peer. Accepted peers have their
+/// [`PeerUid`] and any other components from the [`AuthAccept`] inserted. Rejected peers, and peers that
+/// aren't authenticated within the timeout, are disconnected with a [`DisconnectPeerEvent`].
+///
+/// Transport layers can wait for a [`PeerUid`] to be added before moving a peer out of the handshake.
+///
+/// ```no_run
+/// # use bevy_app::prelude::*;
+/// # use bevy_ecs::prelude::*;
+/// # use bevy_stardust::prelude::*;
+/// # use bevy_stardust::connections::*;
+/// fn authenticate(In(request): In<AuthRequest>) -> AuthDecision {
+///     match request.payload.as_str() {
+///         Ok("let me in") => AuthDecision::accept(PeerUid(1)).into(),
+///         _ => AuthDecision::reject(DisconnectReason::FailedAuthentication, None),
+///     }
+/// }
+///
+/// # let mut app = App::new();
+/// app.add_plugins(PeerAuthenticatorPlugin::from_system(authenticate));
+/// ```
+pub struct PeerAuthenticatorPlugin {
+    authenticator: Mutex<Option<Authenticator>>,
+    timeout: Duration,
+}
+
+enum Authenticator {
+    System(BoxedSystem<In<AuthRequest>, AuthDecision>),
+    Async(Arc<AsyncAuthenticator>),
+}
+
+impl PeerAuthenticatorPlugin {
+    /// Authenticates peers with a system that takes an [`AuthRequest`] as input.
+    ///
+    /// The system is run once for each request, with exclusive access to the `World`.
+    pub fn from_system<M>(system: impl IntoSystem<In<AuthRequest>, AuthDecision, M>) -> Self {
+        Self {
+            authenticator: Mutex::new(Some(Authenticator::System(Box::new(IntoSystem::into_system(system))))),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Authenticates peers with an async closure, such as one that queries a web service.
+    ///
+    /// The returned future is polled once every update until it completes.
+    /// It isn't run on an executor, so it must be woken by something outside of the app,
+    /// such as a channel or a task running on another thread.
+    pub fn from_async<F, Fut>(func: F) -> Self
+    where
+        F: Fn(AuthRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AuthDecision> + Send + 'static,
+    {
+        let func = move |request| Box::pin(func(request)) as BoxedFuture;
+
+        Self {
+            authenticator: Mutex::new(Some(Authenticator::Async(Arc::new(func)))),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets how long a peer can be handshaking before it must be authenticated.
+    /// The timeout starts from [`Peer::joined`]. Defaults to 10 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        return self;
+    }
+}
+
+impl Plugin for PeerAuthenticatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_channel::<AuthenticationChannel>(ChannelConfiguration {
+            consistency: MessageConsistency::ReliableOrdered,
+            priority: u32::MAX,
+            // Only the first message is ever read
+            rate_limit: Some(ChannelRateLimit { messages: Some(4), bytes: Some(65536) }),
+            max_message_size: Some(65536),
+            direction: ChannelDirection::ClientToServer,
+            ..Default::default()
+        });
+
+        let authenticator = self.authenticator.lock().unwrap().take()
+            .expect("PeerAuthenticatorPlugin was built twice");
+
+        let authenticator = match authenticator {
+            Authenticator::System(system) => PeerAuthenticator::System(app.world_mut().register_boxed_system(system)),
+            Authenticator::Async(func) => PeerAuthenticator::Async(func),
+        };
+
+        app.insert_resource(AuthTimeout(self.timeout));
+        app.insert_resource(authenticator);
+
+        app.add_systems(PreUpdate, (
+            begin_auth_system,
+            run_auth_systems.run_if(|auth: Res<PeerAuthenticator>| matches!(*auth, PeerAuthenticator::System(_))),
+            finish_auth_system,
+        ).chain().in_set(NetworkRecv::Synchronise));
+    }
+}
+
+/// The channel that handshaking peers send their authentication payload on.
+/// Only the first message on this channel is considered.
+pub struct AuthenticationChannel;
+
+/// A request to authenticate a peer, passed to the authenticator given to [`PeerAuthenticatorPlugin`].
+#[derive(Debug, Clone)]
+pub struct AuthRequest {
+    /// The peer entity being authenticated.
+    pub peer: Entity,
+
+    /// The first message the peer sent on [`AuthenticationChannel`].
+    pub payload: Message,
+}
+
+/// The outcome of an [`AuthRequest`].
+pub enum AuthDecision {
+    /// The peer is accepted.
+    Accept(AuthAccept),
+
+    /// The peer is rejected and will be disconnected.
+    Reject {
+        /// The reason given for the disconnection.
+        reason: DisconnectReason,
+
+        /// A human-readable string associated with the disconnection.
+        comment: Option<Arc<str>>,
+    },
+}
+
+impl AuthDecision {
+    /// Accepts the peer, assigning it `uid`.
+    /// Additional components can be added with [`AuthAccept::with`].
+    pub fn accept(uid: PeerUid) -> AuthAccept {
+        AuthAccept {
+            uid,
+            inserts: Vec::new(),
+        }
+    }
+
+    /// Rejects the peer, disconnecting it with `reason`.
+    pub fn reject(reason: DisconnectReason, comment: Option<Arc<str>>) -> Self {
+        Self::Reject { reason, comment }
+    }
+}
+
+/// An accepted [`AuthDecision`], created with [`AuthDecision::accept`].
+pub struct AuthAccept {
+    uid: PeerUid,
+    inserts: Vec<BoxedInsert>,
+}
+
+impl AuthAccept {
+    /// Inserts `bundle` on the peer entity when it's accepted.
+    pub fn with<B: Bundle>(mut self, bundle: B) -> Self {
+        self.inserts.push(Box::new(move |mut entity: EntityWorldMut| { entity.insert(bundle); }));
+        return self;
+    }
+}
+
+impl From<AuthAccept> for AuthDecision {
+    #[inline]
+    fn from(value: AuthAccept) -> Self {
+        Self::Accept(value)
+    }
+}
+
+#[derive(Resource)]
+enum PeerAuthenticator {
+    System(SystemId<In<AuthRequest>, AuthDecision>),
+    Async(Arc<AsyncAuthenticator>),
+}
+
+#[derive(Resource)]
+struct AuthTimeout(Duration);
+
+#[derive(Component)]
+enum AuthState {
+    Queued(AuthRequest),
+    // Makes the future Sync, so it can be in a Component.
+    Waiting(Mutex<BoxedFuture>),
+    Decided(AuthDecision),
+    Finished,
+}
+
+type Unauthenticated = (Without<PeerUid>, Without<AuthState>);
+
+fn begin_auth_system(
+    mut commands: Commands,
+    channel: ChannelData<AuthenticationChannel>,
+    authenticator: Res<PeerAuthenticator>,
+    timeout: Res<AuthTimeout>,
+    query: Query<(Entity, &Peer, &PeerLifestage, &PeerMessages<Incoming>), Unauthenticated>,
+) {
+    for (entity, peer, lifestage, messages) in query.iter() {
+        if *lifestage != PeerLifestage::Handshaking { continue }
+
+        let Some(payload) = messages.iter_channel(channel.id()).next() else {
+            // Peers that never send a payload are timed out
+            if peer.joined.elapsed() > timeout.0 {
+                commands.entity(entity).insert(AuthState::Decided(timed_out(peer)));
+            }
+
+            continue;
+        };
+
+        let request = AuthRequest { peer: entity, payload };
+        let state = match authenticator.as_ref() {
+            PeerAuthenticator::System(_) => AuthState::Queued(request),
+            PeerAuthenticator::Async(func) => AuthState::Waiting(Mutex::new(func(request))),
+        };
+
+        commands.entity(entity).insert(state);
+    }
+}
+
+fn run_auth_systems(
+    world: &mut World,
+) {
+    let PeerAuthenticator::System(system) = *world.resource::<PeerAuthenticator>() else { return };
+
+    // Take all queued requests out of the world first,
+    // since the system needs exclusive access to run
+    let mut query = world.query::<(Entity, &mut AuthState)>();
+    let requests: Vec<(Entity, AuthRequest)> = query.iter_mut(world)
+        .filter_map(|(entity, mut state)| {
+            if !matches!(*state, AuthState::Queued(_)) { return None }
+            let AuthState::Queued(request) = std::mem::replace(state.as_mut(), AuthState::Finished) else { unreachable!() };
+            Some((entity, request))
+        })
+        .collect();
+
+    for (entity, request) in requests {
+        let decision = world.run_system_with_input(system, request)
+            .expect("Authentication system was unregistered");
+
+        if let Ok(mut entity) = world.get_entity_mut(entity) {
+            entity.insert(AuthState::Decided(decision));
+        }
+    }
+}
+
+fn finish_auth_system(
+    mut commands: Commands,
+    timeout: Res<AuthTimeout>,
+    mut query: Query<(Entity, &Peer, &mut AuthState)>,
+    mut disconnects: EventWriter<DisconnectPeerEvent>,
+) {
+    for (entity, peer, mut state) in query.iter_mut() {
+        // Poll futures, and check they haven't taken too long
+        if let AuthState::Waiting(future) = state.as_mut() {
+            let future = future.get_mut().unwrap();
+            *state = match block_on(poll_once(future)) {
+                Some(decision) => AuthState::Decided(decision),
+                None if peer.joined.elapsed() > timeout.0 => AuthState::Decided(timed_out(peer)),
+                None => continue,
+            };
+        }
+
+        if !matches!(*state, AuthState::Decided(_)) { continue }
+        let AuthState::Decided(decision) = std::mem::replace(state.as_mut(), AuthState::Finished) else { unreachable!() };
+
+        match decision {
+            AuthDecision::Accept(accept) => {
+                let mut entity = commands.entity(entity);
+                entity.insert(accept.uid);
+                for insert in accept.inserts {
+                    entity.queue(insert);
+                }
+            },
+
+            AuthDecision::Reject { reason, comment } => {
+                disconnects.send(DisconnectPeerEvent {
+                    peer: entity,
+                    reason,
+                    comment,
+                    force: false,
+                });
+            },
+        }
+    }
+}
+
+fn timed_out(peer: &Peer) -> AuthDecision {
+    AuthDecision::reject(
+        DisconnectReason::TimedOut { after: peer.joined.elapsed() },
+        Some(Arc::from("not authenticated in time")),
+    )
+}
+
+#[test]
+fn peer_authentication_test() {
+    use std::time::Instant;
+    use bevy_ecs::event::Events;
+    use bevy_ecs::system::RunSystemOnce;
+
+    #[derive(Component)]
+    struct Admin;
+
+    fn decide(request: &AuthRequest) -> AuthDecision {
+        match request.payload.as_slice() {
+            b"admin" => AuthDecision::accept(PeerUid(1)).with(Admin).into(),
+            b"player" => AuthDecision::accept(PeerUid(2)).into(),
+            _ => AuthDecision::reject(DisconnectReason::FailedAuthentication, None),
+        }
+    }
+
+    fn test(plugin: PeerAuthenticatorPlugin) {
+        let mut app = App::new();
+        app.add_plugins((StardustPlugin, plugin));
+        app.finish();
+        app.cleanup();
+
+        let channel = app.world_mut().run_system_once(|channel: ChannelData<AuthenticationChannel>| channel.id()).unwrap();
+        let mut spawn = |payload: Option<&'static [u8]>, joined: Instant| {
+            let mut messages = PeerMessages::<Incoming>::new();
+            if let Some(payload) = payload {
+                messages.push_one(ChannelMessage { channel, message: Message::from_static(payload) });
+            }
+
+            app.world_mut().spawn((Peer { joined }, PeerLifestage::Handshaking, messages)).id()
+        };
+
+        let now = Instant::now();
+        let admin = spawn(Some(b"admin"), now);
+        let player = spawn(Some(b"player"), now);
+        let rejected = spawn(Some(b"intruder"), now);
+        let silent = spawn(None, now);
+        let late = spawn(None, now - Duration::from_secs(60));
+        app.update();
+
+        // Accepted peers get their uid and any extra components
+        let world = app.world();
+        assert!(world.get::<PeerUid>(admin) == Some(&PeerUid(1)));
+        assert!(world.get::<Admin>(admin).is_some());
+        assert!(world.get::<PeerUid>(player) == Some(&PeerUid(2)));
+        assert!(world.get::<Admin>(player).is_none());
+
+        // Rejected and timed out peers are disconnected, and silent peers are left alone until the timeout
+        let mut disconnects = app.world_mut().resource_mut::<Events<DisconnectPeerEvent>>();
+        let disconnects: Vec<_> = disconnects.drain().map(|event| (event.peer, event.reason)).collect();
+        assert_eq!(disconnects.len(), 2);
+        assert!(disconnects.iter().any(|(peer, reason)| *peer == rejected && matches!(reason, DisconnectReason::FailedAuthentication)));
+        assert!(disconnects.iter().any(|(peer, reason)| *peer == late && matches!(reason, DisconnectReason::TimedOut { .. })));
+        assert!(app.world().get::<PeerUid>(silent).is_none());
+    }
+
+    test(PeerAuthenticatorPlugin::from_system(|In(request): In<AuthRequest>| decide(&request)));
+    test(PeerAuthenticatorPlugin::from_async(|request| std::future::ready(decide(&request))));
+}
@@ -0,0 +1,214 @@
+//! A persistent list of banned peers.
+
+use std::{collections::HashMap, fmt::Display, io::{self, BufRead, Write}, net::IpAddr, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+use bevy_ecs::prelude::*;
+use crate::prelude::*;
+use super::PeerAddress;
+
+/// A list of banned [`PeerUid`]s and [`PeerAddress`]es.
+///
+/// If this resource exists, peers with a banned uid or address are disconnected as soon as
+/// the uid or address is added to the peer entity. Transport layers should also check
+/// [`is_address_banned`](Self::is_address_banned) before accepting a connection,
+/// so banned addresses never get a peer entity in the first place.
+///
+/// The list can be saved to and loaded from a simple text format with [`save`](Self::save)
+/// and [`load`](Self::load), so bans persist across restarts. Each line is a ban, in the form
+/// `<uid|address> <value> <expiry> <reason>`, where the expiry is in seconds since the Unix epoch,
+/// or `-` for permanent bans.
+#[derive(Debug, Default, Clone, Resource)]
+pub struct BanList {
+    uids: HashMap<PeerUid, Ban>,
+    addresses: HashMap<IpAddr, Ban>,
+}
+
+/// An entry in a [`BanList`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    /// When the ban expires, or `None` if it's permanent.
+    pub until: Option<SystemTime>,
+
+    /// A human-readable reason for the ban.
+    pub reason: Arc<str>,
+}
+
+impl Ban {
+    /// Creates a new ban lasting `duration`, or a permanent ban if `duration` is `None`.
+    pub fn new(duration: Option<Duration>, reason: impl Into<Arc<str>>) -> Self {
+        Self {
+            until: duration.map(|d| SystemTime::now() + d),
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns `true` if the ban is in effect at `now`.
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+}
+
+impl BanList {
+    /// Bans `uid`, replacing any existing ban.
+    pub fn ban_uid(&mut self, uid: PeerUid, ban: Ban) {
+        self.uids.insert(uid, ban);
+    }
+
+    /// Bans `address`, replacing any existing ban.
+    pub fn ban_address(&mut self, address: IpAddr, ban: Ban) {
+        self.addresses.insert(address, ban);
+    }
+
+    /// Removes the ban on `uid`, returning it if there was one.
+    pub fn unban_uid(&mut self, uid: PeerUid) -> Option<Ban> {
+        self.uids.remove(&uid)
+    }
+
+    /// Removes the ban on `address`, returning it if there was one.
+    pub fn unban_address(&mut self, address: IpAddr) -> Option<Ban> {
+        self.addresses.remove(&address)
+    }
+
+    /// Returns the ban on `uid`, if it's banned at `now`.
+    pub fn is_uid_banned(&self, uid: PeerUid, now: SystemTime) -> Option<&Ban> {
+        self.uids.get(&uid).filter(|ban| ban.is_active(now))
+    }
+
+    /// Returns the ban on `address`, if it's banned at `now`.
+    pub fn is_address_banned(&self, address: IpAddr, now: SystemTime) -> Option<&Ban> {
+        self.addresses.get(&address).filter(|ban| ban.is_active(now))
+    }
+
+    /// Removes all bans that have expired at `now`.
+    pub fn prune(&mut self, now: SystemTime) {
+        self.uids.retain(|_, ban| ban.is_active(now));
+        self.addresses.retain(|_, ban| ban.is_active(now));
+    }
+
+    /// Writes all bans to `writer`.
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let uids = self.uids.iter().map(|(uid, ban)| ("uid", format!("{:x}", uid.0), ban));
+        let addresses = self.addresses.iter().map(|(address, ban)| ("address", address.to_string(), ban));
+
+        for (kind, value, ban) in uids.chain(addresses) {
+            let until = match ban.until {
+                Some(until) => until.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0).to_string(),
+                None => "-".to_string(),
+            };
+
+            // Newlines would split the entry in two
+            let reason = ban.reason.replace(['\n', '\r'], " ");
+            writeln!(writer, "{kind} {value} {until} {reason}")?;
+        }
+
+        return Ok(());
+    }
+
+    /// Reads bans from `reader`, adding them to the list.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn load<R: BufRead>(&mut self, reader: R) -> Result<(), BanListError> {
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue }
+
+            let malformed = || BanListError::Malformed { line: index + 1 };
+            let mut parts = line.splitn(4, ' ');
+            let (Some(kind), Some(value), Some(until)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(malformed());
+            };
+
+            let ban = Ban {
+                until: match until {
+                    "-" => None,
+                    secs => Some(UNIX_EPOCH + Duration::from_secs(secs.parse().map_err(|_| malformed())?)),
+                },
+                reason: parts.next().unwrap_or("").into(),
+            };
+
+            match kind {
+                "uid" => self.ban_uid(PeerUid(u64::from_str_radix(value, 16).map_err(|_| malformed())?), ban),
+                "address" => self.ban_address(value.parse().map_err(|_| malformed())?, ban),
+                _ => return Err(malformed()),
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// An error returned by [`BanList::load`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BanListError {
+    /// An I/O error occurred.
+    Io(io::Error),
+
+    /// A line couldn't be parsed.
+    Malformed {
+        /// The line number, starting from 1.
+        line: usize,
+    },
+}
+
+impl Display for BanListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BanListError::Io(err) => f.write_fmt(format_args!("i/o error: {err}")),
+            BanListError::Malformed { line } => f.write_fmt(format_args!("malformed entry on line {line}")),
+        }
+    }
+}
+
+impl std::error::Error for BanListError {}
+
+impl From<io::Error> for BanListError {
+    #[inline]
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+type BannablePeer<'a> = (Entity, Option<Ref<'a, PeerUid>>, Option<Ref<'a, PeerAddress>>);
+
+pub(crate) fn enforce_bans_system(
+    bans: Res<BanList>,
+    query: Query<BannablePeer, With<Peer>>,
+    mut disconnects: EventWriter<DisconnectPeerEvent>,
+) {
+    let now = SystemTime::now();
+
+    for (entity, uid, address) in query.iter() {
+        // Only check uids and addresses when they're first added
+        let ban = uid.filter(|uid| uid.is_added()).and_then(|uid| bans.is_uid_banned(*uid, now))
+            .or_else(|| address.filter(|address| address.is_added()).and_then(|address| bans.is_address_banned(address.0, now)));
+
+        let Some(ban) = ban else { continue };
+        disconnects.send(DisconnectPeerEvent {
+            peer: entity,
+            reason: DisconnectReason::Misbehaving,
+            comment: Some(format!("banned: {}", ban.reason).into()),
+            force: false,
+        });
+    }
+}
+
+#[test]
+fn ban_list_persistence_test() {
+    let mut bans = BanList::default();
+    let now = SystemTime::now();
+    bans.ban_uid(PeerUid(0xABCD), Ban::new(None, "cheating"));
+    bans.ban_address(IpAddr::from([192, 0, 2, 1]), Ban::new(Some(Duration::from_secs(60)), "spam\non two lines"));
+    bans.ban_address(IpAddr::from([192, 0, 2, 2]), Ban { until: Some(now - Duration::from_secs(1)), reason: "expired".into() });
+
+    let mut saved = Vec::new();
+    bans.save(&mut saved).unwrap();
+    let mut loaded = BanList::default();
+    loaded.load(&saved[..]).unwrap();
+
+    assert_eq!(&*loaded.is_uid_banned(PeerUid(0xABCD), now).unwrap().reason, "cheating");
+    assert_eq!(&*loaded.is_address_banned(IpAddr::from([192, 0, 2, 1]), now).unwrap().reason, "spam on two lines");
+    assert!(loaded.is_address_banned(IpAddr::from([192, 0, 2, 2]), now).is_none());
+    assert!(loaded.is_uid_banned(PeerUid(0x1234), now).is_none());
+
+    assert!(matches!(loaded.load(&b"uid nothex -"[..]), Err(BanListError::Malformed { line: 1 })));
+}
@@ -0,0 +1,124 @@
+use bevy_ecs::prelude::*;
+use bevy_ecs::query::QueryFilter;
+use bevy_ecs::system::SystemParam;
+use crate::prelude::*;
+use crate::channels::{check_message_size, MessageTooLarge};
+
+/// A [`SystemParam`] for sending the same message to many peers.
+///
+/// Messages are only sent to peers in the [`Established`] lifestage,
+/// that also match the query filter `F`. The message's buffer is shared by all peers,
+/// rather than copied, and peers are iterated in parallel where possible.
+///
+/// Like [`PeerMessages::try_push`], messages larger than their channel's
+/// [`max_message_size`](ChannelConfiguration::max_message_size) aren't sent to anyone.
+///
+/// Since it mutably accesses [`PeerMessages<Outgoing>`], it conflicts with other
+/// queries for it, including other `NetBroadcast`s, unless they're in a [`ParamSet`].
+///
+/// ```
+/// # use bevy_ecs::prelude::*;
+/// # use bevy_stardust::prelude::*;
+/// # struct ChatChannel;
+/// # #[derive(Component)] struct Spectator;
+/// fn announce_system(
+///     channel: ChannelData<ChatChannel>,
+///     mut spectators: NetBroadcast<With<Spectator>>,
+/// ) {
+///     spectators.broadcast(channel.id(), Message::from_static(b"You are spectating")).unwrap();
+/// }
+/// ```
+#[derive(SystemParam)]
+pub struct NetBroadcast<'w, 's, F: QueryFilter + 'static = ()> {
+    channels: Channels<'w>,
+    peers: Query<'w, 's, &'static mut PeerMessages<Outgoing>, (With<Peer>, Established<'static>, F)>,
+}
+
+impl<F: QueryFilter + 'static> NetBroadcast<'_, '_, F> {
+    /// Sends `message` on `channel` to all peers.
+    pub fn broadcast(&mut self, channel: ChannelId, message: Message) -> Result<(), MessageTooLarge> {
+        let message = ChannelMessage { channel, message };
+        check_message_size(&self.channels, &message)?;
+
+        self.peers.par_iter_mut().for_each(|mut messages| {
+            messages.push_one(message.clone());
+        });
+
+        return Ok(());
+    }
+
+    /// Sends `message` on `channel` to the peers in `peers`.
+    /// Entities that aren't peers, or don't match the filter, are ignored.
+    pub fn multicast<I>(&mut self, peers: I, channel: ChannelId, message: Message) -> Result<(), MessageTooLarge>
+    where
+        I: IntoIterator<Item = Entity>,
+    {
+        let message = ChannelMessage { channel, message };
+        check_message_size(&self.channels, &message)?;
+
+        let mut iter = self.peers.iter_many_mut(peers);
+        while let Some(mut messages) = iter.fetch_next() {
+            messages.push_one(message.clone());
+        }
+
+        return Ok(());
+    }
+}
+
+#[test]
+fn broadcast_test() {
+    use bevy_app::prelude::*;
+    use bevy_ecs::system::RunSystemOnce;
+
+    struct Small;
+
+    #[derive(Component)]
+    struct Marked;
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+    let small = app.add_channel::<Small>(ChannelConfiguration {
+        consistency: MessageConsistency::UnreliableUnordered,
+        priority: 0,
+        max_message_size: Some(4),
+        ..Default::default()
+    });
+
+    app.finish();
+    app.cleanup();
+
+    let mut spawn = |lifestage, marked| {
+        let mut entity = app.world_mut().spawn((Peer::new(), lifestage, PeerMessages::<Outgoing>::new()));
+        if marked { entity.insert(Marked); }
+        entity.id()
+    };
+
+    let a = spawn(PeerLifestage::Established, false);
+    let b = spawn(PeerLifestage::Established, true);
+    let c = spawn(PeerLifestage::Handshaking, true);
+
+    app.world_mut().run_system_once(move |mut all: NetBroadcast| {
+        all.broadcast(small, Message::from_static(b"all")).unwrap();
+        assert!(all.broadcast(small, Message::from_static(b"too large")).is_err());
+    }).unwrap();
+
+    app.world_mut().run_system_once(move |mut marked: NetBroadcast<With<Marked>>| {
+        marked.broadcast(small, Message::from_static(b"mark")).unwrap();
+    }).unwrap();
+
+    app.world_mut().run_system_once(move |mut all: NetBroadcast| {
+        all.multicast([a, c], small, Message::from_static(b"list")).unwrap();
+    }).unwrap();
+
+    let sent = |peer| app.world().get::<PeerMessages<Outgoing>>(peer).unwrap()
+        .iter_channel(small).map(|message| message.as_slice().to_vec()).collect::<Vec<_>>();
+
+    assert_eq!(sent(a), vec![b"all".to_vec(), b"list".to_vec()]);
+    assert_eq!(sent(b), vec![b"all".to_vec(), b"mark".to_vec()]);
+    assert!(sent(c).is_empty());
+
+    // All peers share the same buffer
+    let first = |peer| app.world().get::<PeerMessages<Outgoing>>(peer).unwrap()
+        .iter_channel(small).next().unwrap().as_slice().as_ptr();
+    assert_eq!(first(a), first(b));
+}
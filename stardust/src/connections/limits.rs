@@ -0,0 +1,151 @@
+//! Admission control for new peers.
+
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+use bevy_ecs::prelude::*;
+use crate::prelude::*;
+use super::PeerAddress;
+
+/// Limits on how many peers can be connected at once.
+///
+/// If this resource exists, new [`Peer`] entities are checked against the limits
+/// in [`NetworkRecv::Synchronise`], and peers that would exceed any of them are disconnected
+/// with [`DisconnectReason::ResourceCapacity`]. Peers that are [`Closing`](PeerLifestage::Closing)
+/// or [`Closed`](PeerLifestage::Closed) don't count towards the limits.
+///
+/// Limits are only checked when a peer is added, so lowering a limit
+/// doesn't disconnect peers that are already connected.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct ConnectionLimits {
+    /// The maximum number of peers.
+    pub max_peers: Option<usize>,
+
+    /// The maximum number of peers in the [`Handshaking`](PeerLifestage::Handshaking) lifestage.
+    pub max_handshaking: Option<usize>,
+
+    /// The maximum number of peers with the same [`PeerAddress`].
+    /// Peers without an address are not limited.
+    pub max_per_address: Option<usize>,
+}
+
+/// Marks peers that were rejected by [`ConnectionLimits`], so they aren't counted.
+#[derive(Component)]
+pub(crate) struct CapacityRejected;
+
+type LimitedPeer<'a> = (Entity, Ref<'a, Peer>, Option<&'a PeerLifestage>, Option<&'a PeerAddress>);
+
+pub(crate) fn enforce_limits_system(
+    mut commands: Commands,
+    limits: Option<Res<ConnectionLimits>>,
+    query: Query<LimitedPeer, Without<CapacityRejected>>,
+    mut disconnects: EventWriter<DisconnectPeerEvent>,
+) {
+    // This system runs even without limits, so peers are only
+    // considered new if they were added since the last update
+    let Some(limits) = limits else { return };
+
+    let mut peers = 0;
+    let mut handshaking = 0;
+    let mut addresses: HashMap<IpAddr, usize> = HashMap::new();
+    let mut added = Vec::new();
+
+    // Count the peers that were already admitted
+    for (entity, peer, lifestage, address) in query.iter() {
+        if lifestage.is_some_and(|stage| *stage >= PeerLifestage::Closing) { continue }
+
+        if peer.is_added() {
+            added.push((entity, peer.joined, lifestage.copied(), address.map(|a| a.0)));
+            continue;
+        }
+
+        peers += 1;
+        if lifestage == Some(&PeerLifestage::Handshaking) { handshaking += 1 }
+        if let Some(address) = address { *addresses.entry(address.0).or_default() += 1 }
+    }
+
+    // Admit new peers in the order they joined
+    added.sort_by_key(|(_, joined, _, _)| *joined);
+    for (entity, _, lifestage, address) in added {
+        let is_handshaking = lifestage == Some(PeerLifestage::Handshaking);
+        let from_address = address.map(|a| addresses.get(&a).copied().unwrap_or(0));
+
+        let comment = if limits.max_peers.is_some_and(|max| peers >= max) {
+            Some("server is full")
+        } else if is_handshaking && limits.max_handshaking.is_some_and(|max| handshaking >= max) {
+            Some("too many peers are connecting")
+        } else if limits.max_per_address.zip(from_address).is_some_and(|(max, count)| count >= max) {
+            Some("too many connections from this address")
+        } else {
+            None
+        };
+
+        if let Some(comment) = comment {
+            commands.entity(entity).insert(CapacityRejected);
+            disconnects.send(DisconnectPeerEvent {
+                peer: entity,
+                reason: DisconnectReason::ResourceCapacity,
+                comment: Some(Arc::from(comment)),
+                force: false,
+            });
+
+            continue;
+        }
+
+        peers += 1;
+        if is_handshaking { handshaking += 1 }
+        if let Some(address) = address { *addresses.entry(address).or_default() += 1 }
+    }
+}
+
+#[test]
+fn connection_limits_test() {
+    use bevy_app::prelude::*;
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+    app.insert_resource(ConnectionLimits {
+        max_peers: Some(3),
+        max_handshaking: None,
+        max_per_address: Some(1),
+    });
+
+    let address = |n| PeerAddress(IpAddr::from([192, 0, 2, n]));
+    let spawn = |app: &mut App, n| app.world_mut().spawn((Peer::new(), PeerLifestage::Handshaking, address(n))).id();
+
+    spawn(&mut app, 1);
+    let b = spawn(&mut app, 1);
+    spawn(&mut app, 2);
+    app.update();
+
+    // The second peer from the same address is rejected
+    let rejected = |app: &mut App| app.world_mut().query_filtered::<Entity, With<CapacityRejected>>()
+        .iter(app.world()).collect::<Vec<_>>();
+    assert_eq!(rejected(&mut app), vec![b]);
+
+    // Peers admitted in earlier updates count towards the limit
+    spawn(&mut app, 3);
+    let e = spawn(&mut app, 4);
+    app.update();
+    let mut rejected = rejected(&mut app);
+    rejected.sort();
+    assert_eq!(rejected, vec![b, e]);
+}
+
+#[test]
+fn connection_limits_inserted_test() {
+    use bevy_app::prelude::*;
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+
+    for _ in 0..3 { app.world_mut().spawn((Peer::new(), PeerLifestage::Established)); }
+    app.update();
+
+    // Peers that joined before the limits existed are already admitted
+    app.insert_resource(ConnectionLimits { max_peers: Some(2), ..Default::default() });
+    let peer = app.world_mut().spawn((Peer::new(), PeerLifestage::Handshaking)).id();
+    app.update();
+
+    let rejected = app.world_mut().query_filtered::<Entity, With<CapacityRejected>>()
+        .iter(app.world()).collect::<Vec<_>>();
+    assert_eq!(rejected, vec![peer]);
+}
@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 use bevy_ecs::prelude::*;
-use crate::{channels::ChannelId, messages::*};
+use crate::{channels::{check_message_size, ChannelId, ChannelRegistry, MessageTooLarge}, messages::*};
 use super::Peer;
 
 /// A message queue for a [peer entity], exposing a subset of [`MessageQueue`]'s API.
@@ -66,6 +66,16 @@ impl<D: MessageDirection> PeerMessages<D> {
         self.queue.push_channel(channel, iter);
     }
 
+    /// Removes all messages for which `f` returns `false`, preserving the order of the rest.
+    /// Returns the number of messages that were removed.
+    #[inline]
+    pub fn retain<F>(&mut self, f: F) -> usize
+    where
+        F: FnMut(ChannelId, &Message) -> bool,
+    {
+        self.queue.retain(f)
+    }
+
     /// Returns an iterator over channels, and their associated queues.
     #[inline]
     pub fn iter(&self) -> ChannelIter {
@@ -79,6 +89,17 @@ impl<D: MessageDirection> PeerMessages<D> {
     }
 }
 
+impl PeerMessages<Outgoing> {
+    /// Pushes a single message to the queue, unless it's larger than its channel's
+    /// [`max_message_size`](crate::channels::ChannelConfiguration::max_message_size).
+    /// Messages on channels that don't exist in `registry` are always pushed.
+    pub fn try_push(&mut self, registry: &ChannelRegistry, message: ChannelMessage) -> Result<(), MessageTooLarge> {
+        check_message_size(registry, &message)?;
+        self.queue.push_one(message);
+        return Ok(());
+    }
+}
+
 impl<'a, D: MessageDirection> IntoIterator for &'a PeerMessages<D> {
     type Item = <&'a MessageQueue as IntoIterator>::Item;
     type IntoIter = <&'a MessageQueue as IntoIterator>::IntoIter;
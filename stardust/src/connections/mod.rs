@@ -12,6 +12,8 @@
 //! by transport layers to queue unread messages, which the application
 //! and other plugins can use to read incoming messages.
 //! 
+//! To send the same message to many peers, use the [`NetBroadcast`] system parameter.
+//! 
 //! For more information about messaging, see the [messages module](crate::messages).
 //! 
 //! # Additional Data
@@ -20,19 +22,34 @@
 //! Components that store peer-related data on peer entities
 //! are prefixed with `Peer`, such as [`PeerUid`].
 
+mod auth;
+mod bans;
+mod broadcast;
 mod lifestage;
+mod limits;
 mod messages;
 mod peer;
+mod reputation;
+mod role;
 mod stats;
 
 pub(crate) use messages::clear_message_queues_system;
+pub(crate) use limits::enforce_limits_system;
+pub(crate) use bans::enforce_bans_system;
+pub(crate) use reputation::{add_reputation_system, apply_reputation_system};
 
 pub mod events;
 
 #[cfg(feature="debug_tools")]
 pub mod debug_tools;
 
+pub use auth::{PeerAuthenticatorPlugin, AuthenticationChannel, AuthRequest, AuthDecision, AuthAccept};
 pub use messages::PeerMessages;
+pub use broadcast::NetBroadcast;
 pub use peer::{Peer, PeerAddress, PeerUid};
 pub use stats::PeerRtt;
-pub use lifestage::{PeerLifestage, Established};
\ No newline at end of file
+pub use lifestage::{PeerLifestage, Established};
+pub use limits::ConnectionLimits;
+pub use bans::{BanList, Ban, BanListError};
+pub use reputation::{PeerReputation, ReputationPolicy, Infraction};
+pub use role::NetworkRole;
\ No newline at end of file
@@ -0,0 +1,318 @@
+//! Accumulating evidence of misbehaviour from peers.
+
+use std::{collections::VecDeque, fmt::Display, time::{Duration, Instant}};
+use bevy_ecs::prelude::*;
+use crate::prelude::*;
+use super::{Ban, BanList, PeerAddress};
+
+/// The number of recent infractions kept by a [`PeerReputation`].
+const HISTORY_LEN: usize = 16;
+
+/// Something a peer did wrong, reported to its [`PeerReputation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Infraction {
+    /// The peer sent a message that couldn't be parsed.
+    MalformedMessage {
+        /// The channel the message was sent on.
+        channel: ChannelId,
+    },
+
+    /// The peer sent a message larger than its channel allows.
+    OversizedMessage {
+        /// The channel the message was sent on.
+        channel: ChannelId,
+    },
+
+    /// The peer sent a message on a channel it isn't allowed to send on.
+    ForbiddenChannel {
+        /// The channel the message was sent on.
+        channel: ChannelId,
+    },
+
+    /// The peer sent too many messages on a channel.
+    Spam {
+        /// The channel that was spammed.
+        channel: ChannelId,
+    },
+
+    /// The peer violated the protocol in some other way.
+    ProtocolViolation,
+
+    /// The peer behaved in a way the application doesn't allow, such as an impossible movement.
+    Misbehaving {
+        /// A description of what the peer did.
+        reason: &'static str,
+    },
+}
+
+impl Infraction {
+    /// Returns the [`DisconnectReason`] used when this infraction causes a disconnection.
+    pub fn disconnect_reason(&self) -> DisconnectReason {
+        match self {
+            Infraction::MalformedMessage { .. } => DisconnectReason::ProtocolViolation,
+            Infraction::OversizedMessage { .. } => DisconnectReason::ProtocolViolation,
+            Infraction::ForbiddenChannel { .. } => DisconnectReason::ProtocolViolation,
+            Infraction::ProtocolViolation => DisconnectReason::ProtocolViolation,
+            Infraction::Spam { .. } => DisconnectReason::Misbehaving,
+            Infraction::Misbehaving { .. } => DisconnectReason::Misbehaving,
+        }
+    }
+}
+
+impl Display for Infraction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Infraction::MalformedMessage { channel } => f.write_fmt(format_args!("malformed message on channel {channel:?}")),
+            Infraction::OversizedMessage { channel } => f.write_fmt(format_args!("oversized message on channel {channel:?}")),
+            Infraction::ForbiddenChannel { channel } => f.write_fmt(format_args!("message on forbidden channel {channel:?}")),
+            Infraction::Spam { channel } => f.write_fmt(format_args!("spam on channel {channel:?}")),
+            Infraction::ProtocolViolation => f.write_str("protocol violation"),
+            Infraction::Misbehaving { reason } => f.write_str(reason),
+        }
+    }
+}
+
+/// A record of the [`Infraction`]s a peer has committed.
+///
+/// Every infraction adds a penalty to the peer's score, which decays over time.
+/// If a [`ReputationPolicy`] resource exists, peers whose score crosses its thresholds are
+/// disconnected, and optionally banned. This component is added to all new peers while
+/// the policy exists, but can also be added by anything else.
+///
+/// ```
+/// # use bevy_ecs::prelude::*;
+/// # use bevy_stardust::prelude::*;
+/// # use bevy_stardust::connections::*;
+/// fn check_moves(mut peers: Query<&mut PeerReputation>) {
+///     for mut reputation in peers.iter_mut() {
+///         # let impossible = false;
+///         if impossible {
+///             reputation.report(Infraction::Misbehaving { reason: "impossible movement" });
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Component)]
+pub struct PeerReputation {
+    score: f32,
+    updated: Instant,
+    pending: Vec<Infraction>,
+    history: VecDeque<Infraction>,
+    disconnected: bool,
+    banned: bool,
+}
+
+impl PeerReputation {
+    /// Reports an infraction.
+    /// It's applied to the score by the [`ReputationPolicy`] in [`NetworkRecv::Synchronise`].
+    pub fn report(&mut self, infraction: Infraction) {
+        self.pending.push(infraction);
+    }
+
+    /// Returns the current score. Higher scores are worse.
+    #[inline]
+    pub fn score(&self) -> f32 {
+        self.score
+    }
+
+    /// Returns the most recent infractions that have been applied, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &Infraction> {
+        self.history.iter()
+    }
+
+    fn apply(&mut self, policy: &ReputationPolicy, now: Instant) {
+        // Decay the score for the time since it was last applied
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f32();
+        self.score = (self.score - elapsed * policy.decay).max(0.0);
+        self.updated = now;
+
+        for infraction in self.pending.drain(..) {
+            self.score += policy.penalty(&infraction);
+            if self.history.len() == HISTORY_LEN { self.history.pop_front(); }
+            self.history.push_back(infraction);
+        }
+    }
+}
+
+impl Default for PeerReputation {
+    fn default() -> Self {
+        Self {
+            score: 0.0,
+            updated: Instant::now(),
+            pending: Vec::new(),
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            disconnected: false,
+            banned: false,
+        }
+    }
+}
+
+/// Thresholds and penalties for [`PeerReputation`] scores.
+///
+/// Peers whose score reaches `disconnect_at` are disconnected, with a reason based on their most
+/// recent infraction. If `ban_at` is set, and the score reaches it, the peer's [`PeerUid`] and
+/// [`PeerAddress`] are also added to the [`BanList`] resource, if it exists.
+#[derive(Debug, Clone, Resource)]
+pub struct ReputationPolicy {
+    /// The score at which peers are disconnected.
+    pub disconnect_at: f32,
+
+    /// The score at which peers are banned, if any.
+    pub ban_at: Option<f32>,
+
+    /// How long bans last, or `None` for permanent bans.
+    pub ban_duration: Option<Duration>,
+
+    /// How much the score decreases every second.
+    pub decay: f32,
+
+    /// The penalty for [`Infraction::MalformedMessage`].
+    pub malformed_penalty: f32,
+
+    /// The penalty for [`Infraction::Spam`].
+    pub spam_penalty: f32,
+
+    /// The penalty for [`Infraction::ProtocolViolation`], [`Infraction::OversizedMessage`],
+    /// and [`Infraction::ForbiddenChannel`].
+    pub protocol_penalty: f32,
+
+    /// The penalty for [`Infraction::Misbehaving`].
+    pub misbehaving_penalty: f32,
+}
+
+impl ReputationPolicy {
+    /// Returns the penalty for `infraction`.
+    pub fn penalty(&self, infraction: &Infraction) -> f32 {
+        match infraction {
+            Infraction::MalformedMessage { .. } => self.malformed_penalty,
+            Infraction::Spam { .. } => self.spam_penalty,
+            Infraction::OversizedMessage { .. } => self.protocol_penalty,
+            Infraction::ForbiddenChannel { .. } => self.protocol_penalty,
+            Infraction::ProtocolViolation => self.protocol_penalty,
+            Infraction::Misbehaving { .. } => self.misbehaving_penalty,
+        }
+    }
+}
+
+impl Default for ReputationPolicy {
+    fn default() -> Self {
+        Self {
+            disconnect_at: 100.0,
+            ban_at: None,
+            ban_duration: Some(Duration::from_secs(60 * 60)),
+            decay: 1.0,
+            malformed_penalty: 25.0,
+            spam_penalty: 5.0,
+            protocol_penalty: 50.0,
+            misbehaving_penalty: 25.0,
+        }
+    }
+}
+
+pub(crate) fn add_reputation_system(
+    mut commands: Commands,
+    query: Query<Entity, (Added<Peer>, Without<PeerReputation>)>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).insert(PeerReputation::default());
+    }
+}
+
+pub(crate) fn apply_reputation_system(
+    policy: Res<ReputationPolicy>,
+    mut bans: Option<ResMut<BanList>>,
+    mut query: Query<(Entity, &mut PeerReputation, Option<&PeerUid>, Option<&PeerAddress>)>,
+    mut disconnects: EventWriter<DisconnectPeerEvent>,
+) {
+    let now = Instant::now();
+
+    for (entity, mut reputation, uid, address) in query.iter_mut() {
+        if reputation.pending.is_empty() { continue }
+        reputation.apply(&policy, now);
+
+        // Each threshold is checked separately, since the score can keep
+        // growing after the peer is disconnected, while it's still closing
+        let disconnect = !reputation.disconnected && reputation.score >= policy.disconnect_at;
+        let ban = !reputation.banned && policy.ban_at.is_some_and(|ban_at| reputation.score >= ban_at);
+        if !disconnect && !ban { continue }
+
+        let last = reputation.history.back().unwrap();
+        let reason = last.disconnect_reason();
+        let comment = format!("reputation too low, last infraction: {last}");
+
+        if ban {
+            reputation.banned = true;
+            if let Some(bans) = bans.as_mut() {
+                let ban = Ban::new(policy.ban_duration, comment.as_str());
+                if let Some(uid) = uid { bans.ban_uid(*uid, ban.clone()) }
+                if let Some(address) = address { bans.ban_address(address.0, ban) }
+            }
+        }
+
+        if disconnect {
+            reputation.disconnected = true;
+            disconnects.send(DisconnectPeerEvent {
+                peer: entity,
+                reason,
+                comment: Some(comment.into()),
+                force: false,
+            });
+        }
+    }
+}
+
+#[test]
+fn reputation_policy_test() {
+    use std::net::IpAddr;
+    use bevy_app::prelude::*;
+
+    let mut app = App::new();
+    app.add_plugins(StardustPlugin);
+    app.insert_resource(BanList::default());
+    app.insert_resource(ReputationPolicy {
+        ban_at: Some(120.0),
+        ..Default::default()
+    });
+
+    let address = IpAddr::from([192, 0, 2, 1]);
+    let a = app.world_mut().spawn((Peer::new(), PeerUid(1))).id();
+    let b = app.world_mut().spawn((Peer::new(), PeerUid(2), PeerAddress(address))).id();
+    app.update();
+
+    // Disconnected, but not banned
+    let mut reputation = app.world_mut().get_mut::<PeerReputation>(a).unwrap();
+    for _ in 0..4 { reputation.report(Infraction::Misbehaving { reason: "cheating" }) }
+
+    // Banned by uid and address
+    let mut reputation = app.world_mut().get_mut::<PeerReputation>(b).unwrap();
+    reputation.report(Infraction::ProtocolViolation);
+    reputation.report(Infraction::ProtocolViolation);
+    reputation.report(Infraction::MalformedMessage { channel: ChannelId::from(0) });
+    app.update();
+
+    let disconnects = |app: &App| {
+        let events = app.world().resource::<Events<DisconnectPeerEvent>>();
+        events.iter_current_update_events().map(|e| (e.peer, e.reason.clone())).collect::<Vec<_>>()
+    };
+
+    let events = disconnects(&app);
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().any(|(peer, reason)| *peer == a && matches!(reason, DisconnectReason::Misbehaving)));
+    assert!(events.iter().any(|(peer, reason)| *peer == b && matches!(reason, DisconnectReason::ProtocolViolation)));
+
+    let bans = app.world().resource::<BanList>();
+    let now = std::time::SystemTime::now();
+    assert!(bans.is_uid_banned(PeerUid(1), now).is_none());
+    assert!(bans.is_uid_banned(PeerUid(2), now).is_some());
+    assert!(bans.is_address_banned(address, now).is_some());
+
+    // Banned later, while already disconnecting, without being disconnected again
+    let mut reputation = app.world_mut().get_mut::<PeerReputation>(a).unwrap();
+    reputation.report(Infraction::Misbehaving { reason: "still cheating" });
+    app.update();
+
+    assert!(disconnects(&app).is_empty());
+    let bans = app.world().resource::<BanList>();
+    assert!(bans.is_uid_banned(PeerUid(1), std::time::SystemTime::now()).is_some());
+}
@@ -0,0 +1,29 @@
+use bevy_ecs::prelude::*;
+
+#[cfg(feature="reflect")]
+use bevy_reflect::Reflect;
+
+/// Which side of its connections the app is on.
+///
+/// This is used to enforce each channel's [`ChannelDirection`](crate::channels::ChannelDirection).
+/// Apps that are both a server and a client at once, such as peer-to-peer games or a listen server
+/// connected to itself, shouldn't insert this resource, and can only use bidirectional channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Resource)]
+#[cfg_attr(feature="reflect", derive(Reflect), reflect(Debug, Resource, PartialEq, Hash))]
+pub enum NetworkRole {
+    /// The app accepts connections from clients.
+    Server,
+
+    /// The app connects to a server.
+    Client,
+}
+
+impl NetworkRole {
+    /// Returns the role of the other side of a connection.
+    pub fn remote(&self) -> NetworkRole {
+        match self {
+            NetworkRole::Server => NetworkRole::Client,
+            NetworkRole::Client => NetworkRole::Server,
+        }
+    }
+}
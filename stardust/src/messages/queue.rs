@@ -131,6 +131,44 @@ impl MessageQueue {
         }
     }
 
+    /// Removes all messages for which `f` returns `false`, preserving the order of the rest.
+    /// Returns the number of messages that were removed.
+    pub fn retain<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(ChannelId, &Message) -> bool,
+    {
+        // Decide which messages to keep, channel by channel
+        let mut keep = vec![false; self.messages.len()];
+        for (channel, indexes) in self.indexes.iter() {
+            for idx in indexes.iter() {
+                keep[*idx] = f(*channel, &self.messages[*idx]);
+            }
+        }
+
+        let removed = keep.iter().filter(|v| !**v).count();
+        if removed == 0 { return 0 }
+
+        // Work out where each kept message will end up
+        let mut moved = Vec::with_capacity(keep.len());
+        let mut next = 0;
+        for kept in keep.iter() {
+            moved.push(next);
+            if *kept { next += 1 }
+        }
+
+        // Update the indexes to point to the new positions
+        for indexes in self.indexes.values_mut() {
+            indexes.retain(|idx| keep[*idx]);
+            indexes.iter_mut().for_each(|idx| *idx = moved[*idx]);
+        }
+
+        // Remove the messages themselves
+        let mut iter = keep.iter();
+        self.messages.retain(|_| *iter.next().unwrap());
+
+        return removed;
+    }
+
     /// Returns an iterator over channels, and their associated queues.
     pub fn iter(&self) -> ChannelIter {
         ChannelIter {
@@ -292,4 +330,22 @@ fn message_queue_ordering_test() {
     queue.iter_channel(ChannelId::from(1))
     .zip(MESSAGE_SET_C)
     .for_each(|(a, b)| assert_eq!(a.as_slice(), *b));
-}
\ No newline at end of file
+}
+
+#[test]
+fn message_queue_retain_test() {
+    let mut queue = MessageQueue::new();
+    queue.push_one(ChannelMessage { channel: ChannelId::from(0), message: Message::from_static(b"a") });
+    queue.push_one(ChannelMessage { channel: ChannelId::from(1), message: Message::from_static(b"bb") });
+    queue.push_one(ChannelMessage { channel: ChannelId::from(0), message: Message::from_static(b"ccc") });
+    queue.push_one(ChannelMessage { channel: ChannelId::from(1), message: Message::from_static(b"dddd") });
+
+    // Remove the second message on each channel
+    let removed = queue.retain(|_, message| message.len() <= 2);
+    assert_eq!(removed, 2);
+    assert_eq!(queue.count(), 2);
+    assert_eq!(queue.bytes(), 3);
+
+    assert_eq!(queue.iter_channel(ChannelId::from(0)).map(|m| m.as_slice().to_vec()).collect::<Vec<_>>(), vec![b"a".to_vec()]);
+    assert_eq!(queue.iter_channel(ChannelId::from(1)).map(|m| m.as_slice().to_vec()).collect::<Vec<_>>(), vec![b"bb".to_vec()]);
+}
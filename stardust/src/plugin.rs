@@ -34,20 +34,25 @@ impl Plugin for StardustPlugin {
             app.register_type::<ChannelId>();
             app.register_type::<channels::ChannelConfiguration>();
             app.register_type::<channels::MessageConsistency>();
+            app.register_type::<channels::ChannelRateLimit>();
+            app.register_type::<channels::ChannelDirection>();
+            app.register_type::<channels::ChannelCompression>();
+            app.register_type::<channels::CompressionAlgorithm>();
+            app.register_type::<NetworkRole>();
 
             // Register messaging types
             app.register_type::<NetDirection>();
             app.register_type::<Incoming>();
             app.register_type::<Outgoing>();
-
-            // Register events
-            app.add_event::<DisconnectPeerEvent>();
-            app.add_event::<PeerConnectingEvent>();
-            app.add_event::<PeerConnectedEvent>();
-            app.add_event::<PeerDisconnectingEvent>();
-            app.add_event::<PeerDisconnectedEvent>();
         }
 
+        // Register events
+        app.add_event::<DisconnectPeerEvent>();
+        app.add_event::<PeerConnectingEvent>();
+        app.add_event::<PeerConnectedEvent>();
+        app.add_event::<PeerDisconnectingEvent>();
+        app.add_event::<PeerDisconnectedEvent>();
+
         // Setup orderings
         crate::scheduling::configure_scheduling(app);
 
@@ -55,6 +60,18 @@ impl Plugin for StardustPlugin {
         channels::plugin_build(app);
 
         // Add systems
+        app.add_systems(PreUpdate, crate::connections::enforce_limits_system
+            .in_set(NetworkRecv::Synchronise));
+
+        app.add_systems(PreUpdate, crate::connections::enforce_bans_system
+            .run_if(resource_exists::<BanList>)
+            .in_set(NetworkRecv::Synchronise));
+
+        app.add_systems(PreUpdate, (
+            crate::connections::add_reputation_system,
+            crate::connections::apply_reputation_system,
+        ).chain().run_if(resource_exists::<ReputationPolicy>).in_set(NetworkRecv::Synchronise));
+
         app.add_systems(PostUpdate, (
             crate::connections::clear_message_queues_system::<Outgoing>,
             crate::connections::clear_message_queues_system::<Incoming>,
@@ -4,7 +4,7 @@ pub use bytes::Bytes;
 
 pub use crate::plugin::StardustPlugin;
 pub use crate::scheduling::{NetworkRecv, NetworkSend};
-pub use crate::connections::{Peer, PeerMessages, PeerUid, PeerLifestage, Established};
+pub use crate::connections::{Peer, PeerMessages, PeerUid, PeerLifestage, Established, NetBroadcast};
 pub use crate::connections::events::{PeerConnectingEvent, PeerConnectedEvent, DisconnectPeerEvent, PeerDisconnectingEvent, PeerDisconnectedEvent, DisconnectReason};
-pub use crate::channels::{Channel, Channels, ChannelConfiguration, MessageConsistency, ChannelData, ChannelId, ChannelSetupAppExt};
+pub use crate::channels::{Channel, Channels, ChannelConfiguration, ChannelDirection, MessageConsistency, ChannelData, ChannelId, ChannelSetupAppExt};
 pub use crate::messages::{NetDirection, MessageDirection, Incoming, Outgoing, Message, ChannelMessage};
\ No newline at end of file
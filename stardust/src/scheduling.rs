@@ -9,6 +9,11 @@ pub enum NetworkRecv {
     /// Transport layers insert received messages into [`PeerMessages<Incoming>`](crate::connections::PeerMessages) components.
     Receive,
 
+    /// Received messages are checked against the limits set in each channel's
+    /// [`ChannelConfiguration`](crate::channels::ChannelConfiguration), and messages that
+    /// break them are removed before anything else reads them.
+    Validate,
+
     /// Systems update game state and deal with after-effects of received messages,
     /// before the main game systems in [`Update`] are run.
     /// 
@@ -19,6 +24,11 @@ pub enum NetworkRecv {
 /// Systems dealing with sending messages. Run in the [`PostUpdate`] schedule.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, SystemSet)]
 pub enum NetworkSend {
+    /// Queued messages are checked against the limits set in each channel's
+    /// [`ChannelConfiguration`](crate::channels::ChannelConfiguration), and compressed.
+    /// Systems that queue messages in [`PostUpdate`] should run before this set.
+    Prepare,
+
     /// Transport layers send messages queued in [`PeerMessages<Outgoing>`](crate::connections::PeerMessages) components.
     Transmit,
 
@@ -31,10 +41,12 @@ pub enum NetworkSend {
 
 pub(super) fn configure_scheduling(app: &mut App) {
     app.configure_sets(PreUpdate, (
-        NetworkRecv::Synchronise.after(NetworkRecv::Receive),
+        NetworkRecv::Validate.after(NetworkRecv::Receive),
+        NetworkRecv::Synchronise.after(NetworkRecv::Validate),
     ));
 
     app.configure_sets(PostUpdate, (
+        NetworkSend::Transmit.after(NetworkSend::Prepare),
         NetworkSend::Diagnostics.after(NetworkSend::Transmit),
         NetworkSend::Clear.after(NetworkSend::Diagnostics),
     ));